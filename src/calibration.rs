@@ -0,0 +1,68 @@
+//! # Measurement Calibration
+//!
+//! Per-quantity linear correction (`corrected = gain × raw + offset`) for
+//! supplies whose readback is slightly off, plus a two-point helper that
+//! derives gain/offset from two reference-meter readings.
+
+use serde::{Deserialize, Serialize};
+
+/// 一個量 (電壓或電流) 的線性校正
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Correction {
+    pub gain: f64,
+    pub offset: f64,
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self { gain: 1.0, offset: 0.0 }
+    }
+}
+
+impl Correction {
+    pub fn apply(&self, raw: f32) -> f32 {
+        (self.gain * raw as f64 + self.offset) as f32
+    }
+
+    /// 反推原始讀值 (`apply` 的反函式)
+    pub fn raw(&self, corrected: f32) -> f32 {
+        ((corrected as f64 - self.offset) / self.gain) as f32
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 兩點校正：儀器讀到 raw1 時標準表是 ref1、raw2 時是 ref2
+    pub fn two_point(raw1: f64, ref1: f64, raw2: f64, ref2: f64) -> Option<Self> {
+        // 兩個點太接近時斜率沒意義
+        if (raw2 - raw1).abs() < f64::EPSILON { return None; }
+        let gain = (ref2 - ref1) / (raw2 - raw1);
+        (gain.is_finite() && gain != 0.0).then_some(Self { gain, offset: ref1 - gain * raw1 })
+    }
+}
+
+/// 電壓 / 電流的校正 (存在設定檔裡)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Calibration {
+    pub voltage: Correction,
+    pub current: Correction,
+    /// 擷取的 CSV 在註記欄位另外記下原始讀值
+    pub log_raw: bool,
+}
+
+impl Calibration {
+    pub fn is_identity(&self) -> bool {
+        self.voltage.is_identity() && self.current.is_identity()
+    }
+
+    /// 擷取檔開頭的說明 (之後看檔案才知道數值有被修正過)
+    pub fn describe(&self) -> String {
+        format!(
+            "Calibration: V = {} x raw {:+} V, I = {} x raw {:+} A",
+            self.voltage.gain, self.voltage.offset, self.current.gain, self.current.offset
+        )
+    }
+}
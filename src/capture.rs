@@ -0,0 +1,134 @@
+//! # Trigger-Based Capture
+//!
+//! Oscilloscope-style trigger: once armed, watches each new sample for a
+//! level crossing with the chosen edge, then starts a CSV capture that
+//! includes the pre-trigger history.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::calibration::Calibration;
+use crate::logger::{self, CsvLogger};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Source { Voltage, Current }
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edge { Rising, Falling }
+
+#[derive(Clone, Copy, Debug)]
+pub struct TriggerConfig {
+    pub source: Source,
+    pub edge: Edge,
+    pub level: f32,
+    /// 觸發前要一併存下的取樣點數
+    pub pre_samples: usize,
+    /// 長時間錄製的換檔設定
+    pub rotation: logger::Rotation,
+}
+
+/// 前一筆到這一筆是否以指定邊緣穿越 level
+pub fn crossed(prev: f32, curr: f32, level: f32, edge: Edge) -> bool {
+    match edge {
+        Edge::Rising => prev < level && curr >= level,
+        Edge::Falling => prev > level && curr <= level,
+    }
+}
+
+pub enum Capture {
+    Idle,
+    Armed(TriggerConfig),
+    Recording(CsvLogger),
+}
+
+/// 一次 poll 的取樣 (含歷史 Buffer，用來寫 pre-trigger 資料)
+pub struct Sample<'a> {
+    pub history_v: &'a VecDeque<f32>,
+    pub history_i: &'a VecDeque<f32>,
+    pub history_t: &'a VecDeque<Instant>,
+    pub mode: &'a str,
+    /// 儀器時鐘 - PC 時鐘 (秒)；儀器不支援或沒讀到時為 None
+    pub clock_offset: Option<f64>,
+    /// 讀值有套用校正時才有值 (寫進檔案說明，可選擇另外記原始讀值)
+    pub calibration: Option<Calibration>,
+}
+
+impl Sample<'_> {
+    /// 註記欄位的原始讀值 (有開「記錄原始值」時)
+    fn raw_note(&self, v: f32, i: f32) -> String {
+        match self.calibration {
+            Some(c) if c.log_raw => format!("raw {:.4} V, {:.4} A", c.voltage.raw(v), c.current.raw(i)),
+            _ => String::new(),
+        }
+    }
+}
+
+impl Capture {
+    /// 處理最新一筆取樣；剛觸發時回傳 `Some(描述)`
+    pub fn on_sample(&mut self, s: &Sample) -> std::io::Result<Option<String>> {
+        match self {
+            Capture::Idle => Ok(None),
+            Capture::Armed(cfg) => {
+                let cfg = *cfg;
+                let hist = match cfg.source { Source::Voltage => s.history_v, Source::Current => s.history_i };
+                let n = hist.len();
+                let len_t = s.history_t.len();
+                // 前一筆也要是真的取樣 (Buffer 開頭是補 0 的)，否則一開始就會誤觸發
+                if len_t < 2 || !crossed(hist[n - 2], hist[n - 1], cfg.level, cfg.edge) {
+                    return Ok(None);
+                }
+
+                let mut log = CsvLogger::create_rotating("capture", cfg.rotation)?;
+
+                // 先寫觸發前的歷史 (只取有真實時間戳的點)
+                let pre = cfg.pre_samples.min(len_t.saturating_sub(1));
+                let start_t = len_t - 1 - pre;
+                let offset = n - len_t;
+                if let Some(c) = s.calibration {
+                    log.note(logger::wall_clock(s.history_t[start_t]), &c.describe())?;
+                }
+                for k in start_t..len_t - 1 {
+                    let (v, i) = (s.history_v[offset + k], s.history_i[offset + k]);
+                    log.record_with_note(logger::wall_clock(s.history_t[k]), v, i, "", &s.raw_note(v, i))?;
+                }
+
+                let now = logger::wall_clock(s.history_t[len_t - 1]);
+                let desc = format!("TRIGGER {:?} {:?} @ {}", cfg.source, cfg.edge, cfg.level);
+                log.note(now, &desc)?;
+                // 記下儀器端的時間，之後才能跟儀器內部的紀錄對起來 (儀器時間 = timestamp + offset)
+                if let Some(off) = s.clock_offset {
+                    let inst = now + chrono::Duration::milliseconds((off * 1000.0).round() as i64);
+                    log.note(now, &format!("Instrument time {} (clock offset {:+.3} s)", inst.format(logger::TS_FORMAT), off))?;
+                }
+                let (v, i) = (s.history_v[n - 1], s.history_i[n - 1]);
+                log.record_with_note(now, v, i, s.mode, &s.raw_note(v, i))?;
+                let msg = format!("Triggered → {}", log.path().display());
+                *self = Capture::Recording(log);
+                Ok(Some(msg))
+            }
+            Capture::Recording(log) => {
+                let (Some(&v), Some(&i), Some(&t)) = (s.history_v.back(), s.history_i.back(), s.history_t.back()) else {
+                    return Ok(None);
+                };
+                log.record_with_note(logger::wall_clock(t), v, i, s.mode, &s.raw_note(v, i))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// 錄製中時寫一筆註記列 (例如使用者的標記)；沒在錄製時回傳 false
+    pub fn note(&mut self, ts: chrono::DateTime<chrono::Local>, text: &str) -> std::io::Result<bool> {
+        match self {
+            Capture::Recording(log) => log.note(ts, text).map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    /// 停止錄製並關檔
+    pub fn stop(&mut self) {
+        if let Capture::Recording(log) = self {
+            let _ = log.flush();
+        }
+        *self = Capture::Idle;
+    }
+}
@@ -0,0 +1,302 @@
+//! # Trend Chart Geometry
+//!
+//! Converts sample buffers into SVG path commands for the trend chart,
+//! plus the horizontal gridlines, zero baseline and value labels drawn behind them.
+//! Several traces (e.g. two channels) can share one chart with a common or per-trace scale.
+//! Long buffers are downsampled and redraws are throttled so a big history
+//! doesn't turn every poll into a huge string rebuild.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::scpi;
+
+/// 縱軸刻度範圍
+#[derive(Debug, Clone, Copy)]
+pub struct Scale {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Scale {
+    /// 依資料自動決定 (0 ~ 最大值 × 1.1，最大值至少 1.0，避免 0V 時線條亂飛)
+    ///
+    /// 有負值 (相對模式的 Δ、雙象限電源的吸收電流) 時下限一樣往下留 10% 空間，零線會落在圖中間。
+    pub fn auto(buffer: &VecDeque<f32>) -> Self {
+        let max = buffer.iter().fold(0.0f32, |a, &b| a.max(b)).max(1.0) * 1.1;
+        let min = buffer.iter().fold(0.0f32, |a, &b| a.min(b)) * 1.1;
+        Self { min, max }
+    }
+
+    /// 使用者指定的固定範圍 (鎖住刻度方便目視比較)；範圍無效時回傳 None
+    pub fn fixed(min: f32, max: f32) -> Option<Self> {
+        (min.is_finite() && max.is_finite() && max > min).then_some(Self { min, max })
+    }
+
+    /// 同時涵蓋兩組刻度 (疊加參考曲線時，兩條線用同一個刻度才能比較)
+    pub fn union(self, other: Scale) -> Self {
+        Self { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    /// 以 0 為中心、上下對稱的刻度 (給 Δ 這種很小又有正負的數值)
+    pub fn symmetric(buffer: &VecDeque<f32>) -> Self {
+        let peak = buffer.iter().fold(0.0f32, |a, &b| a.max(b.abs())).max(1e-3) * 1.1;
+        Self { min: -peak, max: peak }
+    }
+
+    /// 數值 → 畫面 Y 座標 (Y 軸反轉，Slint 0 在上面)
+    pub fn y(&self, val: f32, height: f32) -> f32 {
+        let span = (self.max - self.min).max(f32::EPSILON);
+        height - ((val - self.min) / span * height)
+    }
+
+    /// 畫面 Y 座標 → 數值 (`y` 的反函式)
+    pub fn value_at(&self, y: f32, height: f32) -> f32 {
+        self.min + (height - y) / height.max(f32::EPSILON) * (self.max - self.min)
+    }
+}
+
+/// 一條曲線最多畫幾個點 (超過就 min/max 抽點；畫面寬度也就一兩千像素)
+pub const MAX_PATH_POINTS: usize = 2000;
+
+/// 重畫的最短間隔 (不論輪詢多快)
+pub const MIN_RENDER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 限制趨勢圖重畫頻率；上一次產生 SVG 花的時間越久，間隔就自動拉越長
+#[derive(Debug, Clone, Copy)]
+pub struct RenderThrottle {
+    min_interval: Duration,
+    last: Option<Instant>,
+    /// 上一次重畫花的時間
+    pub cost: Duration,
+}
+
+impl RenderThrottle {
+    pub fn new(min_interval: Duration) -> Self {
+        Self { min_interval, last: None, cost: Duration::ZERO }
+    }
+
+    /// 最多把 1/4 的時間花在重畫上
+    fn interval(&self) -> Duration { self.min_interval.max(self.cost * 4) }
+
+    /// 距離上次重畫夠久了
+    pub fn due(&self, now: Instant) -> bool {
+        self.last.is_none_or(|t| now.saturating_duration_since(t) >= self.interval())
+    }
+
+    /// 重畫完呼叫，`started` = 開始重畫的時間
+    pub fn record(&mut self, started: Instant) {
+        self.last = Some(started);
+        self.cost = started.elapsed();
+    }
+}
+
+/// 容許範圍 `[lo, hi]` 的色帶 (橫跨整張圖的矩形)，超出刻度的部分貼齊上下緣
+pub fn band_path(scale: Scale, lo: f32, hi: f32, width: f32, height: f32) -> String {
+    let top = scale.y(hi, height).clamp(0.0, height);
+    let bottom = scale.y(lo, height).clamp(0.0, height);
+    if bottom - top < 0.5 { return String::new(); }
+    format!("M 0 {:.1} L {:.1} {:.1} L {:.1} {:.1} L 0 {:.1} Z", top, width, top, width, bottom, bottom)
+}
+
+/// 標記時間落在 `times` 第幾筆 (第一筆在它之後的那一筆)；比最舊一筆還舊就回傳 None
+pub fn marker_index(times: &VecDeque<Instant>, t: Instant) -> Option<usize> {
+    if times.front().is_none_or(|&first| t < first) { return None; }
+    Some(times.partition_point(|&x| x < t).min(times.len() - 1))
+}
+
+/// 在各標記的 X 座標畫一條貫穿整張圖的垂直線
+pub fn marker_path(xs: &[f32], height: f32) -> String {
+    let mut path_cmd = String::with_capacity(xs.len() * 24);
+    for x in xs {
+        let _ = write!(path_cmd, "M {:.1} 0 L {:.1} {:.1} ", x, x, height);
+    }
+    path_cmd
+}
+
+/// 一條格線的標籤
+#[derive(Debug, Clone)]
+pub struct GridLabel {
+    /// 畫面 Y 座標
+    pub y: f32,
+    pub text: String,
+}
+
+/// 格線、零線與標籤
+#[derive(Debug, Clone, Default)]
+pub struct Grid {
+    pub lines: String,
+    pub baseline: String,
+    pub labels: Vec<GridLabel>,
+}
+
+/// 把數值陣列轉成 SVG Path 字串 (自動刻度)
+/// 參數: buffer (數據), width (圖寬), height (圖高)
+pub fn generate_svg_path(buffer: &VecDeque<f32>, width: f32, height: f32) -> String {
+    svg_path_scaled(buffer, Scale::auto(buffer), width, height)
+}
+
+/// 用指定刻度把數值陣列轉成 SVG Path 字串
+pub fn svg_path_scaled(buffer: &VecDeque<f32>, scale: Scale, width: f32, height: f32) -> String {
+    if buffer.is_empty() { return String::new(); }
+    // 點數太多時先抽點，字串長度才有上限
+    let decimated;
+    let buffer = if buffer.len() > MAX_PATH_POINTS {
+        decimated = decimate(&buffer.iter().copied().collect::<Vec<f32>>(), MAX_PATH_POINTS);
+        &decimated
+    } else {
+        buffer
+    };
+
+    // 每個點大約 "L 123.4 56.7 " 14 個字
+    let mut path_cmd = String::with_capacity(buffer.len() * 14);
+    let last = (buffer.len() - 1).max(1) as f32;
+    // NaN = 斷線缺口：跳過，下一個點重新 M 起筆
+    let mut pen_down = false;
+
+    for (i, &val) in buffer.iter().enumerate() {
+        if val.is_nan() {
+            pen_down = false;
+            continue;
+        }
+        let x = (i as f32 / last) * width;
+        // 固定刻度時資料可能超出範圍，貼齊上下緣而不是畫到圖外
+        let y = scale.y(val, height).clamp(0.0, height);
+
+        if !pen_down {
+            let _ = write!(path_cmd, "M {:.1} {:.1} ", x, y);
+            pen_down = true;
+        } else {
+            let _ = write!(path_cmd, "L {:.1} {:.1} ", x, y);
+        }
+    }
+    path_cmd
+}
+
+/// 多通道疊圖的顏色 (0xRRGGBB)，依通道順序輪流使用
+pub const TRACE_COLORS: [u32; 6] = [0x55ff55, 0xffaa55, 0x55aaff, 0xff55ff, 0xffff55, 0xff5555];
+/// 淺色底用的版本 (亮黃、亮綠在白底上看不清楚)
+pub const TRACE_COLORS_LIGHT: [u32; 6] = [0x1b8f1b, 0xb35a00, 0x1f5fbf, 0xa020a0, 0x7a6a00, 0xc62828];
+
+/// 依配色挑疊圖顏色
+pub fn trace_colors(theme: &str) -> &'static [u32; 6] {
+    if theme == "light" { &TRACE_COLORS_LIGHT } else { &TRACE_COLORS }
+}
+
+/// 多條曲線畫在同一張圖
+///
+/// `shared` = 共用刻度 (可以直接比大小與先後，並回傳該刻度給格線用)；否則各自縮放，只比形狀。
+pub fn trace_paths(traces: &[VecDeque<f32>], shared: bool, width: f32, height: f32) -> (Vec<String>, Option<Scale>) {
+    let scales: Vec<Scale> = traces.iter().map(Scale::auto).collect();
+    let common = if shared { scales.iter().copied().reduce(Scale::union) } else { None };
+    let paths = traces.iter().zip(&scales)
+        .map(|(t, &s)| svg_path_scaled(t, common.unwrap_or(s), width, height))
+        .collect();
+    (paths, common)
+}
+
+/// X-Y 曲線 (例如 I-V 掃描)：X、Y 各自依資料範圍縮放，點依傳入順序連線
+pub fn xy_path(points: &[(f32, f32)], width: f32, height: f32) -> String {
+    if points.is_empty() { return String::new(); }
+    let to_px = xy_transform(points, width, height);
+
+    let mut path_cmd = String::with_capacity(points.len() * 16);
+    for (k, &p) in points.iter().enumerate() {
+        let (px, py) = to_px(p);
+        let _ = write!(path_cmd, "{} {:.1} {:.1} ", if k == 0 { "M" } else { "L" }, px, py);
+    }
+    path_cmd
+}
+
+/// 在 `xy_path` 的同一個座標系裡，於 `points[idx]` 畫一個十字標記
+pub fn xy_marker(points: &[(f32, f32)], idx: usize, width: f32, height: f32) -> String {
+    let Some(&p) = points.get(idx) else { return String::new(); };
+    let (px, py) = xy_transform(points, width, height)(p);
+    const R: f32 = 5.0;
+    format!("M {:.1} {:.1} L {:.1} {:.1} M {:.1} {:.1} L {:.1} {:.1}", px - R, py, px + R, py, px, py - R, px, py + R)
+}
+
+/// X 軸依資料範圍拉滿、Y 軸自動刻度，回傳 資料點 → 像素 的換算
+fn xy_transform(points: &[(f32, f32)], width: f32, height: f32) -> impl Fn((f32, f32)) -> (f32, f32) {
+    let (mut x_min, mut x_max) = (f32::INFINITY, f32::NEG_INFINITY);
+    for &(x, _) in points { x_min = x_min.min(x); x_max = x_max.max(x); }
+    let x_span = (x_max - x_min).max(f32::EPSILON);
+    let ys: VecDeque<f32> = points.iter().map(|&(_, y)| y).collect();
+    let scale_y = Scale::auto(&ys);
+    move |(x, y)| ((x - x_min) / x_span * width, scale_y.y(y, height))
+}
+
+/// 線性內插，把任意長度的資料重新取樣成 `n` 點 (對齊目前圖表寬度)
+pub fn resample(data: &[f32], n: usize) -> VecDeque<f32> {
+    match (data.len(), n) {
+        (0, _) | (_, 0) => VecDeque::new(),
+        (1, _) => std::iter::repeat_n(data[0], n).collect(),
+        (len, 1) => std::iter::once(data[len - 1]).collect(),
+        (len, _) => (0..n).map(|k| {
+            let pos = k as f32 * (len - 1) as f32 / (n - 1) as f32;
+            let lo = pos.floor() as usize;
+            let hi = (lo + 1).min(len - 1);
+            let frac = pos - lo as f32;
+            data[lo] + (data[hi] - data[lo]) * frac
+        }).collect(),
+    }
+}
+
+/// 點數太多時每一段只保留最小 / 最大值 (保留尖峰，不像抽樣會漏掉)
+pub fn decimate(data: &[f32], max_points: usize) -> VecDeque<f32> {
+    let buckets = max_points / 2;
+    if data.len() <= max_points || buckets == 0 { return data.iter().copied().collect(); }
+    let mut out = VecDeque::with_capacity(buckets * 2);
+    for k in 0..buckets {
+        let chunk = &data[k * data.len() / buckets..(k + 1) * data.len() / buckets];
+        // 斷線缺口 (NaN) 要留著，曲線才會在那裡斷開
+        if chunk.iter().any(|x| x.is_nan()) {
+            out.extend([f32::NAN, f32::NAN]);
+            continue;
+        }
+        let lo = chunk.iter().copied().fold(f32::INFINITY, f32::min);
+        let hi = chunk.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        // 依出現順序放，曲線走向才正確
+        let lo_first = chunk.iter().position(|&x| x == lo) <= chunk.iter().position(|&x| x == hi);
+        if lo_first { out.extend([lo, hi]); } else { out.extend([hi, lo]); }
+    }
+    out
+}
+
+/// 挑一個好讀的格線間距 (1 / 2 / 5 × 10^n)，大約切成 `divisions` 格
+pub fn nice_step(span: f32, divisions: u32) -> f32 {
+    if span <= 0.0 || divisions == 0 { return 1.0; }
+    let raw = span / divisions as f32;
+    let magnitude = 10f32.powf(raw.log10().floor());
+    let norm = raw / magnitude;
+    let nice = if norm <= 1.0 { 1.0 } else if norm <= 2.0 { 2.0 } else if norm <= 5.0 { 5.0 } else { 10.0 };
+    nice * magnitude
+}
+
+/// 依刻度產生水平格線 + 零線 + 標籤
+///
+/// 格線間距依 `scale` 計算；`unit` 會接在標籤數值後面 (例如 "V")。
+pub fn grid(scale: Scale, unit: &str, width: f32, height: f32) -> Grid {
+    let mut out = Grid::default();
+    let step = nice_step(scale.max - scale.min, 4);
+    // 小數位數跟著間距走 (0.05 → 兩位、0.5 → 一位)，再加上整數位數換算成有效位數
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let top = scale.max.abs().max(scale.min.abs());
+    let int_digits = if top < 1.0 { 1 } else { top.log10().floor() as usize + 1 };
+    let digits = (int_digits + decimals).max(3);
+
+    let mut val = (scale.min / step).ceil() * step;
+    while val <= scale.max + step * 1e-3 {
+        let y = scale.y(val, height);
+        let is_zero = val.abs() < step * 1e-3;
+        if is_zero {
+            let _ = write!(out.baseline, "M 0 {:.1} L {:.1} {:.1} ", y, width, y);
+        } else {
+            let _ = write!(out.lines, "M 0 {:.1} L {:.1} {:.1} ", y, width, y);
+        }
+        out.labels.push(GridLabel { y, text: scpi::format_with_unit_digits(if is_zero { 0.0 } else { val } as f64, unit, digits) });
+        val += step;
+    }
+    out
+}
@@ -0,0 +1,33 @@
+//! # Instrument Clock
+//!
+//! Reads and sets the instrument's real-time clock (`SYST:DATE` / `SYST:TIME`)
+//! and measures its offset from the PC clock, so PC-side CSV logs can be
+//! lined up with the instrument's internal logs.
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, Timelike};
+
+use crate::scpi;
+
+/// 解析 `SYST:DATE?` ("+2024,+5,+3") 與 `SYST:TIME?` ("+12,+30,+45.000")
+pub fn parse(date: &str, time: &str) -> Option<NaiveDateTime> {
+    let fields = |s: &str| -> Option<Vec<f64>> {
+        s.trim().split(',').map(|f| scpi::parse_number(f.trim().trim_start_matches('+'))).collect()
+    };
+    let (d, t) = (fields(date)?, fields(time)?);
+    let (&[y, mo, day], &[h, mi, sec]) = (d.as_slice(), t.as_slice()) else { return None; };
+    let ms = (sec.fract() * 1000.0).round() as u32;
+    NaiveDate::from_ymd_opt(y as i32, mo as u32, day as u32)?.and_hms_milli_opt(h as u32, mi as u32, sec as u32, ms.min(999))
+}
+
+/// 儀器時間 - PC 時間 (秒)；正值 = 儀器比較快
+pub fn offset_seconds(instrument: NaiveDateTime, pc: DateTime<Local>) -> f64 {
+    (instrument - pc.naive_local()).num_milliseconds() as f64 / 1000.0
+}
+
+/// 把儀器時鐘設成 `now` 的兩道指令
+pub fn set_commands(date_cmd: &str, time_cmd: &str, now: DateTime<Local>) -> [String; 2] {
+    [
+        format!("{} {},{},{}", date_cmd, now.year(), now.month(), now.day()),
+        format!("{} {},{},{}", time_cmd, now.hour(), now.minute(), now.second()),
+    ]
+}
@@ -0,0 +1,245 @@
+//! # Persistent Configuration
+//!
+//! Stores user preferences (setpoint presets, selected profile, etc.) as
+//! JSON in the platform's per-user config directory.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::hash::BuildHasher;
+use std::path::PathBuf;
+
+use crate::calibration::Calibration;
+use crate::scpi;
+
+/// 一組常用的電壓/電流設定
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub voltage: f64,
+    pub current: f64,
+}
+
+impl Preset {
+    pub fn new(name: &str, voltage: f64, current: f64) -> Self {
+        Self { name: name.to_string(), voltage, current }
+    }
+}
+
+/// 最近連過的儀器 (以 *IDN? 回應當 key)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecentDevice {
+    pub idn: String,
+    pub port: String,
+    pub profile: String,
+}
+
+impl RecentDevice {
+    /// 按鈕上顯示的文字，例如 "E36313A @ COM3"
+    pub fn label(&self) -> String {
+        let id = scpi::Identity::parse(&self.idn);
+        let model = if id.model.is_empty() { id.manufacturer } else { id.model };
+        format!("{} @ {}", model, self.port)
+    }
+}
+
+/// 最多記住幾台
+pub const MAX_RECENT: usize = 5;
+
+/// 主視窗位置與大小 (實體像素)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// 所有需要存檔的設定 (缺少的欄位用預設值補上，舊設定檔也能讀)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AppConfig {
+    pub presets: Vec<Preset>,
+    /// 效率量測：哪個通道接 DUT 輸入、哪個接輸出
+    pub efficiency_enabled: bool,
+    pub efficiency_in_ch: i32,
+    pub efficiency_out_ch: i32,
+    /// 多通道疊圖：通道清單、"Voltage" / "Current"、是否共用刻度
+    pub overlay_enabled: bool,
+    pub overlay_channels: String,
+    pub overlay_quantity: String,
+    pub overlay_shared_scale: bool,
+    /// 上次選用的 Device Profile 名稱
+    pub profile: String,
+    /// 通道上電順序，格式 "CH:延遲ms, ..."，例如 "1:0, 2:100"
+    pub power_sequence: String,
+    /// 啟動時自動連線到上次的 Port (Port 不在就略過)
+    pub auto_connect: bool,
+    /// 長時間操作 (上電順序、I-V 掃描) 完成時讓儀器嗶一聲
+    pub completion_beep: bool,
+    /// 改完電壓 / 電流設定後多久 (ms) 才相信量測值；0 = 不等
+    pub settle_ms: u64,
+    /// 等儀器回應的超時 (ms)；回應慢的儀器 (電子負載) 可以調長
+    pub read_timeout_ms: u64,
+    /// 輪詢讀取超時 / 收到半行時重送幾次
+    pub poll_retries: u32,
+    /// 連線中斷後自動重連幾次；0 = 不重連
+    pub reconnect_attempts: u32,
+    /// 配色："dark" / "light" / "high-contrast"
+    pub theme: String,
+    /// 上次成功連線的 Port 名稱與鮑率
+    pub last_port: String,
+    pub baud_rate: u32,
+    /// 資料格式，例如 "8N1" / "7E1" (見 `scpi::Framing`)
+    pub framing: String,
+    /// 唯讀 HTTP/JSON 讀值 API
+    pub http_enabled: bool,
+    pub http_port: u16,
+    /// 上次關閉時的視窗位置與大小；None = 用預設大小
+    pub window: Option<WindowGeometry>,
+    /// 最近連過的儀器 (最新的在前面)
+    pub recent: Vec<RecentDevice>,
+    /// Kiosk 鎖定：重開程式也維持鎖定，解鎖要輸入 PIN
+    pub ui_locked: bool,
+    /// 加鹽的 PIN 雜湊 (`salt$sha256`，見 `hash_pin`)；空字串 = 還沒設定 PIN
+    pub lock_pin_hash: String,
+    /// 舊版設定檔的明文 PIN：讀進來就換成 `lock_pin_hash`，不再寫回檔案
+    #[serde(skip_serializing)]
+    pub lock_pin: String,
+    /// 軟體過功率保護 (W)：量到的功率超過就關輸出；0 = 不限制
+    pub max_power_w: f64,
+    /// 讀值的線性校正 (用標準電表量出來的誤差)
+    pub calibration: Calibration,
+}
+
+impl AppConfig {
+    /// 記下剛連上的儀器；同一台 (IDN 相同) 只留最新的 Port / Profile
+    pub fn remember_device(&mut self, dev: RecentDevice) {
+        self.recent.retain(|d| d.idn != dev.idn);
+        self.recent.insert(0, dev);
+        self.recent.truncate(MAX_RECENT);
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            presets: vec![
+                Preset::new("3.3V", 3.3, 1.0),
+                Preset::new("5V", 5.0, 1.0),
+                Preset::new("12V", 12.0, 1.0),
+            ],
+            efficiency_enabled: false,
+            efficiency_in_ch: 1,
+            efficiency_out_ch: 2,
+            overlay_enabled: false,
+            overlay_channels: "1, 2".to_string(),
+            overlay_quantity: "Voltage".to_string(),
+            overlay_shared_scale: true,
+            profile: "Generic SCPI".to_string(),
+            power_sequence: "1:0, 2:100".to_string(),
+            auto_connect: false,
+            completion_beep: false,
+            settle_ms: 0,
+            read_timeout_ms: 500,
+            poll_retries: 2,
+            reconnect_attempts: 5,
+            theme: "dark".to_string(),
+            last_port: String::new(),
+            baud_rate: 9600,
+            framing: "8N1".to_string(),
+            http_enabled: false,
+            http_port: 8080,
+            window: None,
+            recent: Vec::new(),
+            ui_locked: false,
+            lock_pin_hash: String::new(),
+            lock_pin: String::new(),
+            max_power_w: 0.0,
+            calibration: Calibration::default(),
+        }
+    }
+}
+
+/// 設定資料夾 (Windows: %APPDATA%, 其他: $XDG_CONFIG_HOME 或 ~/.config)
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("APPDATA")
+        .or_else(|| std::env::var_os("XDG_CONFIG_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("psu_controller"))
+}
+
+fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.json"))
+}
+
+/// 讀取設定；檔案不存在或格式錯誤時回傳預設值
+pub fn load() -> AppConfig {
+    config_path()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str::<AppConfig>(&s).ok())
+        .map(|mut cfg| {
+            if !cfg.lock_pin.is_empty() {
+                cfg.lock_pin_hash = hash_pin(&std::mem::take(&mut cfg.lock_pin));
+            }
+            cfg
+        })
+        .unwrap_or_default()
+}
+
+/// 寫入設定檔 (自動建立資料夾)
+pub fn save(cfg: &AppConfig) -> Result<(), anyhow::Error> {
+    let path = config_path().ok_or_else(|| anyhow::anyhow!("No config directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(cfg)?)?;
+    Ok(())
+}
+
+/// Kiosk PIN 存檔用的雜湊：`<隨機鹽 hex>$<sha256(鹽 + PIN) hex>`
+pub fn hash_pin(pin: &str) -> String {
+    // RandomState 每個都用系統亂數種子，拿來當鹽夠用
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let salt = format!("{:016x}{:016x}",
+        std::collections::hash_map::RandomState::new().hash_one(nanos),
+        std::collections::hash_map::RandomState::new().hash_one(std::process::id()));
+    format!("{}${}", salt, pin_digest(&salt, pin))
+}
+
+/// 輸入的 PIN 是否符合 `hash_pin` 存下來的雜湊
+pub fn verify_pin(stored: &str, pin: &str) -> bool {
+    stored.split_once('$').is_some_and(|(salt, digest)| pin_digest(salt, pin) == digest)
+}
+
+fn pin_digest(salt: &str, pin: &str) -> String {
+    let mut h = Sha256::new();
+    h.update(salt.as_bytes());
+    h.update(pin.as_bytes());
+    h.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pin_hash_is_salted_and_verifies() {
+        let a = hash_pin("1234");
+        let b = hash_pin("1234");
+        assert_ne!(a, b);
+        assert!(!a.contains("1234"));
+        assert!(verify_pin(&a, "1234"));
+        assert!(verify_pin(&b, "1234"));
+        assert!(!verify_pin(&a, "4321"));
+        assert!(!verify_pin("", "1234"));
+    }
+
+    #[test]
+    fn legacy_plaintext_pin_is_not_written_back() {
+        let cfg: AppConfig = serde_json::from_str(r#"{"lock_pin":"1234"}"#).unwrap();
+        assert_eq!(cfg.lock_pin, "1234");
+        let json = serde_json::to_string(&cfg).unwrap();
+        assert!(!json.contains("\"lock_pin\""));
+    }
+}
@@ -0,0 +1,102 @@
+//! # Measurement History
+//!
+//! Long-running buffer of every polled sample (not just the 100 points of the
+//! inline chart), the user's timestamped markers, and the zoom/pan window
+//! used by the detached chart view.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// 最多保留的取樣數 (200 ms 間隔約 2 小時)
+pub const MAX_SAMPLES: usize = 36_000;
+
+/// 使用者手動加的標記 ("開始熱浸泡"、"加上負載")
+#[derive(Debug, Clone)]
+pub struct Marker {
+    pub t: Instant,
+    pub note: String,
+}
+
+/// 完整的量測歷史 (只存真的讀到的點；斷線缺口存成 NaN)
+#[derive(Default)]
+pub struct History {
+    pub v: VecDeque<f32>,
+    pub i: VecDeque<f32>,
+    pub t: VecDeque<Instant>,
+    pub markers: Vec<Marker>,
+}
+
+impl History {
+    pub fn push(&mut self, v: f32, i: f32, t: Instant) {
+        if self.v.len() == MAX_SAMPLES {
+            self.v.pop_front();
+            self.i.pop_front();
+            self.t.pop_front();
+            // 比最舊一筆還舊的標記已經畫不出來了
+            if let Some(&first) = self.t.front() { self.markers.retain(|m| m.t >= first); }
+        }
+        self.v.push_back(v);
+        self.i.push_back(i);
+        self.t.push_back(t);
+    }
+
+    pub fn len(&self) -> usize { self.v.len() }
+
+    pub fn is_empty(&self) -> bool { self.v.is_empty() }
+
+    pub fn clear(&mut self) {
+        self.v.clear();
+        self.i.clear();
+        self.t.clear();
+        self.markers.clear();
+    }
+
+    pub fn add_marker(&mut self, t: Instant, note: &str) {
+        self.markers.push(Marker { t, note: note.to_string() });
+    }
+}
+
+/// 大圖目前看的範圍：最新往回數 `end_back` 點為右緣，共 `span` 點
+#[derive(Debug, Clone, Copy, Default)]
+pub struct View {
+    /// 0 = 全部
+    pub span: usize,
+    pub end_back: usize,
+}
+
+/// 至少要看得到幾個點 (再放大就沒意義了)
+const MIN_SPAN: usize = 10;
+
+impl View {
+    /// 換算成 `[start, end)` 索引範圍
+    pub fn range(&self, len: usize) -> (usize, usize) {
+        let end = len.saturating_sub(self.end_back.min(len));
+        let span = if self.span == 0 { len } else { self.span.min(len) };
+        (end.saturating_sub(span), end)
+    }
+
+    /// 以 `anchor` (0 左 ~ 1 右) 為中心縮放；factor < 1 = 放大
+    pub fn zoom(&mut self, factor: f32, anchor: f32, len: usize) {
+        if len == 0 { return; }
+        let (start, end) = self.range(len);
+        let old_span = end - start;
+        let new_span = ((old_span as f32 * factor).round() as usize).clamp(MIN_SPAN.min(len), len);
+
+        // 讓滑鼠下的那一點維持在同一個位置
+        let anchor = anchor.clamp(0.0, 1.0);
+        let pivot = start as f32 + old_span as f32 * anchor;
+        let new_start = ((pivot - new_span as f32 * anchor).round().max(0.0) as usize).min(len - new_span);
+        let new_end = new_start + new_span;
+
+        self.span = if new_span >= len { 0 } else { new_span };
+        self.end_back = len - new_end;
+    }
+
+    /// 平移 `frac` 個畫面寬 (正值 = 往舊資料方向)
+    pub fn pan(&mut self, frac: f32, len: usize) {
+        let (start, end) = self.range(len);
+        let shift = (frac * (end - start) as f32).round() as isize;
+        let max_back = (len - (end - start)) as isize;
+        self.end_back = (self.end_back as isize + shift).clamp(0, max_back) as usize;
+    }
+}
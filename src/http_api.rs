@@ -0,0 +1,120 @@
+//! # HTTP/JSON Readings Endpoint
+//!
+//! Optional, read-only embedded HTTP server for lab dashboards:
+//! `GET /readings` returns the latest poll, `GET /history` the recent buffer,
+//! `GET /markers` the user's timestamped notes.
+//! The server runs on its own thread; the poll timer publishes into a shared,
+//! mutex-protected snapshot.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::logger;
+use crate::measurement::{Measurement, MeasurementSink};
+
+/// `/history` 最多回傳幾筆 (對應趨勢圖的點數)
+pub const HISTORY_LEN: usize = 100;
+
+/// 一筆量測 (JSON 欄位名稱就是 API 格式)
+#[derive(Serialize, Clone, Debug)]
+pub struct Reading {
+    pub v: f32,
+    pub i: f32,
+    pub p: f32,
+    pub mode: String,
+    pub ts: String,
+}
+
+impl From<&Measurement> for Reading {
+    fn from(m: &Measurement) -> Self {
+        Self { v: m.v, i: m.i, p: m.p, mode: m.mode.clone(), ts: m.ts.format(logger::TS_FORMAT).to_string() }
+    }
+}
+
+/// 使用者加的標記
+#[derive(Serialize, Clone, Debug)]
+pub struct Marker {
+    pub ts: String,
+    pub note: String,
+}
+
+/// 輪詢寫入、HTTP 執行緒讀取的最新資料
+#[derive(Default)]
+pub struct Readings {
+    latest: Option<Reading>,
+    history: VecDeque<Reading>,
+    markers: VecDeque<Marker>,
+}
+
+impl Readings {
+    pub fn publish(&mut self, r: Reading) {
+        if self.history.len() == HISTORY_LEN { self.history.pop_front(); }
+        self.history.push_back(r.clone());
+        self.latest = Some(r);
+    }
+
+    pub fn add_marker(&mut self, m: Marker) {
+        if self.markers.len() == HISTORY_LEN { self.markers.pop_front(); }
+        self.markers.push_back(m);
+    }
+}
+
+pub type SharedReadings = Arc<Mutex<Readings>>;
+
+impl MeasurementSink for SharedReadings {
+    fn record(&mut self, m: &Measurement) -> Result<(), String> {
+        // HTTP 執行緒拿得到鎖才更新，拿不到就等下一筆 (不卡 UI)
+        if let Ok(mut r) = self.try_lock() { r.publish(Reading::from(m)); }
+        Ok(())
+    }
+}
+
+/// 執行中的 HTTP 伺服器；`stop` 後執行緒會結束
+pub struct Server {
+    server: Arc<tiny_http::Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// 在 `0.0.0.0:port` 開始服務
+    pub fn start(port: u16, readings: SharedReadings) -> Result<Self, anyhow::Error> {
+        let server = Arc::new(tiny_http::Server::http(("0.0.0.0", port)).map_err(|e| anyhow::anyhow!("{}", e))?);
+        let srv = server.clone();
+        let thread = std::thread::spawn(move || {
+            // unblock() 之後 incoming_requests 會結束
+            for req in srv.incoming_requests() {
+                let (status, body) = respond(req.method(), req.url(), &readings);
+                let header = tiny_http::Header::from_bytes("Content-Type", "application/json").expect("static header");
+                let resp = tiny_http::Response::from_string(body).with_status_code(status).with_header(header);
+                let _ = req.respond(resp);
+            }
+        });
+        Ok(Self { server, thread: Some(thread) })
+    }
+
+    pub fn stop(mut self) {
+        self.server.unblock();
+        if let Some(t) = self.thread.take() { let _ = t.join(); }
+    }
+}
+
+/// 路由：只提供唯讀的 GET
+fn respond(method: &tiny_http::Method, url: &str, readings: &SharedReadings) -> (u16, String) {
+    if *method != tiny_http::Method::Get {
+        return (405, r#"{"error":"method not allowed"}"#.to_string());
+    }
+    let Ok(r) = readings.lock() else { return (500, r#"{"error":"state unavailable"}"#.to_string()); };
+    let path = url.split('?').next().unwrap_or("");
+    let body = match path {
+        "/readings" => serde_json::to_string(&r.latest),
+        "/history" => serde_json::to_string(&r.history),
+        "/markers" => serde_json::to_string(&r.markers),
+        _ => return (404, r#"{"error":"not found"}"#.to_string()),
+    };
+    match body {
+        Ok(b) => (200, b),
+        Err(e) => (500, format!(r#"{{"error":"{}"}}"#, e)),
+    }
+}
@@ -0,0 +1,27 @@
+//! # PSU Controller Core
+//!
+//! UI-independent building blocks of the PSU controller: the SCPI link,
+//! device profiles, logging/capture, chart geometry, calibration and
+//! measurement events.
+//! The `psu_controller` binary is a Slint front end on top of this library;
+//! other tools can use the same modules and subscribe to
+//! [`measurement::Observers`] to receive each polled [`measurement::Measurement`].
+
+pub mod scpi;
+pub mod config;
+pub mod spectrum;
+pub mod profile;
+pub mod logger;
+pub mod capture;
+pub mod chart;
+pub mod history;
+pub mod policy;
+pub mod http_api;
+pub mod clock;
+pub mod calibration;
+pub mod measurement;
+pub mod replay;
+pub mod summary;
+pub mod worker;
+pub mod shared_port;
+pub mod txlog;
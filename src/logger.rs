@@ -0,0 +1,239 @@
+//! # CSV Measurement Logger
+//!
+//! Writes one row per measurement (plus optional note rows) to a CSV file,
+//! and reads the voltage/current columns back for reference overlays and replay.
+//! Long runs can rotate to a new timestamped file by size or age, optionally
+//! gzipping the finished parts. `CsvSink` plugs a logger into the
+//! measurement fan-out for continuous logging.
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::measurement::{Measurement, MeasurementSink};
+
+/// CSV 欄位 (其他功能會依此格式讀回檔案)
+pub const CSV_HEADER: &str = "timestamp,voltage,current,power,mode,note";
+
+/// 時間戳格式 (本地時間，精確到 ms)
+pub const TS_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// 把 `Instant` 換算回牆上時間 (用於 Buffer 裡已經取樣過的點)
+pub fn wall_clock(t: Instant) -> DateTime<Local> {
+    let ago = Instant::now().saturating_duration_since(t);
+    Local::now() - chrono::Duration::from_std(ago).unwrap_or_default()
+}
+
+/// 在目前資料夾產生帶時間戳的檔名，例如 `capture_20240101_120000.csv`
+pub fn timestamped_path(prefix: &str) -> PathBuf {
+    PathBuf::from(format!("{}_{}.csv", prefix, Local::now().format("%Y%m%d_%H%M%S")))
+}
+
+/// 自動換檔的條件 (任一個達到就換)；0 = 不限制
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_age: Duration,
+    /// 換下來的舊檔壓成 .csv.gz
+    pub gzip: bool,
+}
+
+impl Rotation {
+    fn enabled(&self) -> bool { self.max_bytes > 0 || !self.max_age.is_zero() }
+}
+
+pub struct CsvLogger {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    rotation: Rotation,
+    /// 換檔時新檔名的前綴 (例如 "capture")
+    prefix: String,
+    opened: Instant,
+    bytes: u64,
+}
+
+impl CsvLogger {
+    /// 建立新檔並寫入標題列
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let (writer, bytes) = open_csv(path)?;
+        Ok(Self { writer, path: path.to_path_buf(), rotation: Rotation::default(), prefix: String::new(), opened: Instant::now(), bytes })
+    }
+
+    /// 建立以 `prefix` 加時間戳命名的檔案，並依 `rotation` 自動換檔
+    pub fn create_rotating(prefix: &str, rotation: Rotation) -> std::io::Result<Self> {
+        let mut log = Self::create(&timestamped_path(prefix))?;
+        log.rotation = rotation;
+        log.prefix = prefix.to_string();
+        Ok(log)
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// 寫入一筆量測
+    pub fn record(&mut self, ts: DateTime<Local>, v: f32, i: f32, mode: &str) -> std::io::Result<()> {
+        self.record_with_note(ts, v, i, mode, "")
+    }
+
+    /// 寫入一筆量測，註記欄位另外帶一段文字 (例如校正前的原始讀值)
+    pub fn record_with_note(&mut self, ts: DateTime<Local>, v: f32, i: f32, mode: &str, note: &str) -> std::io::Result<()> {
+        let note = if note.is_empty() { String::new() } else { format!("\"{}\"", note.replace('"', "\"\"")) };
+        self.write_line(&format!("{},{:.4},{:.4},{:.4},{},{}", ts.format(TS_FORMAT), v, i, v * i, mode, note))
+    }
+
+    /// 寫入一筆註記 (數值欄位留空)
+    pub fn note(&mut self, ts: DateTime<Local>, note: &str) -> std::io::Result<()> {
+        // CSV 內的逗號/引號要跳脫
+        let escaped = note.replace('"', "\"\"");
+        self.write_line(&format!("{},,,,,\"{}\"", ts.format(TS_FORMAT), escaped))
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        writeln!(self.writer, "{}", line)?;
+        self.bytes += line.len() as u64 + 1;
+        self.rotate_if_needed()
+    }
+
+    /// 超過大小或時間就關掉目前的檔案，換一個新的 (舊檔可選擇壓縮)
+    fn rotate_if_needed(&mut self) -> std::io::Result<()> {
+        if !self.rotation.enabled() { return Ok(()); }
+        let too_big = self.rotation.max_bytes > 0 && self.bytes >= self.rotation.max_bytes;
+        let too_old = !self.rotation.max_age.is_zero() && self.opened.elapsed() >= self.rotation.max_age;
+        if !too_big && !too_old { return Ok(()); }
+
+        // 同一秒內換檔時檔名會重複，加上毫秒
+        let next = PathBuf::from(format!("{}_{}.csv", self.prefix, Local::now().format("%Y%m%d_%H%M%S_%3f")));
+        let (writer, bytes) = open_csv(&next)?;
+        let mut old = std::mem::replace(&mut self.writer, writer);
+        old.flush()?;
+        drop(old);
+        let old_path = std::mem::replace(&mut self.path, next);
+        self.opened = Instant::now();
+        self.bytes = bytes;
+
+        if self.rotation.gzip {
+            // 大檔壓縮要一點時間，丟到背景做
+            std::thread::spawn(move || {
+                if let Err(e) = gzip_file(&old_path) { eprintln!("Log gzip error: {}", e); }
+            });
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// 連續記錄：開著的時候每筆量測寫一行
+#[derive(Default)]
+pub struct CsvSink {
+    log: Option<CsvLogger>,
+}
+
+impl CsvSink {
+    /// 開一個 `log_<時間>.csv` 開始記錄，回傳檔名
+    pub fn start(&mut self, rotation: Rotation) -> std::io::Result<PathBuf> {
+        self.stop();
+        let log = CsvLogger::create_rotating("log", rotation)?;
+        let path = log.path().to_path_buf();
+        self.log = Some(log);
+        Ok(path)
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(mut log) = self.log.take() { let _ = log.flush(); }
+    }
+
+    pub fn is_running(&self) -> bool { self.log.is_some() }
+}
+
+impl MeasurementSink for CsvSink {
+    /// 寫入失敗 (磁碟滿、隨身碟拔掉) 就停止記錄，不要每一筆都報錯
+    fn record(&mut self, m: &Measurement) -> Result<(), String> {
+        let Some(log) = self.log.as_mut() else { return Ok(()); };
+        log.record(m.ts, m.v, m.i, &m.mode).map_err(|e| {
+            self.log = None;
+            format!("CSV log stopped: {}", e)
+        })
+    }
+
+    fn enabled(&self) -> bool { self.is_running() }
+}
+
+/// 開新檔並寫入標題列，回傳 writer 與已寫入的 bytes
+fn open_csv(path: &Path) -> std::io::Result<(BufWriter<File>, u64)> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", CSV_HEADER)?;
+    Ok((writer, CSV_HEADER.len() as u64 + 1))
+}
+
+/// `x.csv` → `x.csv.gz`，成功後刪掉原檔
+fn gzip_file(path: &Path) -> std::io::Result<()> {
+    let mut gz_name = path.as_os_str().to_owned();
+    gz_name.push(".gz");
+    let mut input = File::open(path)?;
+    let mut enc = flate2::write::GzEncoder::new(File::create(&gz_name)?, flate2::Compression::default());
+    std::io::copy(&mut input, &mut enc)?;
+    enc.finish()?;
+    std::fs::remove_file(path)
+}
+
+/// 讀回 CSV 的電壓 / 電流欄位 (跳過標題列與註記列)
+pub fn read_trace(path: &Path) -> std::io::Result<(Vec<f32>, Vec<f32>)> {
+    let text = std::fs::read_to_string(path)?;
+    let mut volts = Vec::new();
+    let mut amps = Vec::new();
+    for line in text.lines().skip(1) {
+        let mut cols = line.split(',').skip(1);
+        let (Some(v), Some(i)) = (cols.next(), cols.next()) else { continue; };
+        // 註記列的數值欄位是空的，parse 失敗就略過
+        if let (Ok(v), Ok(i)) = (v.trim().parse::<f32>(), i.trim().parse::<f32>()) {
+            volts.push(v);
+            amps.push(i);
+        }
+    }
+    if volts.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "no measurement rows"));
+    }
+    Ok((volts, amps))
+}
+
+/// 檔案裡的一筆量測 (含原本的時間戳)
+#[derive(Debug, Clone)]
+pub struct LoggedSample {
+    pub ts: NaiveDateTime,
+    pub v: f32,
+    pub i: f32,
+    pub mode: String,
+}
+
+/// 讀回 CSV 的量測列與時間戳 (重播用)；時間戳或數值讀不懂的列略過
+pub fn read_samples(path: &Path) -> std::io::Result<Vec<LoggedSample>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut out = Vec::new();
+    for line in text.lines().skip(1) {
+        // 註記欄在最後 (可能含逗號)，前五欄一定不含
+        let cols: Vec<&str> = line.splitn(6, ',').collect();
+        let &[ts, v, i, _, mode, ..] = cols.as_slice() else { continue; };
+        let Ok(ts) = NaiveDateTime::parse_from_str(ts.trim(), TS_FORMAT) else { continue; };
+        if let (Ok(v), Ok(i)) = (v.trim().parse::<f32>(), i.trim().parse::<f32>()) {
+            out.push(LoggedSample { ts, v, i, mode: mode.trim().to_string() });
+        }
+    }
+    if out.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "no measurement rows"));
+    }
+    Ok(out)
+}
+
+/// 把 I-V 掃描結果寫成 CSV (設定電壓, 量到的電壓, 量到的電流)
+pub fn write_iv_curve(path: &Path, points: &[(f32, f32, f32)]) -> std::io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "voltage_set,voltage,current")?;
+    for (set, v, i) in points {
+        writeln!(w, "{:.4},{:.4},{:.4}", set, v, i)?;
+    }
+    w.flush()
+}
@@ -5,201 +5,863 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod scpi; 
+// 與 UI 無關的邏輯都在 library (src/lib.rs)
+use psu_controller::{calibration, capture, chart, clock, config, history, http_api, logger, measurement, profile, replay, scpi, spectrum, shared_port, summary, txlog, worker};
 
 use slint::{ComponentHandle, Model, SharedString, VecModel, Color, Timer, TimerMode};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque; // 用來做 Ring Buffer
-use serialport::{ClearBuffer, SerialPort};
+use serialport::ClearBuffer;
 
 slint::include_modules!();
 
+/// 找不到任何序列埠時，下拉選單顯示的佔位文字 (不能拿去連線)
+const NO_PORTS_LABEL: &str = "No Ports Found";
+
+/// 所有 callback / timer 共用的序列埠 (見 `shared_port`)
+type SharedPort = shared_port::SharedPort<scpi::Link>;
+
+/// `io_scpi` 的後續處理：指令的結果回到事件迴圈時呼叫
+type IoDone = Box<dyn FnOnce(Result<Option<String>, scpi::ScpiError>)>;
+
+/// 趨勢圖固定的採樣點數
+const CHART_WIDTH: usize = 100;
+
+/// `*RST` 之後等儀器重設完成 (`*OPC?`) 的上限
+const RESET_OPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 開關輸出之後等儀器確認 (`*OPC?`) 的上限；輸出繼電器通常幾十 ms 內就切好
+const OUTPUT_OPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 輪詢重送次數上限 (每次都可能等滿讀取超時，太多會卡住 UI)
+const MAX_POLL_RETRIES: u32 = 5;
+
+/// 輪詢讀取失敗時第一次重送前等多久 (之後每次加倍)
+const POLL_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// 連續幾次輪詢讀不到回應就當作斷線，開始自動重連
+const RECONNECT_AFTER_FAILURES: u32 = 3;
+
+/// 自動重連每次嘗試的間隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 背景正在開 Port / 掃描鮑率時的狀態列文字
+const CONNECTING: &str = "Connecting…";
+
+/// 自動重連次數上限 (設定畫面可以調的最大值)
+const MAX_RECONNECT_ATTEMPTS: u32 = 50;
+
+/// 連續幾次量到超過功率上限才關輸出 (單一筆雜訊讀值不會誤跳)
+const POWER_TRIP_POLLS: u32 = 2;
+
+/// 連續幾次回應一模一樣 (Output 開著) 就提醒儀器可能卡住
+const STALE_POLLS: u32 = 50;
+
+/// 匯入的參考曲線 (電壓, 電流)，已重新取樣成 `CHART_WIDTH` 點
+type GoldenTrace = Rc<RefCell<Option<(VecDeque<f32>, VecDeque<f32>)>>>;
+
+/// 在事件迴圈上直接借用共享序列埠執行 `f`
+///
+/// 只用在不碰序列埠 I/O 的短暫讀取 (例如 trace 內容)；會送指令的一律走 `SharedPort::run` / `post`。
+/// 連線借給背景執行緒時回傳 `Busy`，呼叫端跳過這次就好。
+fn with_port<R>(sp: &SharedPort, f: impl FnOnce(&mut scpi::Link) -> R) -> Result<R, scpi::ScpiError> {
+    let mut port_ref = sp.try_borrow_mut()?;
+    match *port_ref {
+        Some(ref mut p) => Ok(f(p)),
+        None => Err(scpi::ScpiError::Disconnected),
+    }
+}
+
+/// 開 Port 失敗是不是因為別的程式佔用中
+///
+/// Windows 把「被佔用 (ACCESS_DENIED)」和「找不到」都回報成 `NoDevice`，
+/// 所以 Port 還在列表裡才算佔用；Linux / macOS 被鎖住是 EBUSY (`NoDevice`) 或 EACCES。
+fn port_in_use(e: &serialport::Error, port_name: &str) -> bool {
+    match e.kind() {
+        serialport::ErrorKind::Io(std::io::ErrorKind::PermissionDenied) => true,
+        serialport::ErrorKind::NoDevice => serialport::available_ports()
+            .map(|ports| ports.iter().any(|p| p.port_name == port_name))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// 下拉選單顯示的 Port 說明：USB 轉接器加上製造商 / 產品名稱與 VID:PID，其他的只顯示名稱
+fn port_label(p: &serialport::SerialPortInfo) -> String {
+    let serialport::SerialPortType::UsbPort(usb) = &p.port_type else { return p.port_name.clone() };
+    // 很多驅動程式的產品名稱已經包含製造商，不要重複
+    let product = usb.product.as_deref().unwrap_or("");
+    let desc = match usb.manufacturer.as_deref() {
+        Some(m) if !product.contains(m) => format!("{} {}", m, product),
+        _ => product.to_string(),
+    };
+    let desc = desc.trim();
+    let ids = format!("{:04X}:{:04X}", usb.vid, usb.pid);
+    if desc.is_empty() { format!("{} — USB {}", p.port_name, ids) } else { format!("{} — {} ({})", p.port_name, desc, ids) }
+}
+
+/// 序列埠清單：`names` 是拿去開 Port 的名稱，`labels` 是下拉選單顯示的說明 (同樣順序)
+#[derive(Clone)]
+struct PortList {
+    names: Rc<VecModel<SharedString>>,
+    labels: Rc<VecModel<SharedString>>,
+}
+
+impl PortList {
+    fn new(ui: &AppWindow) -> Self {
+        let list = Self { names: Rc::new(VecModel::default()), labels: Rc::new(VecModel::default()) };
+        ui.set_available_ports(list.names.clone().into());
+        ui.set_port_labels(list.labels.clone().into());
+        list
+    }
+
+    /// 重新列出序列埠；原本選的 Port 還在就保留，不在 (或還沒選) 就改選第一個
+    fn refresh(&self, ui: &AppWindow) {
+        let ports = serialport::available_ports().unwrap_or_default();
+        ui.set_has_ports(!ports.is_empty());
+        let (names, labels): (Vec<SharedString>, Vec<SharedString>) = if ports.is_empty() {
+            (vec![NO_PORTS_LABEL.into()], vec![NO_PORTS_LABEL.into()])
+        } else {
+            ports.iter().map(|p| (p.port_name.as_str().into(), port_label(p).into())).unzip()
+        };
+        let selected = ui.get_selected_port();
+        let keep = names.contains(&selected);
+        self.names.set_vec(names);
+        self.labels.set_vec(labels);
+        // 連線中不動選擇 (畫面上的 Port 就是正在用的那個)
+        if keep || ui.get_status_text() == "Connected" || ui.get_status_text() == CONNECTING {
+            select_port(ui, &selected);
+        } else if let Some(first_port) = self.names.row_data(0) {
+            select_port(ui, &first_port);
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|p| p.as_str() == name)
+    }
+}
+
+/// 選定 Port：`selected-port` 是真正拿去開的名稱，下拉選單顯示對應的說明 (不在清單裡就顯示名稱)
+fn select_port(ui: &AppWindow, name: &str) {
+    let label = ui.get_available_ports().iter().position(|p| p.as_str() == name)
+        .and_then(|i| ui.get_port_labels().row_data(i))
+        .unwrap_or_else(|| name.into());
+    ui.set_selected_port(name.into());
+    ui.set_selected_port_label(label);
+}
+
 fn main() -> Result<(), anyhow::Error> {
     let ui = AppWindow::new()?;
 
-    // --- 1. Port 列表初始化 ---
-    let ports = serialport::available_ports().unwrap_or_default();
-    let mut port_names: Vec<SharedString> = vec![];
-    if ports.is_empty() { port_names.push("No Ports Found".into()); } 
-    else { for p in ports { port_names.push(p.port_name.into()); } }
-    
-    let ports_model = Rc::new(VecModel::from(port_names));
-    ui.set_available_ports(ports_model.clone().into());
-    if let Some(first_port) = ports_model.row_data(0) { ui.set_selected_port(first_port); }
+    // --- 1. Port 列表初始化 (之後按 ⟳ 可以重新列出) ---
+    let port_list = PortList::new(&ui);
+    port_list.refresh(&ui);
+
+    let ui_h = ui.as_weak();
+    let ports = port_list.clone();
+    ui.on_refresh_ports(move || {
+        let u = ui_h.unwrap();
+        ports.refresh(&u);
+        let n = if u.get_has_ports() { ports.names.row_count() } else { 0 };
+        u.set_message_text(format!("{} serial port(s) found", n).into());
+    });
+
+    // 下拉選單選的是說明文字，換回真正的 Port 名稱
+    let ui_h = ui.as_weak();
+    let ports = port_list.clone();
+    ui.on_port_selected(move |idx| {
+        let u = ui_h.unwrap();
+        if let Some(name) = ports.names.row_data(idx as usize) { select_port(&u, &name); }
+    });
 
     // --- 2. 共享資源 ---
-    let shared_port: Rc<RefCell<Option<Box<dyn SerialPort>>>> = Rc::new(RefCell::new(None));
+    // 序列埠的讀寫都在這條執行緒上做，事件迴圈不會卡在讀取超時
+    // 背景工作做完就請事件迴圈去接手後續處理
+    let notify: shared_port::Notify = std::sync::Arc::new(|| { let _ = slint::invoke_from_event_loop(shared_port::dispatch); });
+    let shared_port = SharedPort::new(worker::Worker::spawn("serial-io")?, notify);
     let loop_timer = Rc::new(RefCell::new(Timer::default()));
     let loop_state = Rc::new(RefCell::new(false)); 
     let monitor_timer = Rc::new(RefCell::new(Timer::default())); // 在 main 裡叫 monitor_timer
+    let auto_off_timer = Rc::new(RefCell::new(Timer::default()));  // 時間到關輸出 (SingleShot)
+    let auto_off_ticker = Rc::new(RefCell::new(Timer::default())); // 每秒更新倒數顯示
+    let capture_state = Rc::new(RefCell::new(capture::Capture::Idle));
+    let relative_ref = Rc::new(RefCell::new(RelativeRef::default())); // 相對 (Δ) 顯示的參考點
+    let golden_trace: GoldenTrace = Rc::new(RefCell::new(None));
+    let history = Rc::new(RefCell::new(history::History::default())); // 完整歷史 (給分離的大圖)
+    let readings: http_api::SharedReadings = Default::default(); // 給 HTTP 執行緒讀的最新讀值
+    let observers = Rc::new(RefCell::new(measurement::Observers::default())); // 每筆量測的訂閱者
+    let csv_sink = Rc::new(RefCell::new(logger::CsvSink::default())); // 連續 CSV 記錄
+    observers.borrow_mut().subscribe(readings.clone());
+    observers.borrow_mut().subscribe(csv_sink.clone());
+    let session = Rc::new(RefCell::new(summary::SessionSummary::default())); // 整段連線的統計 (匯出摘要用)
+    observers.borrow_mut().subscribe(session.clone());
+    let app_config = Rc::new(RefCell::new(config::load()));
+    let tx_log: Rc<RefCell<Option<txlog::TxLog>>> = Rc::new(RefCell::new(None)); // SCPI 交易紀錄檔 (除錯用)
+    let active_profile = Rc::new(RefCell::new(profile::DeviceProfile::default()));
+    let calibration = Rc::new(RefCell::new(app_config.borrow().calibration)); // 讀值校正 (gain / offset)
+    let poll_state = PollState {
+        calibration: calibration.clone(),
+        profile: active_profile.clone(),
+        capture: capture_state.clone(),
+        reference: relative_ref.clone(),
+        golden: golden_trace.clone(),
+        history: history.clone(),
+        observers: observers.clone(),
+        session: session.clone(),
+        chart: Rc::new(RefCell::new(ChartBuffers::default())),
+        overlay: Rc::new(RefCell::new(ChannelOverlay::default())),
+    };
+    let sequence_timer = Rc::new(RefCell::new(Timer::default()));
+    let telemetry_timer = Rc::new(Timer::default()); // AC 輸入等慢速遙測
+    let ramp_timer = Rc::new(Timer::default());      // 關輸出前的降壓斜坡
+    let iv_timer = Rc::new(Timer::default());        // I-V 掃描的每一步
+    let device_timers = DeviceTimers {
+        poll: monitor_timer.clone(),
+        telemetry: telemetry_timer.clone(),
+        looping: loop_timer.clone(),
+        auto_off: auto_off_timer.clone(),
+        auto_off_ticker: auto_off_ticker.clone(),
+        sequence: sequence_timer.clone(),
+        ramp: ramp_timer.clone(),
+        iv: iv_timer.clone(),
+    };
+    let preset_model = Rc::new(VecModel::<SharedString>::default());
+    ui.set_preset_names(preset_model.clone().into());
+    refresh_preset_names(&preset_model, &app_config.borrow());
+    // Device Profiles (內建 + 使用者自訂)
+    let profiles = Rc::new(RefCell::new(profile::load_all()));
+    let profile_names: Vec<SharedString> = profiles.borrow().iter().map(|p| p.name.as_str().into()).collect();
+    ui.set_profile_names(Rc::new(VecModel::from(profile_names)).into());
+
+    {
+        let c = app_config.borrow();
+        let all = profiles.borrow();
+        let saved = all.iter().find(|p| p.name == c.profile).unwrap_or(&all[0]);
+        ui.set_selected_profile(saved.name.as_str().into());
+        ui.set_on_connect_cmds(saved.on_connect_commands.join("; ").into());
+        ui.set_efficiency_enabled(c.efficiency_enabled);
+        ui.set_efficiency_in_ch(c.efficiency_in_ch);
+        ui.set_efficiency_out_ch(c.efficiency_out_ch);
+        ui.set_overlay_enabled(c.overlay_enabled);
+        ui.set_overlay_channels(c.overlay_channels.as_str().into());
+        ui.set_overlay_quantity(c.overlay_quantity.as_str().into());
+        ui.set_overlay_shared_scale(c.overlay_shared_scale);
+        ui.set_power_sequence(c.power_sequence.as_str().into());
+        ui.set_auto_connect(c.auto_connect);
+        ui.set_baud_rate(c.baud_rate.to_string().into());
+        ui.set_framing(c.framing.as_str().into());
+        ui.set_completion_beep(c.completion_beep);
+        ui.set_settle_ms(c.settle_ms.to_string().into());
+        ui.set_read_timeout_ms(c.read_timeout_ms.to_string().into());
+        ui.set_poll_retries(c.poll_retries.to_string().into());
+        ui.set_reconnect_attempts(c.reconnect_attempts.to_string().into());
+        ui.set_theme_mode(c.theme.as_str().into());
+        ui.set_power_limit(c.max_power_w as f32);
+        ui.set_max_power(scpi::format_number(c.max_power_w).into());
+        ui.set_http_enabled(c.http_enabled);
+        ui.set_http_port(c.http_port.to_string().into());
+        ui.set_recent_devices(recent_labels(&c));
+        ui.set_ui_locked(c.ui_locked);
+        show_calibration(&ui, &c.calibration);
+    }
 
     // --- 3. 連線/斷線邏輯 ---
     let ui_handle = ui.as_weak();
     let sp_connect = shared_port.clone(); 
     let monitor_timer_ref = monitor_timer.clone(); 
+    let poll_connect = poll_state.clone();
+    let history_connect = history.clone();
+    let profiles_connect = profiles.clone();
+    let active_connect = active_profile.clone();
+    let timers_connect = device_timers.clone();
+    let t_telemetry = telemetry_timer.clone();
+    let cfg_connect = app_config.clone();
+    let txlog_connect = tx_log.clone();
 
     ui.on_toggle_connection(move || {
         let ui = ui_handle.unwrap();
         
         if ui.get_status_text() == "Connected" {
             // --- 斷線邏輯 ---
-            monitor_timer_ref.borrow().stop();
-            
-            // 解鎖面板
-            if let Some(ref mut p) = *sp_connect.borrow_mut() {
-                let _ = scpi::send_command(p, scpi::cmds::UNLOCK);
+            // 解鎖面板 (回到本地模式)；還在等觸發的話先取消
+            let profile = active_connect.borrow().clone();
+            let abort = ui.get_inst_trigger_armed() && !profile.abort_cmd.is_empty();
+            if let Err(e) = sp_connect.close(move |p| {
+                if abort { let _ = scpi::send_command(p, &profile.abort_cmd); }
+                profile.return_local(p);
+            }) {
+                ui.set_message_text(e.to_string().into());
+                return;
+            }
+            // 所有會送指令的 Timer 都停掉，免得斷線後還有 callback 去寫已經關掉的 Port
+            stop_all_timers(&ui, &timers_connect, true);
+            // 保留圖表時在斷線點留一個缺口，重新連線後接著畫
+            if ui.get_keep_chart() {
+                let now = Instant::now();
+                poll_connect.chart.borrow_mut().mark_gap(now);
+                history_connect.borrow_mut().push(f32::NAN, f32::NAN, now);
             }
-            *sp_connect.borrow_mut() = None; 
+            ui.set_ac_telemetry_supported(false);
+            ui.set_device_local(false);
+            ui.set_interlock_open(false);
+            ui.set_reading_stale(false);
+            ui.set_poll_rtt_ms(0.0);
+            ui.set_instrument_error("".into());
+            ui.set_inst_trigger_armed(false);
+            ui.set_inst_trigger_status("".into());
 
             // 更新狀態列
             ui.set_status_text("Disconnected".into());
             ui.set_status_color(Color::from_rgb_u8(255, 0, 0).into());
             ui.set_window_title("Rust PSU Controller".into());
+            show_identity(&ui, &scpi::Identity::default());
             
             // 重置功能開關
-            ui.set_is_looping(false); 
             ui.set_is_output_on(false); // 按鈕變回灰色
 
             // 🟢 [新增] 重置讀值顯示
             ui.set_voltage_reading("---".into());
             ui.set_current_reading("---".into());
             ui.set_power_reading("0.00".into()); // 如果你有加功率計的話
+            ui.set_is_sinking(false);
             ui.set_psu_mode("".into());          // 清除 CC/CV 燈號
+            ui.set_mode_explanation("".into());
+            ui.set_baud_suspect(false);
         } else {
+            // 正在開 Port (或掃描鮑率)：重複按不理
+            if ui.get_status_text() == CONNECTING { return; }
             let port_name = ui.get_selected_port();
-            match serialport::new(port_name.as_str(), 9600).timeout(Duration::from_millis(500)).open() {
-                Ok(mut p) => {
-                    let _ = p.clear(ClearBuffer::Input);
-                    
-                    // 1. 獲取 IDN
-                    if let Some(info) = scpi::send_command(&mut p, scpi::cmds::IDN) {
-                        ui.set_window_title(format!("Rust PSU Controller - {}", info).into());
-                    }
-
-                    // 2. 同步 Output 狀態 (上一回加的)
-                    if let Some(outp_status) = scpi::send_command(&mut p, scpi::cmds::READ_OUTP) {
-                        let clean = outp_status.trim().to_uppercase();
-                        let is_on = clean == "1" || clean == "ON";
-                        ui.set_is_output_on(is_on);
-                    }
-
-                    // 🟢 [新增] 3. 同步設定電壓 (Set Voltage)
-                    if let Some(v_str) = scpi::send_command(&mut p, scpi::cmds::GET_SET_VOLT) {
-                        // SCPI 可能回傳 "12.0000"，我們解析後轉回 "12.00" 保持介面整潔
-                        let val: f64 = v_str.trim().parse().unwrap_or(0.0);
-                        ui.set_target_voltage(format!("{:.2}", val).into());
-                    }
+            // 佔位文字不是真的 Port，直接提示使用者而不是丟給 serialport 報錯
+            if port_name.as_str() == NO_PORTS_LABEL || port_name.is_empty() {
+                ui.set_message_text("No serial port available. Plug in the instrument and press ⟳ to refresh the list.".into());
+                return;
+            }
+            // 看不懂的數字 (或 0) 一律退回 9600，不要讓 serialport 拿去開 Port
+            let baud = ui.get_baud_rate().trim().parse::<u32>().ok().filter(|&b| b > 0).unwrap_or(9600);
+            let framing = scpi::Framing::parse(&ui.get_framing()).unwrap_or_default();
+            // 依選定的 Profile 套用 pacing / delay 設定
+            let profile = profiles_connect.borrow().iter()
+                .find(|p| p.name == ui.get_selected_profile().as_str())
+                .cloned()
+                .unwrap_or_default();
+            // 開 Port + DTR/RTS + 開機等待 (可能好幾秒) 在背景做；前一次斷線的收尾一定先做完
+            ui.set_status_text(CONNECTING.into());
+            ui.set_status_color(Color::from_rgb_u8(255, 165, 0).into());
+            let (name, timeout, opening) = (port_name.to_string(), read_timeout(&ui), profile.clone());
+            let ui_done = ui.as_weak();
+            let (sp, monitor, poll, history, active) = (sp_connect.clone(), monitor_timer_ref.clone(), poll_connect.clone(), history_connect.clone(), active_connect.clone());
+            let (t_telemetry, cfg_connect, txlog) = (t_telemetry.clone(), cfg_connect.clone(), txlog_connect.clone());
+            sp_connect.run_detached(move || open_port(&name, baud, framing, timeout, &opening), move |res| {
+                let Some(ui) = ui_done.upgrade() else { return; };
+                let res = match res {
+                    Ok(res) => res,
+                    Err(e) => return ui.set_status_text(format!("Err: {}", e).into()),
+                };
+                match res {
+                    Ok((port, prepared)) => {
+                        // 有些轉接器不支援控制 DTR/RTS，提示後照樣連線
+                        if let Some(e) = prepared {
+                            ui.set_message_text(format!("Could not set DTR/RTS: {}", e).into());
+                        }
+                        let mut p = scpi::Link::new(port, profile.link_options());
+                        (p.opts.write_terminator, p.opts.read_terminator) = terminators(&ui);
+                        p.trace.enabled = ui.get_trace_enabled();
+                        p.trace.preserve_raw = ui.get_trace_preserve_raw();
+                        p.txlog = txlog.borrow().clone();
+                        p.settle = settle_duration(&ui);
+                        p.opts.read_timeout = read_timeout(&ui);
 
-                    // 🟢 [新增] 4. 同步設定電流 (Set Current Limit)
-                    if let Some(c_str) = scpi::send_command(&mut p, scpi::cmds::GET_SET_CURR) {
-                        // 轉為 3 位小數，例如 "1.500"
-                        let val: f64 = c_str.trim().parse().unwrap_or(0.0);
-                        ui.set_target_current(format!("{:.3}", val).into());
-                    }
+                        // 新連線：清掉上一台儀器的完整歷史與摘要統計 (勾了 Keep chart 就保留)
+                        if !ui.get_keep_chart() {
+                            history.borrow_mut().clear();
+                            poll.session.borrow_mut().clear();
+                        }
 
-                    // 3. 同步設定電壓 (Set Voltage)
-                    if let Some(v_str) = scpi::send_command(&mut p, scpi::cmds::GET_SET_VOLT) {
-                        let val: f64 = v_str.trim().parse().unwrap_or(0.0);
-                        // 更新輸入框 (給人看)
-                        ui.set_target_voltage(format!("{:.2}", val).into());
-                        // 🟢 [新增] 更新生效值 (給邏輯用)
-                        ui.set_active_voltage_target(val as f32);
-                    }
+                        // 設定連線狀態；初始化 (下面 0~4) 是第一個背景工作，之後的輪詢 / 遙測都排在它後面
+                        if let Err(e) = sp.attach(p) {
+                            ui.set_status_text("Disconnected".into());
+                            ui.set_status_color(Color::from_rgb_u8(255, 0, 0).into());
+                            ui.set_message_text(e.to_string().into());
+                            return;
+                        }
+                        *active.borrow_mut() = profile.clone();
+                        let max_power = cfg_connect.borrow().max_power_w;
+                        let (ui_done, cfg_done, session) = (ui.as_weak(), cfg_connect.clone(), poll.session.clone());
+                        let port_done = port_name.to_string();
+                        sp.run(move |p| {
+                            // 0. 進入遠端模式，不然有些儀器會默默忽略設定指令
+                            profile.enter_remote(p);
+                            // 0b. Profile 自訂的 on-connect 指令
+                            let failed = profile.run_on_connect(p);
+                            // 0c. 儀器內建的過功率保護跟著軟體的上限走
+                            if !profile.power_prot_cmd.is_empty() && max_power > 0.0 {
+                                let _ = scpi::send_command(p, &format!("{} {}", profile.power_prot_cmd, scpi::format_number(max_power)));
+                            }
+                            // 1~4. 讀回 IDN / Output / 設定值
+                            let state = query_instrument(p, &profile);
+                            (profile, failed, state)
+                        }, move |res| {
+                            let (Some(ui), Ok((profile, failed, state))) = (ui_done.upgrade(), res) else { return; };
+                            if !failed.is_empty() {
+                                ui.set_message_text(format!("On-connect command failed: {}", failed.join(", ")).into());
+                            }
+                            let idn = resync_from_instrument(&ui, &profile, state);
+                            session.borrow_mut().identity = idn.clone().unwrap_or_default();
 
-                    // 4. 同步設定電流 (Set Current Limit)
-                    if let Some(c_str) = scpi::send_command(&mut p, scpi::cmds::GET_SET_CURR) {
-                        let val: f64 = c_str.trim().parse().unwrap_or(0.0);
-                        // 更新輸入框 (給人看)
-                        ui.set_target_current(format!("{:.3}", val).into());
-                        // 🟢 [新增] 更新生效值 (給邏輯用)
-                        ui.set_active_current_limit(val as f32);
-                    }
+                            // 記住這台儀器 (快速連線列)
+                            let Some(idn) = idn else { return; };
+                            let mut c = cfg_done.borrow_mut();
+                            c.remember_device(config::RecentDevice { idn, port: port_done, profile: profile.name });
+                            if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+                            ui.set_recent_devices(recent_labels(&c));
+                        });
+                        ui.set_status_text("Connected".into());
+                        ui.set_status_color(Color::from_rgb_u8(0, 128, 0).into()); 
+                        start_telemetry(ui.as_weak(), sp.clone(), &t_telemetry, active.clone());
 
-                    // 5. 設定連線狀態
-                    *sp_connect.borrow_mut() = Some(p); 
-                    ui.set_status_text("Connected".into());
-                    ui.set_status_color(Color::from_rgb_u8(0, 128, 0).into()); 
+                        // 記住這個 Port (下次啟動可以自動連線)
+                        let mut c = cfg_connect.borrow_mut();
+                        c.last_port = port_name.to_string();
+                        c.baud_rate = baud;
+                        c.framing = framing.to_string();
+                        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+                        drop(c);
 
-                    if ui.get_enable_auto_refresh() {
-                        trigger_auto_poll(ui.as_weak(), sp_connect.clone(), monitor_timer_ref.clone());
+                        if ui.get_enable_auto_refresh() {
+                            trigger_auto_poll(ui.as_weak(), sp.clone(), monitor.clone(), poll.clone());
+                        }
+                    },
+                    Err(e) if port_in_use(&e, port_name.as_str()) => {
+                        ui.set_status_text("Port is in use by another application".into());
+                        ui.set_message_text(format!("{} is open in another program (terminal, vendor software, another copy of this app). Close it and try again.", port_name).into());
                     }
-                },
-                Err(e) => ui.set_status_text(format!("Err: {}", e).into()),
-            }
+                    Err(e) => ui.set_status_text(format!("Err: {}", e).into()),
+                }
+            });
         }
     });
 
     // --- 4. Auto Refresh 切換 ---
     let sp_refresh = shared_port.clone();
     let timer_refresh = monitor_timer.clone();
+    let poll_refresh = poll_state.clone();
     let ui_refresh = ui.as_weak();
     ui.on_toggle_auto_refresh(move |enabled| {
         let ui = ui_refresh.unwrap();
         if ui.get_status_text() == "Connected" {
             if enabled {
-                trigger_auto_poll(ui_refresh.clone(), sp_refresh.clone(), timer_refresh.clone());
+                trigger_auto_poll(ui_refresh.clone(), sp_refresh.clone(), timer_refresh.clone(), poll_refresh.clone());
             } else {
                 timer_refresh.borrow().stop();
             }
         }
     });
 
+    // --- 4b. 斷線後自動重連 (次數 0 = 不重連，只顯示 "Connection lost") ---
+    let reconnect_timer = Rc::new(Timer::default());
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    ui.on_connection_lost(move |message| {
+        let u = ui_h.unwrap();
+        if u.get_status_text() == "Connected" { u.invoke_toggle_connection(); }
+        let max = cfg.borrow().reconnect_attempts.min(MAX_RECONNECT_ATTEMPTS);
+        if max == 0 {
+            u.set_status_text("Connection lost".into());
+            u.set_message_text(format!("{}. Check the cable and press Connect.", message).into());
+            return;
+        }
+        u.set_status_text(format!("Reconnecting… (0/{})", max).into());
+        u.set_status_color(Color::from_rgb_u8(255, 165, 0).into());
+        u.set_message_text(format!("{}. Reconnecting…", message).into());
+
+        let (ui_h, cfg, timer) = (ui_h.clone(), cfg.clone(), Rc::downgrade(&reconnect_timer));
+        let (mut attempt, mut in_flight) = (0, false);
+        reconnect_timer.start(TimerMode::Repeated, RECONNECT_INTERVAL, move || {
+            let Some(u) = ui_h.upgrade() else { return; };
+            let stop = || if let Some(t) = timer.upgrade() { t.stop(); };
+            // 開 Port 在背景做：上一次嘗試還沒有結果就等下一輪
+            if in_flight {
+                if u.get_status_text() == CONNECTING { return; }
+                in_flight = false;
+                if reconnect_result(&u, attempt, max) { return stop(); }
+            }
+            attempt += 1;
+            in_flight = true;
+            if reconnect_attempt(&u, &cfg) { stop(); }
+        });
+    });
+
     // --- 5. 通用 SCPI 通訊 Closure ---
+    // 只排進背景執行緒就返回，結果由 `done` 在事件迴圈上處理
     let sp_io = shared_port.clone();
-    let io_scpi = move |cmd: &str| -> Option<String> {
-        let mut port_ref = sp_io.borrow_mut();
-        if let Some(ref mut p) = *port_ref {
-            scpi::send_command(p, cmd)
-        } else {
-            None
-        }
+    let io_scpi = move |cmd: &str, done: IoDone| {
+        let cmd = cmd.to_string();
+        sp_io.run(move |p| scpi::send_command(p, &cmd), move |res| done(res.and_then(|r| r)));
     };
 
     // --- 6. 綁定 UI Callbacks ---
 
     let io = io_scpi.clone();
-    ui.on_send_command(move |cmd_str| { io(cmd_str.as_str()); });
+    let ui_h = ui.as_weak();
+    let active_cmd = active_profile.clone();
+    let sp_cmd = shared_port.clone();
+    ui.on_send_command(move |cmd_str| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        // 手動輸入的指令先過 Profile 的阻擋規則
+        if let Err(reason) = active_cmd.borrow().policy.check(&cmd_str) {
+            u.set_message_text(reason.into());
+            return;
+        }
+        io(cmd_str.as_str(), report_apply(&u, &sp_cmd, &active_cmd, false));
+    });
 
     // 設定電壓 Apply
     let io = io_scpi.clone();
     let ui_handle_v = ui.as_weak(); // 需要 handle
+    let (sp_v, prof_v) = (shared_port.clone(), active_profile.clone());
     ui.on_apply_voltage(move |v| { 
-        io(&format!("{} {}", scpi::cmds::SET_VOLT, v)); 
+        let u = ui_handle_v.unwrap();
+        if ui_locked(&u) { return; }
+        // 統一數字格式 ("12,5" / "1e1" 之類的先整理好，儀器才不會拒收)
+        let Some(v) = scpi::normalize_number(&v) else {
+            u.set_message_text(format!("Invalid voltage: {}", v).into());
+            return;
+        };
+        io(&format!("{} {}", scpi::cmds::SET_VOLT, v), report_apply(&u, &sp_v, &prof_v, true));
         // 🟢 [新增] 同步生效值
         let val: f32 = v.parse().unwrap_or(0.0);
-        ui_handle_v.unwrap().set_active_voltage_target(val);
+        u.set_active_voltage_target(val);
     });
 
     // 設定電流 Apply
     let io = io_scpi.clone();
     let ui_handle_c = ui.as_weak(); // 需要 handle
+    let (sp_c, prof_c) = (shared_port.clone(), active_profile.clone());
     ui.on_apply_current(move |c| { 
-        io(&format!("{} {}", scpi::cmds::SET_CURR, c)); 
+        let u = ui_handle_c.unwrap();
+        if ui_locked(&u) { return; }
+        let Some(c) = scpi::normalize_number(&c) else {
+            u.set_message_text(format!("Invalid current: {}", c).into());
+            return;
+        };
+        io(&format!("{} {}", scpi::cmds::SET_CURR, c), report_apply(&u, &sp_c, &prof_c, true));
         // 🟢 [新增] 同步生效值
         let val: f32 = c.parse().unwrap_or(0.0);
-        ui_handle_c.unwrap().set_active_current_limit(val);
+        u.set_active_current_limit(val);
     });
 
-    let io = io_scpi.clone();
+    // 手動讀值：點擊只排進佇列馬上返回，連點時同一個查詢只會讀一次
+    let read_queue: ReadQueue = Rc::new(RefCell::new(Vec::new()));
+    let (ui_h, sp_read, queue, cal) = (ui.as_weak(), shared_port.clone(), read_queue.clone(), calibration.clone());
+    ui.on_read_voltage(move || queue_manual_read(ui_h.clone(), sp_read.clone(), queue.clone(), cal.clone(), scpi::cmds::READ_VOLT));
+    let (ui_h, sp_read, queue, cal) = (ui.as_weak(), shared_port.clone(), read_queue.clone(), calibration.clone());
+    ui.on_read_current(move || queue_manual_read(ui_h.clone(), sp_read.clone(), queue.clone(), cal.clone(), scpi::cmds::READ_CURR));
+
+    // --- 標記：時間戳 + 註記，畫在圖上、寫進錄製中的 CSV 與 HTTP API ---
+    let (hist, cap, readings_marker, ui_h) = (history.clone(), capture_state.clone(), readings.clone(), ui.as_weak());
+    ui.on_add_marker(move |note| {
+        let u = ui_h.unwrap();
+        let mut h = hist.borrow_mut();
+        let note = match note.trim() {
+            "" => format!("Marker {}", h.markers.len() + 1),
+            n => n.to_string(),
+        };
+        let (t, ts) = (Instant::now(), chrono::Local::now());
+        h.add_marker(t, &note);
+        if let Ok(mut r) = readings_marker.lock() {
+            r.add_marker(http_api::Marker { ts: ts.format(logger::TS_FORMAT).to_string(), note: note.clone() });
+        }
+        let logged = match cap.borrow_mut().note(ts, &format!("MARKER: {}", note)) {
+            Ok(true) => " (logged to capture)",
+            Ok(false) => "",
+            Err(e) => { u.set_message_text(format!("Capture Error: {}", e).into()); "" }
+        };
+        u.set_marker_status(format!("{} @ {}{}", note, ts.format("%H:%M:%S"), logged).into());
+        u.set_marker_note("".into());
+    });
+
+    // --- 重播：把記錄的 CSV 依原本的時間戳送進趨勢圖 (0.5× / 1× / 2× 或逐筆) ---
+    let replay_state: Rc<RefCell<Option<replay::Replay>>> = Rc::new(RefCell::new(None));
+    let replay_timer = Rc::new(Timer::default());
+
+    let (rs, ui_h, rf, gt) = (replay_state.clone(), ui.as_weak(), relative_ref.clone(), golden_trace.clone());
+    let rt = replay_timer.clone();
+    ui.on_replay_load(move |path| {
+        let u = ui_h.unwrap();
+        rt.stop();
+        let loaded = logger::read_samples(std::path::Path::new(path.trim())).map(replay::Replay::new);
+        match loaded {
+            Ok(Some(mut r)) => {
+                r.speed = replay::SPEEDS[(u.get_replay_speed_index().max(0) as usize).min(replay::SPEEDS.len() - 1)];
+                u.set_replay_loaded(true);
+                show_replay_frame(&u, &r, &rf, &gt);
+                u.set_replay_info(format!("{} samples, {:.1} s", r.len(), r.duration()).into());
+                *rs.borrow_mut() = Some(r);
+            }
+            Ok(None) => u.set_replay_info("Load Error: no measurement rows".into()),
+            Err(e) => u.set_replay_info(format!("Load Error: {}", e).into()),
+        }
+    });
+
+    let (rs, ui_h, rf, gt) = (replay_state.clone(), ui.as_weak(), relative_ref.clone(), golden_trace.clone());
+    let rt = replay_timer.clone();
+    ui.on_replay_toggle(move || {
+        let u = ui_h.unwrap();
+        let mut guard = rs.borrow_mut();
+        let Some(r) = guard.as_mut() else { return; };
+        if r.playing {
+            r.playing = false;
+            rt.stop();
+            u.set_replay_playing(false);
+            return;
+        }
+        r.play();
+        show_replay_frame(&u, r, &rf, &gt);
+        // 每次 tick 用實際經過的時間推進，Timer 有延遲也不會越播越慢
+        let (rs, ui_w, rf, gt, timer_weak) = (rs.clone(), ui_h.clone(), rf.clone(), gt.clone(), Rc::downgrade(&rt));
+        let mut last = Instant::now();
+        rt.start(TimerMode::Repeated, Duration::from_millis(50), move || {
+            let Some(u) = ui_w.upgrade() else { return; };
+            let now = Instant::now();
+            let dt = now - last;
+            last = now;
+            let mut guard = rs.borrow_mut();
+            let Some(r) = guard.as_mut() else { return; };
+            if r.tick(dt) || !r.playing { show_replay_frame(&u, r, &rf, &gt); }
+            if !r.playing {
+                if let Some(t) = timer_weak.upgrade() { t.stop(); }
+            }
+        });
+    });
+
+    let (rs, ui_h, rf, gt) = (replay_state.clone(), ui.as_weak(), relative_ref.clone(), golden_trace.clone());
+    let rt = replay_timer.clone();
+    ui.on_replay_step(move |n| {
+        let mut guard = rs.borrow_mut();
+        let Some(r) = guard.as_mut() else { return; };
+        rt.stop();
+        r.step(n as isize);
+        show_replay_frame(&ui_h.unwrap(), r, &rf, &gt);
+    });
+
+    let (rs, ui_h, rf, gt) = (replay_state.clone(), ui.as_weak(), relative_ref.clone(), golden_trace.clone());
+    ui.on_replay_seek(move |frac| {
+        let mut guard = rs.borrow_mut();
+        let Some(r) = guard.as_mut() else { return; };
+        r.seek(frac);
+        show_replay_frame(&ui_h.unwrap(), r, &rf, &gt);
+    });
+
+    let rs = replay_state.clone();
+    ui.on_replay_speed(move |idx| {
+        if let Some(r) = rs.borrow_mut().as_mut() {
+            r.speed = replay::SPEEDS[(idx.max(0) as usize).min(replay::SPEEDS.len() - 1)];
+        }
+    });
+
+    // 關掉重播：下一次輪詢就會畫回即時資料
+    let (rs, ui_h, rt) = (replay_state.clone(), ui.as_weak(), replay_timer.clone());
+    ui.on_replay_close(move || {
+        let u = ui_h.unwrap();
+        rt.stop();
+        *rs.borrow_mut() = None;
+        u.set_replay_loaded(false);
+        u.set_replay_playing(false);
+        u.set_replay_status("".into());
+        u.set_replay_info("".into());
+    });
+
+    // --- 參考曲線：匯入之前記錄的 CSV，疊在即時曲線後面 ---
+    let golden = golden_trace.clone();
     let ui_h = ui.as_weak();
-    ui.on_read_voltage(move || {
-        if let Some(val) = io(scpi::cmds::READ_VOLT) { ui_h.unwrap().set_voltage_reading(val.into()); }
+    ui.on_load_ref_trace(move |path| {
+        let u = ui_h.unwrap();
+        match logger::read_trace(std::path::Path::new(path.trim())) {
+            Ok((v, i)) => {
+                u.set_ref_trace_status(format!("Reference: {} samples", v.len()).into());
+                *golden.borrow_mut() = Some((chart::resample(&v, CHART_WIDTH), chart::resample(&i, CHART_WIDTH)));
+            }
+            Err(e) => u.set_ref_trace_status(format!("Load Error: {}", e).into()),
+        }
     });
 
-    let io = io_scpi.clone();
+    let golden = golden_trace.clone();
     let ui_h = ui.as_weak();
-    ui.on_read_current(move || {
-        if let Some(val) = io(scpi::cmds::READ_CURR) { ui_h.unwrap().set_current_reading(val.into()); }
+    ui.on_clear_ref_trace(move || {
+        let u = ui_h.unwrap();
+        *golden.borrow_mut() = None;
+        u.set_chart_ref_v("".into());
+        u.set_chart_ref_i("".into());
+        u.set_ref_trace_status("".into());
     });
 
-    let io = io_scpi.clone();
-    ui.on_confirm_reset(move || { io(scpi::cmds::RESET); });
+    // --- 分離出來的大圖 (完整歷史 + 縮放/平移) ---
+    let chart_window = ChartWindow::new()?;
+    chart_window.set_theme_mode(ui.get_theme_mode());
+    let chart_view = Rc::new(RefCell::new(history::View::default()));
+    let chart_window_timer = Rc::new(Timer::default());
+    // 上次重畫時的歷史長度，用來在不跟隨即時資料時把畫面固定住
+    let seen_len = Rc::new(RefCell::new(0usize));
+
+    let redraw = {
+        let win = chart_window.as_weak();
+        let hist = history.clone();
+        let view = chart_view.clone();
+        let seen = seen_len.clone();
+        Rc::new(move || {
+            let Some(win) = win.upgrade() else { return; };
+            let hist = hist.borrow();
+            let mut view = view.borrow_mut();
+            let mut seen = seen.borrow_mut();
+            if win.get_follow_live() {
+                view.end_back = 0;
+            } else {
+                view.end_back += hist.len().saturating_sub(*seen);
+            }
+            *seen = hist.len();
+            refresh_chart_window(&win, &hist, &view);
+        })
+    };
+
+    let r = redraw.clone();
+    let view = chart_view.clone();
+    let hist = history.clone();
+    chart_window.on_zoom(move |factor, anchor| {
+        view.borrow_mut().zoom(factor, anchor, hist.borrow().len());
+        r();
+    });
+
+    let r = redraw.clone();
+    let view = chart_view.clone();
+    let hist = history.clone();
+    let win = chart_window.as_weak();
+    chart_window.on_pan(move |frac| {
+        view.borrow_mut().pan(frac, hist.borrow().len());
+        // 往回看舊資料時就不再跟著最新資料跑
+        if view.borrow().end_back > 0 { win.unwrap().set_follow_live(false); }
+        r();
+    });
+
+    let r = redraw.clone();
+    let view = chart_view.clone();
+    let win = chart_window.as_weak();
+    chart_window.on_reset_view(move || {
+        *view.borrow_mut() = history::View::default();
+        win.unwrap().set_follow_live(true);
+        r();
+    });
+
+    let t = chart_window_timer.clone();
+    chart_window.window().on_close_requested(move || {
+        t.stop();
+        slint::CloseRequestResponse::HideWindow
+    });
+
+    let r = redraw.clone();
+    let win = chart_window.as_weak();
+    let t = chart_window_timer.clone();
+    ui.on_detach_chart(move || {
+        r();
+        let r = r.clone();
+        t.start(TimerMode::Repeated, Duration::from_millis(500), move || r());
+        if let Err(e) = win.unwrap().show() { eprintln!("Chart Window Error: {}", e); }
+    });
+
+    // --- 迷你監看視窗：訂閱量測，只顯示讀值 ---
+    let mini_monitor = MiniMonitor::new()?;
+    mini_monitor.set_theme_mode(ui.get_theme_mode());
+    let mini = mini_monitor.as_weak();
+    observers.borrow_mut().subscribe(move |m: &measurement::Measurement| {
+        let Some(w) = mini.upgrade() else { return; };
+        w.set_voltage(scpi::format_with_unit(m.v as f64, "V").into());
+        w.set_current(scpi::format_with_unit(m.i as f64, "A").into());
+        w.set_power(format!("{:.2} W", m.p).into());
+        w.set_mode(m.mode.as_str().into());
+        w.set_updated(m.ts.format("%H:%M:%S").to_string().into());
+    });
+
+    let ui_h = ui.as_weak();
+    mini_monitor.window().on_close_requested(move || {
+        ui_h.unwrap().set_mini_monitor_open(false);
+        slint::CloseRequestResponse::HideWindow
+    });
+
+    let ui_h = ui.as_weak();
+    let mini = mini_monitor.as_weak();
+    ui.on_toggle_mini_monitor(move || {
+        let u = ui_h.unwrap();
+        let w = mini.unwrap();
+        let res = if u.get_mini_monitor_open() { w.hide() } else { w.show() };
+        match res {
+            Ok(()) => u.set_mini_monitor_open(!u.get_mini_monitor_open()),
+            Err(e) => eprintln!("Mini Monitor Error: {}", e),
+        }
+    });
+
+    // --- 配色：每個視窗有自己的 Theme global，要一起切換 ---
+    let cfg = app_config.clone();
+    let chart_win = chart_window.as_weak();
+    let mini = mini_monitor.as_weak();
+    ui.on_theme_changed(move |mode| {
+        // 主視窗的 Theme.mode 已經被 ComboBox 改掉了
+        if let Some(w) = chart_win.upgrade() { w.set_theme_mode(mode.clone()); }
+        if let Some(w) = mini.upgrade() { w.set_theme_mode(mode.clone()); }
+        let mut c = cfg.borrow_mut();
+        c.theme = mode.to_string();
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+    });
+
+    // --- 相對模式：把最近一次讀值記成參考點 ---
+    let rel = relative_ref.clone();
+    let ui_h = ui.as_weak();
+    ui.on_set_reference(move || {
+        let u = ui_h.unwrap();
+        let mut r = rel.borrow_mut();
+        match r.last_raw {
+            Some((v, i)) => {
+                r.reference = Some((v, i));
+                u.set_reference_text(format!("Ref: {:.4} V / {:.4} A", v, i).into());
+            }
+            None => u.set_message_text("No reading yet: enable Auto-Poll first".into()),
+        }
+    });
+
+    // *RST 後重新讀回設定，並列出被重置的項目
+    let sp_reset = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_reset = active_profile.clone();
+    ui.on_confirm_reset(move || {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let profile = active_reset.borrow().clone();
+        let ui_weak = ui_h.clone();
+        sp_reset.run(move |p| {
+            let reset = scpi::send_command(p, scpi::cmds::RESET);
+            // 重設還沒做完就讀，會讀到垃圾或舊值 (CC/CV 判斷跟著誤判)
+            let opc = scpi::wait_operation_complete(p, RESET_OPC_TIMEOUT);
+            let state = query_instrument(p, &profile);
+            (reset, opc, profile, state)
+        }, move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            let (reset, opc, profile, state) = match res {
+                Ok(r) => r,
+                Err(e) => { u.set_message_text(e.to_string().into()); return; }
+            };
+            report_result(&u, reset);
+            if let Err(e) = opc {
+                u.set_message_text(format!("*RST not confirmed by *OPC?: {}", e).into());
+            }
+            resync_with_diff(&u, &profile, state, "After *RST");
+        });
+    });
 
     let ui_h = ui.as_weak();
     ui.on_adjust_voltage(move |step| {
         let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
         let val: f64 = u.get_target_voltage().parse().unwrap_or(0.0);
         u.set_target_voltage(format!("{:.2}", (val + step as f64).max(0.0)).into());
     });
@@ -207,6 +869,7 @@ fn main() -> Result<(), anyhow::Error> {
     let ui_h = ui.as_weak();
     ui.on_adjust_current(move |step| {
         let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
         let val: f64 = u.get_target_current().parse().unwrap_or(0.0);
         u.set_target_current(format!("{:.3}", (val + step as f64).max(0.0)).into());
     });
@@ -223,169 +886,2814 @@ fn main() -> Result<(), anyhow::Error> {
             t_loop.borrow().stop();
             u.set_is_looping(false);
         } else {
+            // 鎖定中只能停、不能開始
+            if ui_locked(&u) || automation_busy(&u) { return; }
+            let (Some(v1), Some(v2)) = (scpi::normalize_number(&va), scpi::normalize_number(&vb)) else {
+                u.set_message_text("Invalid loop voltage".into());
+                return;
+            };
             u.set_is_looping(true);
             let sp = sp_loop.clone();
             let state = s_loop.clone();
-            let v1 = va.to_string();
-            let v2 = vb.to_string();
 
+            let in_flight = Rc::new(Cell::new(false));
+            let ui_loop = ui_h.clone();
             t_loop.borrow().start(TimerMode::Repeated, Duration::from_millis(interval as u64), move || {
-                let mut curr_state = state.borrow_mut();
-                *curr_state = !*curr_state;
-                let target_v = if *curr_state { &v1 } else { &v2 };
-                if let Some(ref mut p) = *sp.borrow_mut() {
-                    let _ = scpi::send_command(p, &format!("{} {}", scpi::cmds::SET_VOLT, target_v));
-                }
+                // 上一個電壓還在排隊 / 送出中：這一拍不再疊一個上去 (狀態還沒翻轉，下一拍照順序送)
+                if in_flight.get() { return; }
+                let next = !*state.borrow();
+                let target_v = if next { &v1 } else { &v2 };
+                let cmd = format!("{} {}", scpi::cmds::SET_VOLT, target_v);
+                in_flight.set(true);
+                let (state, in_flight, ui_weak) = (state.clone(), in_flight.clone(), ui_loop.clone());
+                sp.run(move |p| scpi::send_command(p, &cmd), move |res| {
+                    in_flight.set(false);
+                    match res.and_then(|r| r) {
+                        Ok(_) => *state.borrow_mut() = next,
+                        Err(e) => if let Some(u) = ui_weak.upgrade() { report_result(&u, Err(e)); },
+                    }
+                });
             });
         }
     });
 
-    // 🔴 [已刪除] 這裡原本有一段 "7. 圖表資料處理" 的重複程式碼，已移除。
-    // 圖表更新已經整合進底部的 trigger_auto_poll 函式，並透過上方的 callbacks 呼叫。
-
-    ui.run()?;
-    Ok(())
-}
+    // 緊急斷電：停止循環並立即關閉輸出 (Esc 快捷鍵也會呼叫這裡)
+    let sp_emergency = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let timers_emergency = device_timers.clone();
+    ui.on_emergency_off(move || {
+        let u = ui_h.unwrap();
+        // 循環、斜坡、掃描、順序一律停掉 (輪詢保留，才看得到輸出真的關了)
+        stop_all_timers(&u, &timers_emergency, false);
+        send_urgent(ui_h.clone(), &sp_emergency, scpi::cmds::OUTP_OFF);
+        u.set_is_output_on(false);
+    });
 
-// 🟢 [新增] 一個輔助函式，用來把數值陣列轉成 SVG Path 字串
-// 參數: buffer (數據), width (圖寬), height (圖高)
-fn generate_svg_path(buffer: &VecDeque<f32>, width: f32, height: f32) -> String {
-    if buffer.is_empty() { return String::new(); }
+    // 停止所有自動化：循環、輪詢、遙測、上電順序、I-V 掃描；連線與輸出維持原狀
+    // (Auto-Off 屬於安全機制，不在此列)
+    let ui_h = ui.as_weak();
+    let t_loop = loop_timer.clone();
+    let t_poll = monitor_timer.clone();
+    let t_seq = sequence_timer.clone();
+    let t_tele = telemetry_timer.clone();
+    ui.on_stop_all(move || {
+        let u = ui_h.unwrap();
+        t_loop.borrow().stop();
+        u.set_is_looping(false);
+        t_poll.borrow().stop();
+        u.set_enable_auto_refresh(false);
+        t_tele.stop();
+        if u.get_sequence_running() {
+            t_seq.borrow().stop();
+            u.set_sequence_running(false);
+            u.set_sequence_status("Sequence stopped".into());
+        }
+        // 掃描的下一步會看到 iv-running = false，自己還原電壓設定
+        u.set_iv_running(false);
+    });
 
-    // 1. 找出最大值做 Auto-Scale (防止除以 0，且給一點頂部空間)
-    // 技巧: 如果最大值很小(例如 0V)，強制設為 1.0，避免線條亂飛
-    let max_val = buffer.iter().fold(0.0f32, |a, &b| a.max(b)).max(1.0) * 1.1; 
-    
-    let mut path_cmd = String::with_capacity(1024);
-    use std::fmt::Write;
+    // --- Output 開關 (含 Auto-Off 倒數、關閉前的降壓斜坡) ---
+    let ui_h = ui.as_weak();
+    let sp_off = shared_port.clone();
+    let t_off = auto_off_timer.clone();
+    let t_tick = auto_off_ticker.clone();
+    let prof_out = active_profile.clone();
+    let ramp_timer = ramp_timer.clone();
+    ui.on_set_output(move |on| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
 
-    for (i, &val) in buffer.iter().enumerate() {
-        let x = (i as f32 / (buffer.len() - 1) as f32) * width;
-        // Y 軸反轉 (Slint 0 在上面)
-        let y = height - (val / max_val * height); 
-        
-        if i == 0 {
-            let _ = write!(path_cmd, "M {:.1} {:.1} ", x, y);
-        } else {
-            let _ = write!(path_cmd, "L {:.1} {:.1} ", x, y);
+        // 斜坡進行中再按一次 = 不等了，立刻關；否則有勾 Ramp-down 就先把電壓慢慢降到 0
+        // (其他自動化還在寫設定值時不做斜坡，直接關，免得兩邊搶電壓)
+        if u.get_ramp_down_running() {
+            u.set_ramp_down_running(false);
+        } else if !on && u.get_is_output_on() && u.get_ramp_down_enabled() && u.get_active_automation().is_empty() {
+            let seconds = scpi::parse_number(&u.get_ramp_down_seconds()).unwrap_or(0.0);
+            if seconds > 0.0 {
+                cancel_auto_off(&u, &t_off.borrow(), &t_tick.borrow());
+                let n = ((seconds * 1000.0 / RAMP_STEP_INTERVAL.as_millis() as f64).ceil() as usize).max(1);
+                // 第一點就是目前的設定值，不用再送
+                let setpoints = sweep_setpoints(u.get_active_voltage_target() as f64, 0.0, n + 1).split_off(1);
+                u.set_ramp_down_running(true);
+                ramp_down_step(ui_h.clone(), sp_off.clone(), ramp_timer.clone(), Rc::new(setpoints), 0);
+                return;
+            }
         }
-    }
-    path_cmd
-}
+        let cmd = if on { scpi::cmds::OUTP_ON } else { scpi::cmds::OUTP_OFF };
+        let profile = prof_out.borrow().clone();
+        let (ui_weak, off_timer, off_ticker) = (ui_h.clone(), t_off.clone(), t_tick.clone());
+        sp_off.run(move |p| {
+            // 開輸出前即時讀安全連鎖，和 OUTP ON 在同一個背景工作裡，中間不會被別的工作插隊
+            if on && interlock_open_now(p, &profile) { return None; }
+            let res = scpi::send_command(p, cmd);
+            // 等繼電器切完，下一筆輪詢才不會讀到切換前的值
+            if res.is_ok() { let _ = scpi::wait_operation_complete(p, OUTPUT_OPC_TIMEOUT); }
+            Some(res)
+        }, move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            match res {
+                Ok(None) => {
+                    report_interlock_blocked(&u);
+                    u.set_is_output_on(false);
+                    cancel_auto_off(&u, &off_timer.borrow(), &off_ticker.borrow());
+                }
+                Ok(Some(res)) => { report_result(&u, res); }
+                Err(e) => { report_result(&u, Err(e)); }
+            }
+        });
+        u.set_is_output_on(on);
+        if on { u.set_setpoint_while_off(false); }
 
-// 🟢 [修改] 主邏輯函式
-fn trigger_auto_poll(ui_weak: slint::Weak<AppWindow>, sp: Rc<RefCell<Option<Box<dyn SerialPort>>>>, timer: Rc<RefCell<Timer>>) {
-    // 1. 初始化歷史資料 Buffer
-    const CHART_WIDTH: usize = 100; // 這是我們固定的採樣點數
-    let mut history_v = VecDeque::with_capacity(CHART_WIDTH);
-    let mut history_i = VecDeque::with_capacity(CHART_WIDTH);
-    for _ in 0..CHART_WIDTH { 
-        history_v.push_back(0.0f32); 
-        history_i.push_back(0.0f32); 
-    }
+        // 手動關閉時取消倒數；開啟且有勾 Auto-Off 時重新開始倒數
+        cancel_auto_off(&u, &t_off.borrow(), &t_tick.borrow());
+        if on && u.get_auto_off_enabled() {
+            let seconds = u.get_auto_off_seconds().trim().parse::<u64>().unwrap_or(0);
+            if seconds > 0 {
+                start_auto_off(ui_h.clone(), sp_off.clone(), &t_off.borrow(), t_tick.clone(), seconds);
+            }
+        }
+    });
 
-    // 2. 讀取時間並限制最小間隔 (避免過快導致塞車)
-    let ui = ui_weak.unwrap(); 
-    let raw_interval = ui.get_polling_interval().parse::<u64>().unwrap_or(100);
-    // 強制設定最小 200ms (RS232 物理極限保護)
-    let interval_ms = raw_interval.max(200); 
+    // --- Presets：一次套用電壓 + 電流 ---
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    ui.on_recall_preset(move |idx| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let Some(p) = cfg.borrow().presets.get(idx as usize).cloned() else { return; };
+        let v = format!("{:.2}", p.voltage);
+        let c = format!("{:.3}", p.current);
+        u.set_target_voltage(v.clone().into());
+        u.set_target_current(c.clone().into());
+        // 走原本的 Apply 流程，確保生效值也同步
+        u.invoke_apply_voltage(v.into());
+        u.invoke_apply_current(c.into());
+    });
 
-    if raw_interval < 200 {
-        ui.set_polling_interval(format!("{}", interval_ms).into());
-    }
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    let model = preset_model.clone();
+    ui.on_save_preset(move |name| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let name = name.trim().to_string();
+        if name.is_empty() { return; }
+        let voltage: f64 = u.get_target_voltage().parse().unwrap_or(0.0);
+        let current: f64 = u.get_target_current().parse().unwrap_or(0.0);
 
-    // 更新圖表時間標籤
-    let total_seconds = (interval_ms as f64 * CHART_WIDTH as f64) / 1000.0;
-    ui.set_chart_duration(format!("{:.1}s", total_seconds).into());
+        let mut c = cfg.borrow_mut();
+        // 同名就覆寫 (編輯)，否則新增
+        match c.presets.iter_mut().find(|p| p.name == name) {
+            Some(p) => { p.voltage = voltage; p.current = current; }
+            None => c.presets.push(config::Preset::new(&name, voltage, current)),
+        }
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        refresh_preset_names(&model, &c);
+        u.set_preset_name_input("".into());
+    });
 
-    // 🟢 [修改] 使用變數 interval_ms
-    timer.borrow().start(TimerMode::Repeated, Duration::from_millis(interval_ms), move || {
-        let ui = ui_weak.unwrap();
-        let mut port_ref = sp.borrow_mut();
-        
-        // 暫存目前的數值
-        let mut curr_v = 0.0f32;
-        let mut curr_i = 0.0f32;
-        let mut read_success = false;
+    let cfg = app_config.clone();
+    let model = preset_model.clone();
+    let ui_h = ui.as_weak();
+    ui.on_delete_preset(move |idx| {
+        if ui_locked(&ui_h.unwrap()) { return; }
+        let mut c = cfg.borrow_mut();
+        if (idx as usize) < c.presets.len() {
+            c.presets.remove(idx as usize);
+            if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+            refresh_preset_names(&model, &c);
+        }
+    });
 
-        // --- A. SCPI 通訊 ---
-        if let Some(ref mut p) = *port_ref {
-            
-            if let Some(raw_res) = scpi::send_command(p, scpi::cmds::READ_ALL) {
-                let clean_str = raw_res.replace("«", "").trim().to_string();
-                let parts: Vec<&str> = clean_str.split(',').collect();
-                
-                if parts.len() >= 2 {
-                    let v_str = parts[0].trim();
-                    let i_str = parts[1].trim();
-                    
-                    // 1. 更新文字 UI (只有讀成功才更新文字)
-                    ui.set_voltage_reading(v_str.into());
-                    ui.set_current_reading(i_str.into());
-                    
-                    // 2. 解析數值
-                    curr_v = v_str.parse().unwrap_or(0.0);
-                    curr_i = i_str.parse().unwrap_or(0.0);
-
-                    // 3. 更新功率 UI
-                    let power = curr_v * curr_i;
-                    ui.set_power_reading(format!("{:.2}", power).into());
-
-                    // 🟢 [修正] CC/CV 智能判斷邏輯
-                    // 1. 取得生效的電流上限 (Active Limit)
-                    let i_limit_active = ui.get_active_current_limit() as f32;
-                    
-                    // 2. 判斷是否 Output ON (沒開電就不顯示模式)
-                    let output_on = ui.get_is_output_on();
-
-                    // 3. 判斷 CC (電流接近上限 95% 且大於 10mA 避免雜訊)
-                    let is_cc = (curr_i - i_limit_active).abs() < (i_limit_active * 0.05) && curr_i > 0.01;
-
-                    let mode = if !output_on {
-                        "" // 沒開電，燈號熄滅
-                    } else if is_cc {
-                        "CC" // 限流模式
-                    } else {
-                        "CV" // 定壓模式
-                    };
-                    ui.set_psu_mode(mode.into());
-
-                    // 🟢 [重點修改 2] 標記讀取成功
-                    read_success = true;
+    // --- Profile 選擇 (下次連線生效，並記住選擇) ---
+    // 自動偵測鮑率：(先斷線) 逐一試常見速率，找到 *IDN? 回應乾淨的就用它重新連線
+    let ui_h = ui.as_weak();
+    let profiles_baud = profiles.clone();
+    let sp_baud = shared_port.clone();
+    ui.on_auto_baud(move || {
+        let u = ui_h.unwrap();
+        if u.get_status_text() == CONNECTING { return; }
+        let port_name = u.get_selected_port();
+        if port_name.as_str() == NO_PORTS_LABEL || port_name.is_empty() { return; }
+        if u.get_status_text() == "Connected" { u.invoke_toggle_connection(); }
+        let profile = profiles_baud.borrow().iter().find(|p| p.name == u.get_selected_profile().as_str()).cloned().unwrap_or_default();
+        let framing = scpi::Framing::parse(&u.get_framing()).unwrap_or_default();
+        // 每個速率都要開 Port、等開機、等 *IDN? (最久十幾秒)，整個在背景做；
+        // 上面斷線的收尾 (ABOR / 回本地模式) 還占著 Port，run_detached 會排在它後面 (Linux 開 Port 是獨占的)
+        u.set_status_text(CONNECTING.into());
+        u.set_status_color(Color::from_rgb_u8(255, 165, 0).into());
+        u.set_message_text("Auto-baud: trying common rates…".into());
+        let (name, ui_done) = (port_name.to_string(), u.as_weak());
+        sp_baud.run_detached(move || scan_baud(&name, framing, &profile), move |res| {
+            let Some(u) = ui_done.upgrade() else { return; };
+            u.set_status_text("Disconnected".into());
+            u.set_status_color(Color::from_rgb_u8(255, 0, 0).into());
+            match res.ok().flatten() {
+                Some((baud, idn)) => {
+                    u.set_baud_rate(baud.to_string().into());
+                    u.set_message_text(format!("Auto-baud: {} baud ({})", baud, idn).into());
+                    u.invoke_toggle_connection();
                 }
+                None => u.set_message_text("Auto-baud: no common rate gave a clean *IDN? reply. Check the cable and the instrument's interface settings.".into()),
+            }
+        });
+    });
+
+    // 指令 / 回應的行結尾：不用改 Profile，這次連線直接生效
+    let sp_term = shared_port.clone();
+    let ui_h = ui.as_weak();
+    ui.on_terminators_changed(move || {
+        let (tx, rx) = terminators(&ui_h.unwrap());
+        sp_term.post(move |p| {
+            p.opts.write_terminator = tx;
+            p.opts.read_terminator = rx;
+        });
+    });
+
+    // 連線中切換：立即套用新的連線參數並重新同步，列出差異
+    let cfg = app_config.clone();
+    let ui_h = ui.as_weak();
+    let sp_profile = shared_port.clone();
+    let profiles_select = profiles.clone();
+    let active_select = active_profile.clone();
+    ui.on_profile_selected(move |name| {
+        let mut c = cfg.borrow_mut();
+        c.profile = name.to_string();
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+
+        let profile = profiles_select.borrow().iter().find(|x| x.name == name.as_str()).cloned().unwrap_or_default();
+        let (write_terminator, read_terminator) = terminators(&ui_h.unwrap());
+        ui_h.unwrap().set_on_connect_cmds(profile.on_connect_commands.join("; ").into());
+        let (ui_weak, active) = (ui_h.clone(), active_select.clone());
+        sp_profile.run(move |p| {
+            // 行結尾與讀取超時是 UI 的設定，換 Profile 也保留
+            p.opts = scpi::LinkOptions { write_terminator, read_terminator, read_timeout: p.opts.read_timeout, ..profile.link_options() };
+            profile.enter_remote(p);
+            let failed = profile.run_on_connect(p);
+            let state = query_instrument(p, &profile);
+            (profile, failed, state)
+        }, move |res| {
+            // 沒連線時不用做什麼，下次連線會套用
+            let (Some(u), Ok((profile, failed, state))) = (ui_weak.upgrade(), res) else { return; };
+            resync_with_diff(&u, &profile, state, &format!("After switching to \"{}\"", name));
+            if !failed.is_empty() {
+                u.set_message_text(format!("On-connect command failed: {}", failed.join(", ")).into());
             }
+            *active.borrow_mut() = profile;
+        });
+    });
+
+    // 編輯目前 Profile 的 on-connect 指令 (用 ';' 分隔)，存成使用者 Profile，下次連線生效
+    let ui_h = ui.as_weak();
+    let profiles_edit = profiles.clone();
+    let active_edit = active_profile.clone();
+    ui.on_save_on_connect(move |text| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let commands: Vec<String> = text.split(';').map(str::trim).filter(|c| !c.is_empty()).map(String::from).collect();
+        let mut all = profiles_edit.borrow_mut();
+        let Some(profile) = all.iter_mut().find(|x| x.name == u.get_selected_profile().as_str()) else { return; };
+        profile.on_connect_commands = commands;
+        let mut active = active_edit.borrow_mut();
+        if active.name == profile.name { active.on_connect_commands = profile.on_connect_commands.clone(); }
+        match profile::save_user(profile) {
+            Ok(()) => u.set_message_text(format!("Saved {} on-connect command(s) to \"{}\"", profile.on_connect_commands.len(), profile.name).into()),
+            Err(e) => u.set_message_text(format!("Profile Save Error: {}", e).into()),
         }
+        u.set_on_connect_cmds(profile.on_connect_commands.join("; ").into());
+    });
 
-        // --- B. 圖表更新邏輯 ---
-        
-        // 🟢 [重點修改 3] 如果讀取失敗 (塞車或超時)，使用「上一次的值」填補
-        // 這樣圖表會變成「水平線」繼續往左跑，而不會掉到 0，也不會因為沒 push 導致不同步
-        if !read_success {
-             // 拿 Buffer 最後一筆資料，如果 Buffer 是空的就用 0.0
-             curr_v = *history_v.back().unwrap_or(&0.0);
-             curr_i = *history_i.back().unwrap_or(&0.0);
+    // --- Profile 編輯器：以目前選的 Profile 為底，存檔後切換過去 ---
+    let editor_base = Rc::new(RefCell::new(profile::DeviceProfile::default()));
+    let ui_h = ui.as_weak();
+    let profiles_editor = profiles.clone();
+    let base = editor_base.clone();
+    ui.on_open_profile_editor(move || {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let profile = profiles_editor.borrow().iter().find(|x| x.name == u.get_selected_profile().as_str()).cloned().unwrap_or_default();
+        let rows: Vec<ProfileField> = profile.edit_fields().into_iter().map(|f| ProfileField {
+            key: f.key.into(),
+            value: f.value.into(),
+            kind: field_kind_name(f.kind).into(),
+        }).collect();
+        *base.borrow_mut() = profile;
+        u.set_profile_fields(Rc::new(VecModel::from(rows)).into());
+        u.set_profile_editor_status("".into());
+        u.set_show_profile_editor(true);
+    });
+
+    let ui_h = ui.as_weak();
+    ui.on_profile_field_edited(move |i, value| {
+        let fields = ui_h.unwrap().get_profile_fields();
+        if let Some(mut row) = fields.row_data(i as usize) {
+            row.value = value;
+            fields.set_row_data(i as usize, row);
+        }
+    });
+
+    let ui_h = ui.as_weak();
+    let profiles_editor = profiles.clone();
+    let base = editor_base.clone();
+    ui.on_save_profile_edit(move || {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let fields: Vec<profile::EditField> = u.get_profile_fields().iter().map(|f| profile::EditField {
+            kind: field_kind(&f.kind),
+            key: f.key.into(),
+            value: f.value.into(),
+        }).collect();
+        let edited = match base.borrow().from_edit_fields(&fields).and_then(|p| p.validate().map(|()| p)) {
+            Ok(p) => p,
+            Err(e) => { u.set_profile_editor_status(e.into()); return; }
+        };
+        if let Err(e) = profile::save_user(&edited) {
+            u.set_profile_editor_status(format!("Profile Save Error: {}", e).into());
+            return;
         }
+        let name = edited.name.clone();
+        {
+            let mut all = profiles_editor.borrow_mut();
+            match all.iter_mut().find(|x| x.name == name) {
+                Some(existing) => *existing = edited,
+                None => all.push(edited),
+            }
+            let names: Vec<SharedString> = all.iter().map(|p| p.name.as_str().into()).collect();
+            u.set_profile_names(Rc::new(VecModel::from(names)).into());
+        }
+        u.set_show_profile_editor(false);
+        // 跟從下拉選單選一樣：存設定、連線中就馬上套用
+        u.set_selected_profile(name.as_str().into());
+        u.invoke_profile_selected(name.as_str().into());
+        u.set_message_text(format!("Saved profile \"{}\"", name).into());
+    });
 
-        // 🟢 [重點修改 4] 無條件推進 Buffer (保證 V 和 I 永遠同步)
-        // 不管 read_success 是 true 還是 false，這裡都要執行
-        
-        // 更新 V
-        history_v.pop_front();
-        history_v.push_back(curr_v);
-        
-        // 更新 I
-        history_i.pop_front();
-        history_i.push_back(curr_i);
+    // --- 觸發擷取：設定並 ARM ---
+    let cap = capture_state.clone();
+    let ui_h = ui.as_weak();
+    ui.on_arm_trigger(move |src, edge, level, pre| {
+        let u = ui_h.unwrap();
+        let Ok(level) = level.trim().parse::<f32>() else {
+            u.set_trigger_status("Invalid trigger level".into());
+            return;
+        };
+        let cfg = capture::TriggerConfig {
+            source: if src == "Current" { capture::Source::Current } else { capture::Source::Voltage },
+            edge: if edge == "Falling" { capture::Edge::Falling } else { capture::Edge::Rising },
+            level,
+            pre_samples: pre.trim().parse().unwrap_or(0),
+            rotation: rotation_from_ui(&u),
+        };
+        *cap.borrow_mut() = capture::Capture::Armed(cfg);
+        u.set_trigger_armed(true);
+        u.set_trigger_status(format!("Armed: {:?} {:?} @ {}", cfg.source, cfg.edge, level).into());
+    });
+
+    let cap = capture_state.clone();
+    let ui_h = ui.as_weak();
+    ui.on_disarm_trigger(move || {
+        let u = ui_h.unwrap();
+        cap.borrow_mut().stop();
+        u.set_trigger_armed(false);
+        u.set_trigger_status("Idle".into());
+    });
 
-        // 3. 生成 SVG (重複利用 generate_svg_path 函式)
-        let chart_h = 120.0; // 對應 UI 高度
-        let chart_w = 750.0; // 對應 UI 寬度
+    // 連續 CSV 記錄 (跟擷取共用換檔設定)
+    let sink = csv_sink.clone();
+    let ui_h = ui.as_weak();
+    ui.on_toggle_csv_log(move || {
+        let u = ui_h.unwrap();
+        let mut s = sink.borrow_mut();
+        // 看按鈕狀態而不是 Sink：寫入失敗自己停掉時，按一次 Stop 就回到可以重新開始
+        if u.get_csv_log_running() {
+            s.stop();
+            u.set_csv_log_status("Logging stopped".into());
+        } else {
+            match s.start(rotation_from_ui(&u)) {
+                Ok(path) => u.set_csv_log_status(format!("Logging to {}", path.display()).into()),
+                Err(e) => u.set_csv_log_status(format!("Log Error: {}", e).into()),
+            }
+        }
+        u.set_csv_log_running(s.is_running());
+    });
+
+    // 摘要報告：寫成 CSV 檔，或填進 summary-text 讓 UI 複製到剪貼簿
+    let sess = session.clone();
+    let ui_h = ui.as_weak();
+    ui.on_refresh_summary(move || {
+        ui_h.unwrap().set_summary_text(sess.borrow().to_csv().into());
+    });
 
-        let path_v_str = generate_svg_path(&history_v, chart_w, chart_h);
-        let path_i_str = generate_svg_path(&history_i, chart_w, chart_h);
+    let sess = session.clone();
+    let ui_h = ui.as_weak();
+    ui.on_export_summary(move || {
+        let u = ui_h.unwrap();
+        let path = logger::timestamped_path("summary");
+        match std::fs::write(&path, sess.borrow().to_csv()) {
+            Ok(()) => u.set_summary_status(format!("Exported: {}", path.display()).into()),
+            Err(e) => u.set_summary_status(format!("Export Error: {}", e).into()),
+        }
+    });
 
-        // 4. 更新 UI
-        ui.set_chart_data_v(path_v_str.into());
-        ui.set_chart_data_i(path_i_str.into());
+    let cap = capture_state.clone();
+    let ui_h = ui.as_weak();
+    ui.on_stop_capture(move || {
+        let u = ui_h.unwrap();
+        cap.borrow_mut().stop();
+        u.set_capture_running(false);
+        u.set_trigger_status("Capture stopped".into());
     });
+
+    // --- 輸出開啟延遲 (單台儀器，需 Profile 支援) ---
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    let active_delay = active_profile.clone();
+    ui.on_apply_output_delay(move |secs| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let cmd = active_delay.borrow().output_delay_cmd.clone();
+        if cmd.is_empty() { return; }
+        let Some(secs) = scpi::normalize_number(&secs) else {
+            u.set_message_text("Invalid delay".into());
+            return;
+        };
+        io(&format!("{} {}", cmd, secs), report_to(&u));
+    });
+
+    // --- 散熱：風扇模式 / 過溫保護 (Profile 有定義指令才會顯示) ---
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    let active_fan = active_profile.clone();
+    ui.on_apply_fan_mode(move |mode| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let cmd = active_fan.borrow().fan_mode_cmd.clone();
+        if cmd.is_empty() { return; }
+        io(&format!("{} {}", cmd, mode), report_to(&u));
+    });
+
+    // --- 過功率保護：軟體每次輪詢檢查，儀器支援的話也設定內建的 POW:PROT ---
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    let active_opp = active_profile.clone();
+    let cfg = app_config.clone();
+    ui.on_apply_power_limit(move |text| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let Some(watts) = scpi::parse_number(text.trim()).filter(|w| *w >= 0.0) else {
+            u.set_message_text("Invalid power limit".into());
+            return;
+        };
+        u.set_power_limit(watts as f32);
+        u.set_max_power(scpi::format_number(watts).into());
+        let mut c = cfg.borrow_mut();
+        c.max_power_w = watts;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        drop(c);
+        let cmd = active_opp.borrow().power_prot_cmd.clone();
+        // 0 = 關掉軟體檢查；儀器內建的保護就維持原本的設定
+        if !cmd.is_empty() && watts > 0.0 {
+            io(&format!("{} {}", cmd, scpi::format_number(watts)), report_to(&u));
+        }
+    });
+
+    // --- 通道耦合：先關掉其他模式再開選定的 (Independent = 全部關掉) ---
+    let sp_coupling = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_coupling = active_profile.clone();
+    ui.on_apply_coupling(move |mode| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let profile = active_coupling.borrow();
+        let modes = profile.coupling_modes();
+        let off = modes.iter().filter(|(name, _)| *name != mode.as_str()).map(|(_, cmd)| format!("{} OFF", cmd));
+        let on = modes.iter().filter(|(name, _)| *name == mode.as_str()).map(|(_, cmd)| format!("{} ON", cmd));
+        let cmds: Vec<String> = off.chain(on).collect();
+        // 中途失敗就停下，不要留在「全部關掉」之外的半套狀態還繼續送
+        let done = report_to(&u);
+        sp_coupling.run(move |p| {
+            cmds.iter().try_for_each(|cmd| scpi::send_command(p, cmd).map(drop)).map(|_| None)
+        }, move |res| done(res.and_then(|r| r)));
+    });
+
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    let active_otp = active_profile.clone();
+    ui.on_apply_otp(move |celsius| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let cmd = active_otp.borrow().otp_cmd.clone();
+        if cmd.is_empty() { return; }
+        let Some(celsius) = scpi::normalize_number(&celsius) else {
+            u.set_message_text("Invalid temperature".into());
+            return;
+        };
+        io(&format!("{} {}", cmd, celsius), report_to(&u));
+    });
+
+    // --- 儀器在本地模式：重新送出遠端指令 ---
+    let sp_remote = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_remote = active_profile.clone();
+    ui.on_reassert_remote(move || {
+        let profile = active_remote.borrow().clone();
+        let ui_weak = ui_h.clone();
+        sp_remote.run(move |p| {
+            profile.enter_remote(p);
+            profile.is_local(p)
+        }, move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            match res {
+                Ok(local) => u.set_device_local(local.unwrap_or(false)),
+                Err(e) => u.set_message_text(e.to_string().into()),
+            }
+        });
+    });
+
+    // --- 標準事件狀態 (*ESR?)：讀取會順便清掉暫存器 ---
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    ui.on_read_esr(move || {
+        let ui_weak = ui_h.clone();
+        io(scpi::cmds::ESR, Box::new(move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            let Some(resp) = report_result(&u, res) else { return; };
+            let text = match scpi::decode_esr(&resp) {
+                Some(bits) if bits.is_empty() => format!("{} — no events", resp.trim()),
+                Some(bits) => format!("{} — {}", resp.trim(), bits.join(", ")),
+                None => format!("Unexpected reply: {}", resp.trim()),
+            };
+            u.set_esr_text(text.into());
+        }));
+    });
+
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    ui.on_clear_status(move || {
+        let ui_weak = ui_h.clone();
+        io(scpi::cmds::CLS, Box::new(move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            if res.is_ok() { u.set_esr_text("Cleared (*CLS)".into()); }
+            report_result(&u, res);
+        }));
+    });
+
+    // --- 儀器時鐘：設成 PC 時間後重新量時間差 ---
+    let sp_clock = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_clock = active_profile.clone();
+    ui.on_sync_clock(move || {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let profile = active_clock.borrow().clone();
+        if !profile.has_clock() { return; }
+        let ui_weak = ui_h.clone();
+        sp_clock.run(move |p| {
+            for cmd in clock::set_commands(&profile.date_cmd, &profile.time_cmd, chrono::Local::now()) {
+                scpi::send_command(p, &cmd)?;
+            }
+            Ok((query_instrument_clock(p, &profile), profile))
+        }, move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            match res.and_then(|r| r) {
+                Ok((reading, profile)) => {
+                    report_result(&u, Ok(None));
+                    show_instrument_clock(&u, &profile, reading);
+                }
+                Err(e) => { report_result(&u, Err(e)); }
+            }
+        });
+    });
+
+    // --- 儀器端觸發：arm → (*TRG / 外部觸發) → fetch；armed 期間輪詢暫停 ---
+    let sp_trig = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_trig = active_profile.clone();
+    ui.on_arm_inst_trigger(move |source| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let profile = active_trig.borrow().clone();
+        if !profile.has_trigger() { return; }
+        let ui_weak = ui_h.clone();
+        let src = source.to_string();
+        sp_trig.run(move |p| profile.arm_trigger(p, &src), move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            match res.and_then(|r| r) {
+                Ok(()) => {
+                    u.set_inst_trigger_armed(true);
+                    u.set_inst_trigger_status(format!("Armed, waiting for {} trigger", source).into());
+                }
+                Err(e) => u.set_inst_trigger_status(format!("Arm failed: {}", e).into()),
+            }
+        });
+    });
+
+    let sp_trig = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_trig = active_profile.clone();
+    ui.on_abort_inst_trigger(move || {
+        let u = ui_h.unwrap();
+        let cmd = active_trig.borrow().abort_cmd.clone();
+        if !cmd.is_empty() {
+            let ui_weak = ui_h.clone();
+            sp_trig.run(move |p| scpi::send_command(p, &cmd), move |res| {
+                if let Some(u) = ui_weak.upgrade() { report_result(&u, res.and_then(|r| r)); }
+            });
+        }
+        u.set_inst_trigger_armed(false);
+        u.set_inst_trigger_status("Aborted".into());
+    });
+
+    let sp_trig = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_trig = active_profile.clone();
+    ui.on_send_inst_trigger(move || {
+        let cmd = active_trig.borrow().trigger_cmd.clone();
+        if cmd.is_empty() { return; }
+        let (ui_weak, sp, active) = (ui_h.clone(), sp_trig.clone(), active_trig.clone());
+        sp_trig.run(move |p| scpi::send_command(p, &cmd), move |res| {
+            let Some(u) = ui_weak.upgrade() else { return; };
+            match res.and_then(|r| r) {
+                // 軟體觸發完馬上讀回
+                Ok(_) => fetch_inst_trigger(&u, &sp, &active.borrow()),
+                Err(e) => u.set_inst_trigger_status(format!("Trigger failed: {}", e).into()),
+            }
+        });
+    });
+
+    let sp_trig = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let active_trig = active_profile.clone();
+    ui.on_fetch_inst_trigger(move || {
+        let u = ui_h.unwrap();
+        fetch_inst_trigger(&u, &sp_trig, &active_trig.borrow());
+    });
+
+    // --- 電流量測檔位 (Profile 有定義指令才會顯示) ---
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    let active_range = active_profile.clone();
+    ui.on_apply_current_range(move |range| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let cmd = active_range.borrow().current_range_cmd.clone();
+        if cmd.is_empty() { return; }
+        io(&format!("{} {}", cmd, range), report_to(&u));
+    });
+
+    // --- 讀值校正 (gain × raw + offset) ---
+    let ui_h = ui.as_weak();
+    let cal = calibration.clone();
+    let cfg = app_config.clone();
+    ui.on_apply_calibration(move || {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let num = |s: SharedString| scpi::parse_number(&s);
+        let (Some(vg), Some(vo), Some(ig), Some(io)) =
+            (num(u.get_cal_v_gain()), num(u.get_cal_v_offset()), num(u.get_cal_i_gain()), num(u.get_cal_i_offset())) else {
+            u.set_cal_status("Invalid calibration value".into());
+            return;
+        };
+        if vg == 0.0 || ig == 0.0 {
+            u.set_cal_status("Gain can't be 0".into());
+            return;
+        }
+        let new = calibration::Calibration {
+            voltage: calibration::Correction { gain: vg, offset: vo },
+            current: calibration::Correction { gain: ig, offset: io },
+            log_raw: u.get_cal_log_raw(),
+        };
+        *cal.borrow_mut() = new;
+        let mut c = cfg.borrow_mut();
+        c.calibration = new;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        show_calibration(&u, &new);
+    });
+
+    let ui_h = ui.as_weak();
+    ui.on_reset_calibration(move || {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let log_raw = u.get_cal_log_raw();
+        show_calibration(&u, &calibration::Calibration { log_raw, ..Default::default() });
+        u.invoke_apply_calibration();
+    });
+
+    // 兩點校正：各記一次儀器讀值與標準表讀值，算出 gain / offset
+    let ui_h = ui.as_weak();
+    ui.on_use_raw_reading(move |point| {
+        let u = ui_h.unwrap();
+        let raw = if u.get_cal_quantity() == "Current" { u.get_last_raw_i() } else { u.get_last_raw_v() };
+        let text: SharedString = scpi::format_number(raw as f64).into();
+        if point == 1 { u.set_cal_raw1(text); } else { u.set_cal_raw2(text); }
+    });
+
+    let ui_h = ui.as_weak();
+    ui.on_compute_two_point(move || {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let num = |s: SharedString| scpi::parse_number(&s);
+        let (Some(r1), Some(m1), Some(r2), Some(m2)) =
+            (num(u.get_cal_raw1()), num(u.get_cal_ref1()), num(u.get_cal_raw2()), num(u.get_cal_ref2())) else {
+            u.set_cal_status("Enter both points (reading and reference)".into());
+            return;
+        };
+        let Some(c) = calibration::Correction::two_point(r1, m1, r2, m2) else {
+            u.set_cal_status("The two readings must differ".into());
+            return;
+        };
+        let (gain, offset) = (format!("{:.6}", c.gain).into(), format!("{:.6}", c.offset).into());
+        if u.get_cal_quantity() == "Current" {
+            u.set_cal_i_gain(gain);
+            u.set_cal_i_offset(offset);
+        } else {
+            u.set_cal_v_gain(gain);
+            u.set_cal_v_offset(offset);
+        }
+        u.invoke_apply_calibration();
+    });
+
+    // --- 量測積分時間 NPLC (Profile 有定義指令才會顯示) ---
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    let active_nplc = active_profile.clone();
+    ui.on_apply_nplc(move |text| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        let profile = active_nplc.borrow();
+        if profile.nplc_cmd.is_empty() { return; }
+        let Some(nplc) = scpi::parse_number(&text).filter(|n| *n > 0.0) else {
+            u.set_message_text("Invalid NPLC (must be a positive number)".into());
+            return;
+        };
+        io(&format!("{} {}", profile.nplc_cmd, scpi::format_number(nplc)), report_to(&u));
+        u.set_nplc_hint(nplc_hint(nplc, profile.line_freq_hz, &u.get_polling_interval()).into());
+    });
+
+    // --- 通道上電/斷電順序 ---
+    let ui_h = ui.as_weak();
+    let sp_seq = shared_port.clone();
+    let t_seq = sequence_timer.clone();
+    let cfg = app_config.clone();
+    let prof_seq = active_profile.clone();
+    ui.on_power_up_sequence(move |text| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        if automation_busy(&u) { return; }
+        let Some(steps) = parse_sequence(&text) else {
+            u.set_sequence_status("Invalid sequence (use CH:delay_ms, e.g. 1:0, 2:100)".into());
+            return;
+        };
+        {
+            let mut c = cfg.borrow_mut();
+            c.power_sequence = text.to_string();
+            if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        }
+        u.set_sequence_running(true);
+        let profile = Rc::new(prof_seq.borrow().clone());
+        schedule_sequence_step(ui_h.clone(), sp_seq.clone(), t_seq.clone(), Rc::new(steps), 0, true, profile);
+    });
+
+    let ui_h = ui.as_weak();
+    let sp_seq = shared_port.clone();
+    let t_seq = sequence_timer.clone();
+    let prof_seq = active_profile.clone();
+    ui.on_power_down_sequence(move |text| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) || automation_busy(&u) { return; }
+        let Some(steps) = parse_sequence(&text) else {
+            u.set_sequence_status("Invalid sequence (use CH:delay_ms, e.g. 1:0, 2:100)".into());
+            return;
+        };
+        u.set_sequence_running(true);
+        let profile = Rc::new(prof_seq.borrow().clone());
+        schedule_sequence_step(ui_h.clone(), sp_seq.clone(), t_seq.clone(), Rc::new(reverse_sequence(&steps)), 0, false, profile);
+    });
+
+    // --- 多通道：切換手動控制的通道 / 效率量測設定 ---
+    let io = io_scpi.clone();
+    let ui_h = ui.as_weak();
+    ui.on_select_channel(move |ch| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) { return; }
+        io(&format!("{} {}", scpi::cmds::SELECT_CHANNEL, ch), report_to(&u));
+    });
+
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    ui.on_efficiency_config_changed(move || {
+        let u = ui_h.unwrap();
+        let mut c = cfg.borrow_mut();
+        c.efficiency_enabled = u.get_efficiency_enabled();
+        c.efficiency_in_ch = u.get_efficiency_in_ch();
+        c.efficiency_out_ch = u.get_efficiency_out_ch();
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        if !c.efficiency_enabled {
+            u.set_power_in_reading("---".into());
+            u.set_power_out_reading("---".into());
+            u.set_efficiency_reading("---".into());
+        }
+    });
+
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    let overlay = poll_state.overlay.clone();
+    ui.on_overlay_config_changed(move || {
+        let u = ui_h.unwrap();
+        let mut c = cfg.borrow_mut();
+        c.overlay_enabled = u.get_overlay_enabled();
+        c.overlay_channels = u.get_overlay_channels().to_string();
+        c.overlay_quantity = u.get_overlay_quantity().to_string();
+        c.overlay_shared_scale = u.get_overlay_shared_scale();
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        // 換了共用 / 各自刻度時馬上重畫，不用等下一筆
+        if c.overlay_enabled { render_overlay(&u, &overlay.borrow()); }
+    });
+
+    // --- I-V 曲線掃描 ---
+    let iv_points: IvPoints = Rc::new(RefCell::new(Vec::new()));
+
+    let sp_iv = shared_port.clone();
+    let t_iv = iv_timer.clone();
+    let pts = iv_points.clone();
+    let ui_h = ui.as_weak();
+    ui.on_run_iv_sweep(move |v_start, v_stop, steps, settle_ms| {
+        let u = ui_h.unwrap();
+        if ui_locked(&u) || automation_busy(&u) { return; }
+        let (Some(start), Some(stop)) = (scpi::parse_number(&v_start), scpi::parse_number(&v_stop)) else {
+            u.set_iv_status("Invalid start/stop voltage".into());
+            return;
+        };
+        if steps < 2 || settle_ms < 0 {
+            u.set_iv_status("Need at least 2 steps and a non-negative settle time".into());
+            return;
+        }
+        let sweep = Rc::new(IvSweep {
+            setpoints: sweep_setpoints(start, stop, steps as usize),
+            settle: Duration::from_millis(settle_ms as u64),
+        });
+        pts.borrow_mut().clear();
+        u.set_iv_curve("".into());
+        u.set_iv_knee("".into());
+        u.set_iv_knee_text("".into());
+        u.set_iv_running(true);
+        iv_sweep_step(ui_h.clone(), sp_iv.clone(), t_iv.clone(), sweep, pts.clone(), 0);
+    });
+
+    // 停止：下一步開始前會看到 iv-running = false 並還原設定
+    let ui_h = ui.as_weak();
+    ui.on_stop_iv_sweep(move || ui_h.unwrap().set_iv_running(false));
+
+    let pts = iv_points.clone();
+    let ui_h = ui.as_weak();
+    ui.on_export_iv_curve(move || {
+        let u = ui_h.unwrap();
+        let path = logger::timestamped_path("iv_curve");
+        match logger::write_iv_curve(&path, &pts.borrow()) {
+            Ok(()) => u.set_iv_status(format!("Exported: {}", path.display()).into()),
+            Err(e) => u.set_iv_status(format!("Export Error: {}", e).into()),
+        }
+    });
+
+    // --- 通訊除錯紀錄 ---
+    let sp_trace = shared_port.clone();
+    let ui_h = ui.as_weak();
+    ui.on_trace_config_changed(move || {
+        let u = ui_h.unwrap();
+        let (enabled, preserve_raw) = (u.get_trace_enabled(), u.get_trace_preserve_raw());
+        sp_trace.post(move |p| {
+            p.trace.enabled = enabled;
+            p.trace.preserve_raw = preserve_raw;
+        });
+    });
+
+    // 交易紀錄檔：每筆指令的原始回應與耗時，寫檔在背景執行緒 (約 5 MB 換檔)
+    let sp_txlog = shared_port.clone();
+    let ui_h = ui.as_weak();
+    let log_state = tx_log.clone();
+    ui.on_txlog_toggled(move |on| {
+        let u = ui_h.unwrap();
+        let log = if !on {
+            u.set_txlog_status("".into());
+            None
+        } else {
+            match txlog::TxLog::start(std::path::Path::new(txlog::DEFAULT_PATH)) {
+                Ok(log) => {
+                    u.set_txlog_status(format!("Logging to {}", log.path().display()).into());
+                    Some(log)
+                }
+                Err(e) => {
+                    u.set_txlog_enabled(false);
+                    u.set_txlog_status(format!("Log Error: {}", e).into());
+                    None
+                }
+            }
+        };
+        *log_state.borrow_mut() = log.clone();
+        sp_txlog.post(move |p| p.txlog = log);
+    });
+
+    let sp_trace = shared_port.clone();
+    let ui_h = ui.as_weak();
+    ui.on_clear_trace(move || {
+        sp_trace.post(|p| p.trace.lines.clear());
+        ui_h.unwrap().set_trace_text("".into());
+    });
+
+    // 紀錄畫面每 500ms 更新一次 (有開才更新；序列埠忙碌就等下一次)
+    let trace_timer = Timer::default();
+    let sp_trace = shared_port.clone();
+    let ui_h = ui.as_weak();
+    trace_timer.start(TimerMode::Repeated, Duration::from_millis(500), move || {
+        let Some(u) = ui_h.upgrade() else { return; };
+        if !u.get_trace_enabled() { return; }
+        if let Ok(text) = with_port(&sp_trace, |p| p.trace.lines.iter().cloned().collect::<Vec<_>>().join("\n")) {
+            u.set_trace_text(text.into());
+        }
+    });
+
+    // --- 啟動時自動連線 ---
+    let cfg = app_config.clone();
+    ui.on_auto_connect_toggled(move |on| {
+        let mut c = cfg.borrow_mut();
+        c.auto_connect = on;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+    });
+
+    let cfg = app_config.clone();
+    ui.on_completion_beep_toggled(move |on| {
+        let mut c = cfg.borrow_mut();
+        c.completion_beep = on;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+    });
+
+    let cfg = app_config.clone();
+    let ui_h = ui.as_weak();
+    let sp_settle = shared_port.clone();
+    ui.on_settle_time_changed(move |text| {
+        let u = ui_h.unwrap();
+        let Ok(ms) = text.trim().parse::<u64>() else {
+            u.set_message_text("Settle time must be a whole number of milliseconds".into());
+            return;
+        };
+        let mut c = cfg.borrow_mut();
+        c.settle_ms = ms;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        // 連線中直接生效
+        sp_settle.post(move |p| p.settle = Duration::from_millis(ms));
+    });
+
+    let cfg = app_config.clone();
+    let ui_h = ui.as_weak();
+    ui.on_reconnect_attempts_changed(move |text| {
+        let u = ui_h.unwrap();
+        let Some(n) = text.trim().parse::<u32>().ok().filter(|&n| n <= MAX_RECONNECT_ATTEMPTS) else {
+            u.set_message_text(format!("Reconnect attempts must be a whole number from 0 to {}", MAX_RECONNECT_ATTEMPTS).into());
+            return;
+        };
+        let mut c = cfg.borrow_mut();
+        c.reconnect_attempts = n;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+    });
+
+    let cfg = app_config.clone();
+    let ui_h = ui.as_weak();
+    ui.on_poll_retries_changed(move |text| {
+        let u = ui_h.unwrap();
+        let Some(n) = text.trim().parse::<u32>().ok().filter(|&n| n <= MAX_POLL_RETRIES) else {
+            u.set_message_text(format!("Poll retries must be a whole number from 0 to {}", MAX_POLL_RETRIES).into());
+            return;
+        };
+        let mut c = cfg.borrow_mut();
+        c.poll_retries = n;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+    });
+
+    let cfg = app_config.clone();
+    let ui_h = ui.as_weak();
+    let sp_timeout = shared_port.clone();
+    ui.on_read_timeout_changed(move |text| {
+        let u = ui_h.unwrap();
+        let Some(ms) = text.trim().parse::<u64>().ok().filter(|&ms| ms > 0) else {
+            u.set_message_text("Read timeout must be a positive number of milliseconds".into());
+            return;
+        };
+        let mut c = cfg.borrow_mut();
+        c.read_timeout_ms = ms;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        // 連線中直接生效 (Port 本身的超時也跟著調，每次 read 會再依剩餘時間縮短)
+        sp_timeout.post(move |p| {
+            p.opts.read_timeout = Duration::from_millis(ms);
+            let _ = p.port.set_timeout(Duration::from_millis(ms));
+        });
+    });
+
+    // --- HTTP/JSON 讀值 API (唯讀，給實驗室儀表板抓資料) ---
+    let http_server: Rc<RefCell<Option<http_api::Server>>> = Rc::new(RefCell::new(None));
+    let apply_http = {
+        let server = http_server.clone();
+        let readings = readings.clone();
+        let ui_h = ui.as_weak();
+        move |enabled: bool, port: u16| {
+            let u = ui_h.unwrap();
+            if let Some(old) = server.borrow_mut().take() { old.stop(); }
+            if !enabled {
+                u.set_http_status("".into());
+                return;
+            }
+            match http_api::Server::start(port, readings.clone()) {
+                Ok(s) => {
+                    *server.borrow_mut() = Some(s);
+                    u.set_http_status(format!("Serving on :{} (/readings, /history)", port).into());
+                }
+                Err(e) => u.set_http_status(format!("HTTP Error: {}", e).into()),
+            }
+        }
+    };
+
+    let cfg = app_config.clone();
+    let apply = apply_http.clone();
+    let ui_h = ui.as_weak();
+    ui.on_http_config_changed(move |enabled, port| {
+        let Ok(port) = port.trim().parse::<u16>() else {
+            ui_h.unwrap().set_http_status("Invalid port".into());
+            return;
+        };
+        {
+            let mut c = cfg.borrow_mut();
+            c.http_enabled = enabled;
+            c.http_port = port;
+            if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        }
+        apply(enabled, port);
+    });
+    {
+        let c = app_config.borrow();
+        if c.http_enabled { apply_http(true, c.http_port); }
+    }
+
+    // --- Kiosk 鎖定：只留監看，解鎖要輸入 PIN ---
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    ui.on_lock_ui(move |pin| {
+        let u = ui_h.unwrap();
+        let mut c = cfg.borrow_mut();
+        // 有輸入就當成新的 PIN；沒輸入就沿用之前設定的 (設定檔只存加鹽雜湊)
+        if !pin.trim().is_empty() { c.lock_pin_hash = config::hash_pin(pin.trim()); }
+        if c.lock_pin_hash.is_empty() {
+            u.set_message_text("Set a PIN before locking the UI".into());
+            return;
+        }
+        c.ui_locked = true;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        u.set_ui_locked(true);
+        u.set_show_settings(false);
+        u.set_message_text("".into());
+    });
+
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    ui.on_unlock_ui(move |pin| {
+        let u = ui_h.unwrap();
+        let mut c = cfg.borrow_mut();
+        if !config::verify_pin(&c.lock_pin_hash, pin.trim()) {
+            u.set_message_text("Wrong PIN".into());
+            return;
+        }
+        c.ui_locked = false;
+        if let Err(e) = config::save(&c) { eprintln!("Config Save Error: {}", e); }
+        u.set_ui_locked(false);
+        u.set_message_text("".into());
+    });
+
+    // --- 快速連線：套用該儀器上次的 Port / Profile 後連線 ---
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    let ports = port_list.clone();
+    ui.on_connect_recent(move |idx| {
+        let u = ui_h.unwrap();
+        if u.get_status_text() == "Connected" { return; }
+        let Some(dev) = cfg.borrow().recent.get(idx as usize).cloned() else { return; };
+        if !ports.contains(&dev.port) {
+            u.set_message_text(format!("Port {} is not available", dev.port).into());
+            return;
+        }
+        select_port(&u, &dev.port);
+        if u.get_profile_names().iter().any(|n| n.as_str() == dev.profile) {
+            u.set_selected_profile(dev.profile.as_str().into());
+            u.invoke_profile_selected(dev.profile.as_str().into());
+        }
+        u.invoke_toggle_connection();
+    });
+
+    // 上次的 Port 還在才連，不在就安靜略過 (不跳錯誤訊息)
+    let (auto_connect, last_port) = {
+        let c = app_config.borrow();
+        (c.auto_connect, c.last_port.clone())
+    };
+    if auto_connect && port_list.contains(&last_port) {
+        select_port(&ui, &last_port);
+        ui.invoke_toggle_connection();
+    }
+
+    // 🔴 [已刪除] 這裡原本有一段 "7. 圖表資料處理" 的重複程式碼，已移除。
+    // 圖表更新已經整合進底部的 trigger_auto_poll 函式，並透過上方的 callbacks 呼叫。
+
+    // --- 視窗位置 / 大小：啟動時還原，關閉時記下來 ---
+    if let Some(g) = app_config.borrow().window {
+        ui.window().set_position(slint::PhysicalPosition::new(g.x, g.y));
+        ui.window().set_size(slint::PhysicalSize::new(g.width, g.height));
+    }
+    let ui_h = ui.as_weak();
+    let cfg = app_config.clone();
+    let chart_win = chart_window.as_weak();
+    let mini = mini_monitor.as_weak();
+    ui.window().on_close_requested(move || {
+        csv_sink.borrow_mut().stop();
+        if let Some(u) = ui_h.upgrade() {
+            stop_all_timers(&u, &device_timers, true);
+            let pos = u.window().position();
+            let size = u.window().size();
+            let mut c = cfg.borrow_mut();
+            c.window = Some(config::WindowGeometry { x: pos.x, y: pos.y, width: size.width, height: size.height });
+            let _ = config::save(&c);
+        }
+        // 主視窗關掉就整個結束：圖表視窗 / 迷你監看還開著的話一起關，不然程式會留在背景
+        chart_window_timer.stop();
+        if let Some(w) = chart_win.upgrade() { let _ = w.hide(); }
+        if let Some(w) = mini.upgrade() { let _ = w.hide(); }
+        let _ = slint::quit_event_loop();
+        slint::CloseRequestResponse::HideWindow
+    });
+
+    ui.run()?;
+    Ok(())
+}
+
+/// 背景執行緒上開 Port：套用 Profile 的 DTR / RTS 並等裝置開機，清掉輸入緩衝區
+///
+/// DTR / RTS 設不了不算失敗 (有些轉接器不支援)，錯誤另外回傳讓 UI 提示。
+fn open_port(name: &str, baud: u32, framing: scpi::Framing, timeout: Duration, profile: &profile::DeviceProfile)
+    -> serialport::Result<(Box<dyn serialport::SerialPort>, Option<serialport::Error>)> {
+    let mut port = framing.apply(serialport::new(name, baud)).timeout(timeout).open()?;
+    let prepared = profile.prepare_port(port.as_mut()).err();
+    let _ = port.clear(ClearBuffer::Input);
+    Ok((port, prepared))
+}
+
+/// 依序用常見的鮑率 (資料格式固定用 `framing`) 開 Port 送 *IDN?，回傳第一個回應乾淨的 (鮑率, IDN)
+fn scan_baud(port_name: &str, framing: scpi::Framing, profile: &profile::DeviceProfile) -> Option<(u32, String)> {
+    scpi::COMMON_BAUD_RATES.iter().find_map(|&baud| {
+        let mut port = framing.apply(serialport::new(port_name, baud)).timeout(Duration::from_millis(300)).open().ok()?;
+        let _ = profile.prepare_port(port.as_mut());
+        let mut link = scpi::Link::new(port, profile.link_options());
+        let _ = link.port.clear(ClearBuffer::Input);
+        let idn = scpi::send_command(&mut link, scpi::cmds::IDN).ok().flatten()?;
+        (!idn.is_empty() && !scpi::looks_garbled(&idn)).then_some((baud, idn))
+    })
+}
+
+/// 連線、*RST、切換 Profile 之後從儀器讀回來的狀態
+///
+/// 在背景執行緒上用 `query_instrument` 讀，回到事件迴圈再由 `resync_from_instrument` 套用到 UI。
+struct InstrumentState {
+    idn: Result<Option<String>, scpi::ScpiError>,
+    /// `resync_queries` 各自的回應
+    answers: Vec<Option<String>>,
+    clock: ClockReading,
+}
+
+/// 同步時要查的項目：輸出狀態 / 設定值，加上 Profile 有定義的選配功能 (順序就是 `resync_from_instrument` 取用的順序)
+fn resync_queries(profile: &profile::DeviceProfile) -> Vec<String> {
+    let optional = [&profile.output_delay_cmd, &profile.fan_mode_cmd, &profile.otp_cmd, &profile.current_range_cmd, &profile.nplc_cmd];
+    let mut queries: Vec<String> = [scpi::cmds::READ_OUTP, scpi::cmds::GET_SET_VOLT, scpi::cmds::GET_SET_CURR].map(String::from).into();
+    queries.extend(optional.iter().filter(|c| !c.is_empty()).map(|c| format!("{}?", c)));
+    queries.extend(profile.coupling_modes().iter().map(|(_, cmd)| format!("{}?", cmd)));
+    // 額定最大值本身就是完整的查詢，不用再加 '?'
+    let rated = [&profile.max_voltage_query, &profile.max_current_query];
+    queries.extend(rated.iter().filter(|q| !q.is_empty()).map(|q| q.to_string()));
+    queries
+}
+
+/// 背景執行緒上讀 IDN、Output 狀態、設定值與選配功能 (支援複合查詢的儀器會合併成一次來回)
+fn query_instrument(p: &mut scpi::Link, profile: &profile::DeviceProfile) -> InstrumentState {
+    let idn = scpi::send_command(p, scpi::cmds::IDN);
+    let queries = resync_queries(profile);
+    let answers = scpi::query_multi(p, &queries.iter().map(String::as_str).collect::<Vec<_>>());
+    InstrumentState { idn, answers, clock: query_instrument_clock(p, profile) }
+}
+
+/// 把 `query_instrument` 讀回來的 IDN、Output 狀態與電壓/電流設定值同步到 UI；回傳 IDN (讀得到的話)
+fn resync_from_instrument(ui: &AppWindow, profile: &profile::DeviceProfile, state: InstrumentState) -> Option<String> {
+    // 1. IDN；回應是亂碼時多半是鮑率設錯 (亂碼不拿來當儀器名稱)
+    let idn_res = state.idn;
+    let garbled = match &idn_res {
+        Ok(Some(s)) | Err(scpi::ScpiError::Partial(s)) | Err(scpi::ScpiError::Truncated(s)) => scpi::looks_garbled(s),
+        _ => false,
+    };
+    ui.set_baud_suspect(garbled);
+    let idn = idn_res.ok().flatten().filter(|_| !garbled);
+    if let Some(info) = &idn {
+        ui.set_window_title(format!("Rust PSU Controller - {}", info).into());
+    }
+    show_identity(ui, &scpi::Identity::parse(idn.as_deref().unwrap_or("")));
+
+    // 2~10. 輸出狀態 / 設定值 / 選配功能 (順序見 `resync_queries`)
+    let coupling = profile.coupling_modes();
+    let mut answers = state.answers.into_iter();
+
+    // 2. 同步 Output 狀態
+    if let Some(Some(outp_status)) = answers.next() {
+        let clean = outp_status.trim().to_uppercase();
+        let is_on = clean == "1" || clean == "ON";
+        ui.set_is_output_on(is_on);
+    }
+
+    // 3. 同步設定電壓 (Set Voltage)
+    if let Some(Some(v_str)) = answers.next() {
+        // SCPI 可能回傳 "12.0000" 或帶單位的 "12.000V"，我們解析後轉回 "12.00" 保持介面整潔
+        let val = scpi::parse_measurement(&v_str).unwrap_or(0.0);
+        // 更新輸入框 (給人看)
+        ui.set_target_voltage(format!("{:.2}", val).into());
+        // 更新生效值 (給邏輯用)
+        ui.set_active_voltage_target(val as f32);
+    }
+
+    // 4. 同步設定電流 (Set Current Limit)
+    if let Some(Some(c_str)) = answers.next() {
+        // 轉為 3 位小數，例如 "1.500"
+        let val = scpi::parse_measurement(&c_str).unwrap_or(0.0);
+        ui.set_target_current(format!("{:.3}", val).into());
+        ui.set_active_current_limit(val as f32);
+    }
+
+    // 5. 選配功能：輸出開啟延遲 (Profile 有定義指令才顯示)
+    // (answers 只有 Profile 有定義的選配查詢，依序取用)
+    ui.set_output_delay_supported(!profile.output_delay_cmd.is_empty());
+    if !profile.output_delay_cmd.is_empty() {
+        if let Some(d) = answers.next().flatten() {
+            let val = scpi::parse_measurement(&d).unwrap_or(0.0);
+            ui.set_output_delay(format!("{:.3}", val).into());
+        }
+    }
+
+    // 6. 選配功能：風扇模式 / 過溫保護門檻
+    ui.set_fan_supported(!profile.fan_mode_cmd.is_empty());
+    let modes: Vec<SharedString> = profile.fan_modes.iter().map(|m| m.as_str().into()).collect();
+    ui.set_fan_modes(Rc::new(VecModel::from(modes)).into());
+    if !profile.fan_mode_cmd.is_empty() {
+        if let Some(m) = answers.next().flatten() {
+            ui.set_fan_mode(m.trim().to_uppercase().into());
+        }
+    }
+    ui.set_otp_supported(!profile.otp_cmd.is_empty());
+    if !profile.otp_cmd.is_empty() {
+        if let Some(t) = answers.next().flatten() {
+            let val = scpi::parse_measurement(&t).unwrap_or(0.0);
+            ui.set_otp_threshold(format!("{:.1}", val).into());
+        }
+    }
+
+    // 7. 選配功能：電流量測檔位
+    ui.set_range_supported(!profile.current_range_cmd.is_empty());
+    let ranges: Vec<SharedString> = profile.current_ranges.iter().map(|r| r.as_str().into()).collect();
+    ui.set_current_ranges(Rc::new(VecModel::from(ranges)).into());
+    if !profile.current_range_cmd.is_empty() {
+        if let Some(r) = answers.next().flatten() {
+            ui.set_current_range(r.trim().to_uppercase().into());
+        }
+    }
+
+    // 8. 選配功能：量測積分時間 (NPLC)
+    ui.set_nplc_supported(!profile.nplc_cmd.is_empty());
+    if !profile.nplc_cmd.is_empty() {
+        if let Some(n) = answers.next().flatten().and_then(|n| scpi::parse_number(n.trim())) {
+            ui.set_nplc(scpi::format_number(n).into());
+            ui.set_nplc_hint(nplc_hint(n, profile.line_freq_hz, &ui.get_polling_interval()).into());
+        }
+    }
+
+    // 9. 選配功能：通道耦合 (串聯 / 並聯 / 追蹤)；第一個回 ON 的就是目前模式
+    ui.set_coupling_supported(!coupling.is_empty());
+    let names: Vec<SharedString> = std::iter::once("Independent").chain(coupling.iter().map(|(name, _)| *name)).map(SharedString::from).collect();
+    ui.set_coupling_modes(Rc::new(VecModel::from(names)).into());
+    let mut active = "Independent";
+    for (name, _) in &coupling {
+        let on = answers.next().flatten().is_some_and(|r| matches!(r.trim().to_uppercase().as_str(), "1" | "ON"));
+        if on && active == "Independent" { active = name; }
+    }
+    ui.set_coupling_mode(active.into());
+
+    // 10. 額定最大值 (設定值餘裕用)；0 = 不知道
+    let mut rated_max = || answers.next().flatten().and_then(|r| scpi::parse_measurement(&r)).unwrap_or(0.0) as f32;
+    ui.set_rated_voltage(if profile.max_voltage_query.is_empty() { 0.0 } else { rated_max() });
+    ui.set_rated_current(if profile.max_current_query.is_empty() { 0.0 } else { rated_max() });
+
+    // 11. 選配功能：儀器時鐘 (記下與 PC 的時間差)
+    show_instrument_clock(ui, profile, state.clock);
+
+    // 12. 選配功能：儀器端觸發量測
+    ui.set_inst_trigger_supported(profile.has_trigger());
+    let sources: Vec<SharedString> = profile.trigger_sources.iter().map(|s| s.as_str().into()).collect();
+    if let Some(first) = sources.first().filter(|_| !sources.iter().any(|s| *s == ui.get_inst_trigger_source())) {
+        ui.set_inst_trigger_source(first.clone());
+    }
+    ui.set_inst_trigger_sources(Rc::new(VecModel::from(sources)).into());
+    idn
+}
+
+/// 讀回觸發量到的結果；還沒觸發 (Timeout) 就維持 armed 讓使用者稍後再讀
+fn fetch_inst_trigger(ui: &AppWindow, sp: &SharedPort, profile: &profile::DeviceProfile) {
+    let profile = profile.clone();
+    let ui_weak = ui.as_weak();
+    sp.run(move |p| profile.fetch_triggered(p), move |res| {
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        let show = |v: Option<f64>, unit: &str| v.map(|v| scpi::format_with_unit(v, unit)).unwrap_or("---".into());
+        match res.and_then(|r| r) {
+            Ok((v, i)) => {
+                ui.set_inst_trigger_armed(false);
+                ui.set_inst_trigger_status(format!(
+                    "{}  V = {}, I = {}", chrono::Local::now().format("%H:%M:%S%.3f"), show(v, "V"), show(i, "A")
+                ).into());
+            }
+            Err(scpi::ScpiError::Timeout) => ui.set_inst_trigger_status("Not triggered yet, still armed".into()),
+            Err(e) => ui.set_inst_trigger_status(format!("Fetch failed: {}", e).into()),
+        }
+    });
+}
+
+/// Profile 編輯器欄位型別 ↔ UI 用的字串 (決定顯示 CheckBox 還是輸入框)
+fn field_kind_name(kind: profile::FieldKind) -> &'static str {
+    match kind {
+        profile::FieldKind::Text => "text",
+        profile::FieldKind::Number => "number",
+        profile::FieldKind::Bool => "bool",
+        profile::FieldKind::OptionalBool => "optional",
+        profile::FieldKind::List => "list",
+    }
+}
+
+fn field_kind(name: &str) -> profile::FieldKind {
+    match name {
+        "number" => profile::FieldKind::Number,
+        "bool" => profile::FieldKind::Bool,
+        "optional" => profile::FieldKind::OptionalBool,
+        "list" => profile::FieldKind::List,
+        _ => profile::FieldKind::Text,
+    }
+}
+
+/// NPLC 的速度 / 雜訊取捨說明：每次 READ_ALL 要積分 V、I 兩次，輪詢不能比這更快
+fn nplc_hint(nplc: f64, line_hz: f64, poll_interval: &str) -> String {
+    let per_reading_ms = nplc / line_hz.max(1.0) * 1000.0;
+    let per_poll_ms = per_reading_ms * 2.0;
+    // 白雜訊隨積分時間開根號下降，以 NPLC 1 為基準
+    let noise = 1.0 / nplc.sqrt();
+    let mut hint = format!(
+        "≈ {:.1} ms per reading, {:.0} ms per V+I poll (max ~{:.1} Hz). Noise ≈ ×{:.2} vs NPLC 1.",
+        per_reading_ms, per_poll_ms, 1000.0 / per_poll_ms, noise
+    );
+    if poll_interval.trim().parse::<f64>().is_ok_and(|ms| ms < per_poll_ms) {
+        hint.push_str(" Poll interval is shorter than the integration time; readings will lag.");
+    }
+    hint
+}
+
+/// 把校正值填回 Calibration 分頁
+fn show_calibration(ui: &AppWindow, cal: &calibration::Calibration) {
+    ui.set_cal_v_gain(format!("{:.6}", cal.voltage.gain).into());
+    ui.set_cal_v_offset(format!("{:.6}", cal.voltage.offset).into());
+    ui.set_cal_i_gain(format!("{:.6}", cal.current.gain).into());
+    ui.set_cal_i_offset(format!("{:.6}", cal.current.offset).into());
+    ui.set_cal_log_raw(cal.log_raw);
+    ui.set_cal_status(if cal.is_identity() { "No correction applied" } else { "Correction active" }.into());
+}
+
+/// Kiosk 鎖定中：擋下會改變設定的操作並提示 (緊急斷電不受影響)
+fn ui_locked(ui: &AppWindow) -> bool {
+    if !ui.get_ui_locked() { return false; }
+    ui.set_message_text("UI is locked (monitoring only). Unlock with the PIN first.".into());
+    true
+}
+
+/// 已經有自動化 (循環、斜坡、I-V 掃描、上電順序) 在跑時不准再開一個，免得互相搶著寫設定值
+fn automation_busy(ui: &AppWindow) -> bool {
+    let active = ui.get_active_automation();
+    if active.is_empty() { return false; }
+    ui.set_message_text(format!("{} is running. Stop it first.", active).into());
+    true
+}
+
+/// 「About Device」面板 (沒有的欄位顯示 ---)
+fn show_identity(ui: &AppWindow, id: &scpi::Identity) {
+    let show = |s: &str| -> SharedString { if s.is_empty() { "---".into() } else { s.into() } };
+    ui.set_idn_manufacturer(show(&id.manufacturer));
+    ui.set_idn_model(show(&id.model));
+    ui.set_idn_serial(show(&id.serial));
+    ui.set_idn_firmware(show(&id.firmware));
+}
+
+/// 儀器時鐘讀到的 (日期, 時間) 與讀的當下的 PC 時間；Profile 沒定義日期/時間指令時是 None
+type ClockReading = Option<(Option<String>, Option<String>, chrono::DateTime<chrono::Local>)>;
+
+/// 背景執行緒上讀儀器時鐘
+fn query_instrument_clock(p: &mut scpi::Link, profile: &profile::DeviceProfile) -> ClockReading {
+    if !profile.has_clock() { return None; }
+    let date = scpi::send_command(p, &format!("{}?", profile.date_cmd)).ok().flatten();
+    let time = scpi::send_command(p, &format!("{}?", profile.time_cmd)).ok().flatten();
+    Some((date, time, chrono::Local::now()))
+}
+
+/// 顯示儀器時鐘與 PC 的時間差 (Profile 沒定義日期/時間指令就隱藏)
+fn show_instrument_clock(ui: &AppWindow, profile: &profile::DeviceProfile, reading: ClockReading) {
+    ui.set_clock_supported(profile.has_clock());
+    ui.set_clock_offset_known(false);
+    let Some((date, time, pc)) = reading else { return; };
+    match date.zip(time).and_then(|(d, t)| clock::parse(&d, &t)) {
+        Some(inst) => {
+            let offset = clock::offset_seconds(inst, pc);
+            ui.set_clock_offset(offset as f32);
+            ui.set_clock_offset_known(true);
+            ui.set_clock_text(format!("{} (offset {:+.1} s vs PC)", inst.format("%Y-%m-%d %H:%M:%S"), offset).into());
+        }
+        None => ui.set_clock_text("Could not read the instrument clock".into()),
+    }
+}
+
+/// 相對 (Δ) 顯示：最近一次的原始讀值與使用者記下的參考點 (V, A)
+#[derive(Default)]
+struct RelativeRef {
+    last_raw: Option<(f32, f32)>,
+    reference: Option<(f32, f32)>,
+}
+
+impl RelativeRef {
+    /// 相對模式開啟且有參考點時回傳參考點，否則 (0, 0)
+    fn offset(&self, ui: &AppWindow) -> (f32, f32) {
+        match self.reference {
+            Some(r) if ui.get_relative_mode() => r,
+            _ => (0.0, 0.0),
+        }
+    }
+}
+
+/// 儀器狀態快照，用來比對 *RST / 切換 Profile 前後的差異
+#[derive(Clone, PartialEq)]
+struct StateSnapshot {
+    voltage: String,
+    current: String,
+    output_on: bool,
+}
+
+impl StateSnapshot {
+    fn capture(ui: &AppWindow) -> Self {
+        Self {
+            voltage: ui.get_target_voltage().to_string(),
+            current: ui.get_target_current().to_string(),
+            output_on: ui.get_is_output_on(),
+        }
+    }
+
+    /// 列出有變動的欄位，例如 "Voltage set: 12.00 → 0.00"
+    fn diff(&self, after: &Self) -> Vec<String> {
+        let on_off = |b: bool| if b { "ON" } else { "OFF" };
+        let mut out = Vec::new();
+        if self.voltage != after.voltage {
+            out.push(format!("Voltage set: {} V → {} V", self.voltage, after.voltage));
+        }
+        if self.current != after.current {
+            out.push(format!("Current limit: {} A → {} A", self.current, after.current));
+        }
+        if self.output_on != after.output_on {
+            out.push(format!("Output: {} → {}", on_off(self.output_on), on_off(after.output_on)));
+        }
+        out
+    }
+}
+
+/// 重新同步並把差異顯示在「變更」面板
+fn resync_with_diff(ui: &AppWindow, profile: &profile::DeviceProfile, state: InstrumentState, reason: &str) {
+    let before = StateSnapshot::capture(ui);
+    resync_from_instrument(ui, profile, state);
+    let changes = before.diff(&StateSnapshot::capture(ui));
+
+    let body = if changes.is_empty() { "No changes".to_string() } else { changes.join("\n") };
+    ui.set_changes_text(format!("{}:\n{}", reason, body).into());
+}
+
+/// 把通訊結果反映到狀態列：失敗顯示原因，成功則清除訊息
+fn report_result(ui: &AppWindow, res: Result<Option<String>, scpi::ScpiError>) -> Option<String> {
+    match res {
+        Ok(v) => { ui.set_message_text("".into()); v }
+        Err(e) => { ui.set_message_text(e.to_string().into()); handle_link_lost(ui, &e); None }
+    }
+}
+
+/// Port 不見了 (線被拔掉)：照一般斷線流程收尾，再交給自動重連
+fn handle_link_lost(ui: &AppWindow, e: &scpi::ScpiError) {
+    if e.is_link_lost() { connection_lost(ui, e.to_string()); }
+}
+
+/// 連線斷了 (Port 不見或一直讀不到)：觸發 `connection-lost`，自動重連或顯示 "Connection lost"
+///
+/// 呼叫端通常還借著序列埠 / 圖表 Buffer，所以延到下一輪事件迴圈再斷線。
+fn connection_lost(ui: &AppWindow, message: String) {
+    if ui.get_status_text() != "Connected" { return; }
+    let ui_weak = ui.as_weak();
+    Timer::single_shot(Duration::ZERO, move || {
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        if ui.get_status_text() != "Connected" { return; }
+        ui.invoke_connection_lost(message.into());
+    });
+}
+
+/// 自動重連的一次嘗試：用上次成功連線的 Port / 鮑率走一般的連線流程 (同步 IDN / Output / 設定值)
+///
+/// 開 Port 在背景做，結果由下一輪的 `reconnect_result` 檢查；使用者自己接手時回傳 `true` (停止重連)。
+fn reconnect_attempt(ui: &AppWindow, cfg: &RefCell<config::AppConfig>) -> bool {
+    // 使用者自己按了 Connect (成功或失敗都算接手)
+    if !ui.get_status_text().starts_with("Reconnecting") { return true; }
+    {
+        let c = cfg.borrow();
+        select_port(ui, &c.last_port);
+        ui.set_baud_rate(c.baud_rate.to_string().into());
+    }
+    ui.invoke_toggle_connection();
+    false
+}
+
+/// 一次重連嘗試有結果之後：成功或放棄時回傳 `true` (停止重連)，否則顯示失敗原因等下一次
+fn reconnect_result(ui: &AppWindow, attempt: u32, max: u32) -> bool {
+    if ui.get_status_text() == "Connected" {
+        ui.set_message_text(format!("Reconnected ({} of {} attempts)", attempt, max).into());
+        return true;
+    }
+    // 連線失敗時狀態列是錯誤原因，改放到訊息列
+    let reason = ui.get_status_text();
+    if attempt >= max {
+        ui.set_status_text("Disconnected".into());
+        ui.set_status_color(Color::from_rgb_u8(255, 0, 0).into());
+        ui.set_message_text(format!("Gave up reconnecting after {} attempts ({}). Check the cable and press Connect.", max, reason).into());
+        return true;
+    }
+    ui.set_status_text(format!("Reconnecting… ({}/{})", attempt, max).into());
+    ui.set_status_color(Color::from_rgb_u8(255, 165, 0).into());
+    ui.set_message_text(format!("Reconnect attempt {} failed: {}", attempt, reason).into());
+    false
+}
+
+/// 套用設定後讀儀器的錯誤佇列：被拒絕的指令顯示在紅字標籤 (最近一筆)，沒錯誤就清掉
+///
+/// 序列埠忙碌或讀不到時維持原本的顯示，不要把還沒確認的錯誤清掉。
+fn check_instrument_errors(ui: &AppWindow, sp: &SharedPort, profile: &profile::DeviceProfile) {
+    if !profile.error_queue { return; }
+    let ui_weak = ui.as_weak();
+    sp.run(scpi::drain_error_queue, move |res| {
+        let (Some(ui), Ok(Ok(errors))) = (ui_weak.upgrade(), res) else { return; };
+        let text = match errors.as_slice() {
+            [] => String::new(),
+            [e] => e.to_string(),
+            [.., last] => format!("{} (+{} more)", last, errors.len() - 1),
+        };
+        ui.set_instrument_error(text.into());
+    });
+}
+
+/// `io_scpi` 最常用的後續處理：只把結果反映到狀態列
+fn report_to(ui: &AppWindow) -> IoDone {
+    let ui_weak = ui.as_weak();
+    Box::new(move |res| {
+        if let Some(ui) = ui_weak.upgrade() { report_result(&ui, res); }
+    })
+}
+
+/// 套用設定的後續處理：回報結果 (設定值另外提醒 Output 關著) 再讀錯誤佇列
+fn report_apply(ui: &AppWindow, sp: &SharedPort, profile: &Rc<RefCell<profile::DeviceProfile>>, setpoint: bool) -> IoDone {
+    let (ui_weak, sp, profile) = (ui.as_weak(), sp.clone(), profile.clone());
+    Box::new(move |res| {
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        if setpoint { report_setpoint(&ui, res); } else { report_result(&ui, res); }
+        check_instrument_errors(&ui, &sp, &profile.borrow());
+    })
+}
+
+/// 設定值送出後回報結果；Output 關著時提醒使用者 (讀值是 0 不是設定沒生效)
+fn report_setpoint(ui: &AppWindow, res: Result<Option<String>, scpi::ScpiError>) {
+    let ok = res.is_ok();
+    report_result(ui, res);
+    if ok && !ui.get_is_output_on() { ui.set_setpoint_while_off(true); }
+}
+
+/// Trigger 分頁的換檔設定；空白或無效 = 不換檔
+fn rotation_from_ui(ui: &AppWindow) -> logger::Rotation {
+    logger::Rotation {
+        max_bytes: (scpi::parse_number(&ui.get_capture_rotate_mb()).unwrap_or(0.0).max(0.0) * 1_000_000.0) as u64,
+        max_age: Duration::from_secs_f64(scpi::parse_number(&ui.get_capture_rotate_min()).unwrap_or(0.0).max(0.0) * 60.0),
+        gzip: ui.get_capture_gzip(),
+    }
+}
+
+/// 會送指令到儀器的 Timer，由 `stop_all_timers` 一起停掉
+#[derive(Clone)]
+struct DeviceTimers {
+    poll: Rc<RefCell<Timer>>,
+    telemetry: Rc<Timer>,
+    looping: Rc<RefCell<Timer>>,
+    auto_off: Rc<RefCell<Timer>>,
+    auto_off_ticker: Rc<RefCell<Timer>>,
+    sequence: Rc<RefCell<Timer>>,
+    ramp: Rc<Timer>,
+    iv: Rc<Timer>,
+}
+
+/// 停掉循環、Auto-Off、上電順序、降壓斜坡與 I-V 掃描，並重設它們的狀態旗標
+///
+/// 只停 Timer、不送任何指令 (斜坡 / 掃描中斷後電壓設定停在當下的值)。
+/// `monitoring` = 連輪詢與遙測也停 (斷線、關閉程式)；緊急斷電時保留輪詢。
+fn stop_all_timers(ui: &AppWindow, timers: &DeviceTimers, monitoring: bool) {
+    timers.looping.borrow().stop();
+    ui.set_is_looping(false);
+    cancel_auto_off(ui, &timers.auto_off.borrow(), &timers.auto_off_ticker.borrow());
+    if ui.get_sequence_running() {
+        timers.sequence.borrow().stop();
+        ui.set_sequence_running(false);
+        ui.set_sequence_status("Sequence stopped".into());
+    }
+    timers.ramp.stop();
+    ui.set_ramp_down_running(false);
+    if ui.get_iv_running() {
+        timers.iv.stop();
+        ui.set_iv_running(false);
+        ui.set_iv_status("Stopped".into());
+    }
+    if monitoring {
+        timers.poll.borrow().stop();
+        timers.telemetry.stop();
+    }
+}
+
+/// 啟動 Auto-Off：`seconds` 秒後送出 OUTP OFF，期間每秒更新倒數
+fn start_auto_off(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: &Timer, ticker: Rc<RefCell<Timer>>, seconds: u64) {
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let show_remaining = move |ui: &AppWindow| {
+        let left = deadline.saturating_duration_since(Instant::now()).as_secs();
+        ui.set_auto_off_remaining(format!("{:02}:{:02}", left / 60, left % 60).into());
+    };
+
+    if let Some(ui) = ui_weak.upgrade() { show_remaining(&ui); }
+    let ui_tick = ui_weak.clone();
+    ticker.borrow().start(TimerMode::Repeated, Duration::from_secs(1), move || {
+        if let Some(ui) = ui_tick.upgrade() { show_remaining(&ui); }
+    });
+
+    timer.start(TimerMode::SingleShot, Duration::from_secs(seconds), move || {
+        ticker.borrow().stop();
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        send_urgent(ui.as_weak(), &sp, scpi::cmds::OUTP_OFF);
+        ui.set_is_output_on(false);
+        ui.set_auto_off_remaining("".into());
+    });
+}
+
+/// 等待執行的手動查詢 (不重複)
+type ReadQueue = Rc<RefCell<Vec<&'static str>>>;
+
+/// 排入一個手動讀值，下一輪事件迴圈再一次讀完；已經在佇列裡的查詢直接略過
+fn queue_manual_read(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, queue: ReadQueue, cal: Rc<RefCell<calibration::Calibration>>, cmd: &'static str) {
+    let mut pending = queue.borrow_mut();
+    if pending.contains(&cmd) { return; }
+    pending.push(cmd);
+    if pending.len() > 1 { return; } // 已經排好處理了
+    drop(pending);
+
+    Timer::single_shot(Duration::ZERO, move || {
+        let cmds = std::mem::take(&mut *queue.borrow_mut());
+        sp.run(move |p| cmds.into_iter().map(|cmd| (cmd, scpi::send_command(p, cmd))).collect::<Vec<_>>(), move |res| {
+            let Some(ui) = ui_weak.upgrade() else { return; };
+            let replies = match res {
+                Ok(replies) => replies,
+                Err(e) => { report_result(&ui, Err(e)); return; }
+            };
+            for (cmd, res) in replies {
+                let Some(val) = report_result(&ui, res) else { continue; };
+                let (unit, corr) = if cmd == scpi::cmds::READ_VOLT { ("V", cal.borrow().voltage) } else { ("A", cal.borrow().current) };
+                let text: SharedString = scpi::parse_measurement(&val)
+                    .map(|x| scpi::format_with_resolution(corr.apply(x as f32) as f64, unit, scpi::decimals_of(&val)))
+                    .unwrap_or(val).into();
+                if cmd == scpi::cmds::READ_VOLT { ui.set_voltage_reading(text); } else { ui.set_current_reading(text); }
+            }
+        });
+    });
+}
+
+/// 一定要送出的指令 (關輸出這類安全相關的)：序列埠借出去時插到佇列最前面，不會被略過也不用等前面排隊的
+fn send_urgent(ui_weak: slint::Weak<AppWindow>, sp: &SharedPort, cmd: &'static str) {
+    sp.run_urgent(move |p| scpi::send_command(p, cmd), move |res| {
+        if let Some(ui) = ui_weak.upgrade() { report_result(&ui, res.and_then(|r| r)); }
+    });
+}
+
+/// 一輪慢速遙測在背景執行緒上讀到的東西
+struct TelemetryReply {
+    local: Option<bool>,
+    /// None = 有開自動輪詢 (連鎖交給輪詢檢查)
+    interlock: Option<Option<bool>>,
+    /// Profile 自訂的額外量測項目
+    extra: Vec<Option<f64>>,
+    /// AC 輸入 (電壓, 頻率)
+    ac: (Option<f64>, Option<f64>),
+}
+
+/// 慢速遙測 (AC 輸入電壓 / 頻率、本地 / 遠端模式)：每 5 秒讀一次，Profile 沒定義查詢就隱藏面板
+fn start_telemetry(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: &Timer, profile: Rc<RefCell<profile::DeviceProfile>>) {
+    let in_flight = Rc::new(Cell::new(false));
+    let tick = move || {
+        // 上一輪還沒讀完 (序列埠很慢或一直超時)：不要再排一輪
+        if in_flight.get() { return; }
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        // 每次都看目前的 Profile，連線中切換 Profile 也會跟著變
+        let profile = profile.borrow().clone();
+        // 沒開自動輪詢時，安全連鎖至少在這裡還會檢查
+        let check_interlock = !ui.get_enable_auto_refresh();
+
+        in_flight.set(true);
+        let (ui_done, sp_done, in_flight) = (ui_weak.clone(), sp.clone(), in_flight.clone());
+        sp.run(move |p| {
+            let read = |p: &mut scpi::Link, query: &str| -> Option<f64> {
+                if query.is_empty() { return None; }
+                scpi::send_command(p, query).ok().flatten().and_then(|r| scpi::parse_measurement(&r))
+            };
+            let local = profile.is_local(p);
+            let interlock = check_interlock.then(|| profile.interlock_ok(p.port.as_mut()));
+            let extra = profile.measurements.iter().map(|m| read(p, &m.query)).collect();
+            let ac = if profile.has_ac_telemetry() { (read(p, &profile.ac_voltage_query), read(p, &profile.ac_freq_query)) } else { (None, None) };
+            (profile, TelemetryReply { local, interlock, extra, ac })
+        }, move |res| {
+            in_flight.set(false);
+            // 沒連線 / 斷線中：保留上一次的值
+            let (Some(ui), Ok((profile, reply))) = (ui_done.upgrade(), res) else { return; };
+
+            // 面板被按了 Local 鍵時，遠端指令會被默默忽略：顯示提示列
+            if let Some(local) = reply.local { ui.set_device_local(local); }
+            if let Some(ok) = reply.interlock { enforce_interlock(&ui, &sp_done, ok); }
+
+            // Profile 自訂的額外量測項目
+            let rows: Vec<MeasurementReadout> = profile.measurements.iter().zip(reply.extra).map(|(m, v)| MeasurementReadout {
+                name: m.name.as_str().into(),
+                value: match v {
+                    Some(v) if m.unit.is_empty() => scpi::format_number(v).into(),
+                    Some(v) => scpi::format_with_unit(v, &m.unit).into(),
+                    None => "---".into(),
+                },
+            }).collect();
+            ui.set_extra_readings(Rc::new(VecModel::from(rows)).into());
+
+            ui.set_ac_telemetry_supported(profile.has_ac_telemetry());
+            if !profile.has_ac_telemetry() { return; }
+            let (volts, freq) = reply.ac;
+            ui.set_ac_input_voltage(volts.map(|v| format!("{:.1} V", v)).unwrap_or("---".into()).into());
+            ui.set_ac_input_freq(freq.map(|f| format!("{:.2} Hz", f)).unwrap_or("---".into()).into());
+        });
+    };
+    tick();
+    timer.start(TimerMode::Repeated, Duration::from_secs(5), tick);
+}
+
+/// 安全連鎖沒滿足時關掉輸出 (剛斷開的那一次無論 UI 狀態都送 OUTP OFF)；回傳是否真的把開著的輸出關掉
+///
+/// `ok` 是剛讀到的連鎖狀態 (None = Profile 沒定義連鎖)。
+fn enforce_interlock(ui: &AppWindow, sp: &SharedPort, ok: Option<bool>) -> bool {
+    let Some(ok) = ok else {
+        ui.set_interlock_open(false);
+        return false;
+    };
+    let was_open = ui.get_interlock_open();
+    ui.set_interlock_open(!ok);
+    let tripped = !ok && ui.get_is_output_on();
+    if !ok && (!was_open || ui.get_is_output_on()) {
+        sp.post_urgent(|p| { let _ = scpi::send_command(p, scpi::cmds::OUTP_OFF); });
+        ui.set_is_output_on(false);
+        ui.set_message_text("Interlock open: output turned off".into());
+    }
+    tripped
+}
+
+/// 要開輸出前即時讀安全連鎖 (在背景工作裡，跟 OUTP ON 同一個工作)；沒滿足或讀不到都算開著
+fn interlock_open_now(p: &mut scpi::Link, profile: &profile::DeviceProfile) -> bool {
+    profile.interlock_ok(p.port.as_mut()) == Some(false)
+}
+
+/// 開輸出被安全連鎖擋下
+fn report_interlock_blocked(ui: &AppWindow) {
+    ui.set_interlock_open(true);
+    ui.set_message_text("Interlock open: output stays off".into());
+}
+
+/// 取消 Auto-Off 倒數
+fn cancel_auto_off(ui: &AppWindow, timer: &Timer, ticker: &Timer) {
+    timer.stop();
+    ticker.stop();
+    ui.set_auto_off_remaining("".into());
+}
+
+/// 解析上電順序 "1:0, 2:100" → [(通道, 與前一步間隔 ms)]
+fn parse_sequence(text: &str) -> Option<Vec<(i32, u64)>> {
+    let steps: Option<Vec<(i32, u64)>> = text.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| {
+            let (ch, delay) = s.split_once(':').unwrap_or((s, "0"));
+            Some((ch.trim().parse().ok()?, delay.trim().parse().ok()?))
+        })
+        .collect();
+    steps.filter(|s| !s.is_empty())
+}
+
+/// 斷電順序 = 上電反過來，每一步等待的時間也跟著對調
+fn reverse_sequence(steps: &[(i32, u64)]) -> Vec<(i32, u64)> {
+    let n = steps.len();
+    (0..n).map(|k| (steps[n - 1 - k].0, if k == 0 { 0 } else { steps[n - k].1 })).collect()
+}
+
+/// 排程第 `idx` 步：等待該步的延遲後執行
+fn schedule_sequence_step(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: Rc<RefCell<Timer>>, steps: Rc<Vec<(i32, u64)>>, idx: usize, on: bool, profile: Rc<profile::DeviceProfile>) {
+    let Some(&(_, delay)) = steps.get(idx) else { return; };
+    timer.borrow().start(TimerMode::SingleShot, Duration::from_millis(delay), {
+        let timer = timer.clone();
+        move || run_sequence_step(ui_weak.clone(), sp.clone(), timer.clone(), steps.clone(), idx, on, profile.clone())
+    });
+}
+
+/// 執行第 `idx` 步 (切通道 + OUTP ON/OFF)，做完再排下一步
+///
+/// 上電時每一步都在同一個背景工作裡先讀安全連鎖，沒滿足就整個順序停下來。
+fn run_sequence_step(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: Rc<RefCell<Timer>>, steps: Rc<Vec<(i32, u64)>>, idx: usize, on: bool, profile: Rc<profile::DeviceProfile>) {
+    let ch = steps[idx].0;
+    let interlock = (*profile).clone();
+    let sp_next = sp.clone();
+    sp.run(move |p| {
+        if on && interlock_open_now(p, &interlock) { return None; }
+        let _ = scpi::send_command(p, &format!("{} {}", scpi::cmds::SELECT_CHANNEL, ch));
+        Some(scpi::send_command(p, if on { scpi::cmds::OUTP_ON } else { scpi::cmds::OUTP_OFF }))
+    }, move |res| {
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        // 等回應的時候被停掉了 (Stop / 緊急關閉)
+        if !ui.get_sequence_running() { return; }
+        let sp = sp_next;
+        let res = match res {
+            Ok(None) => {
+                report_interlock_blocked(&ui);
+                ui.set_sequence_running(false);
+                ui.set_sequence_status(format!("Stopped at CH{}: interlock open", ch).into());
+                return;
+            }
+            Ok(Some(res)) => res,
+            Err(e) => Err(e),
+        };
+        report_result(&ui, res);
+        ui.set_sequence_status(format!("CH{} {} ({}/{})", ch, if on { "ON" } else { "OFF" }, idx + 1, steps.len()).into());
+
+        if idx + 1 < steps.len() {
+            schedule_sequence_step(ui_weak, sp, timer, steps, idx + 1, on, profile);
+            return;
+        }
+
+        // 完成：切回手動控制的通道並同步按鈕狀態
+        let select = format!("{} {}", scpi::cmds::SELECT_CHANNEL, ui.get_control_channel());
+        sp.post(move |p| { let _ = scpi::send_command(p, &select); });
+        ui.set_is_output_on(on);
+        ui.set_sequence_running(false);
+        let msg = format!("Power-{} sequence done", if on { "up" } else { "down" });
+        ui.set_sequence_status(msg.as_str().into());
+        notify_complete(&ui, &sp, &msg);
+    });
+}
+
+/// 長時間操作完成的提示：閃一下完成橫幅，有勾選就讓儀器嗶一聲 (不用一直盯著畫面)
+fn notify_complete(ui: &AppWindow, sp: &SharedPort, msg: &str) {
+    ui.set_completion_text(msg.into());
+    ui.set_completion_flash(ui.get_completion_flash() + 1);
+    if ui.get_completion_beep() {
+        // 不是每台都支援 SYST:BEEP，失敗就算了
+        sp.post(|p| { let _ = scpi::send_command(p, scpi::cmds::BEEP); });
+    }
+    // 幾秒後收起；期間又有新的完成訊息就讓新的那個決定
+    let flash = ui.get_completion_flash();
+    let ui_weak = ui.as_weak();
+    Timer::single_shot(Duration::from_secs(4), move || {
+        if let Some(ui) = ui_weak.upgrade() {
+            if ui.get_completion_flash() == flash { ui.set_completion_text("".into()); }
+        }
+    });
+}
+
+/// 狀態列 CC/CV/OFF 提示文字 (用讀值與生效中的設定值解釋目前的模式)
+fn mode_explanation(mode: &str, v: f32, i: f32, v_set: f32, i_limit: f32) -> String {
+    match mode {
+        "CC" => format!(
+            "Constant Current: output is limiting current at {:.3} A (voltage has dropped to {:.3} V of the {:.3} V setpoint)",
+            i_limit.abs(), v, v_set
+        ),
+        "CV" => format!(
+            "Constant Voltage: output is regulating at {:.3} V, load draws {:.3} A of the {:.3} A limit",
+            v, i.abs(), i_limit.abs()
+        ),
+        _ => "Output OFF: the supply is connected but not delivering power".to_string(),
+    }
+}
+
+/// 切到指定通道並量測功率 (W)
+fn read_channel_power(p: &mut scpi::Link, ch: i32) -> Option<f32> {
+    scpi::send_command(p, &format!("{} {}", scpi::cmds::SELECT_CHANNEL, ch)).ok()?;
+    let (v, i) = read_vi(p)?;
+    Some(v * i)
+}
+
+/// 效率量測：讀輸入 / 輸出通道的功率，讀完切回手動控制的通道 (避免之後的設定打到別的通道)
+fn read_efficiency(p: &mut scpi::Link, in_ch: i32, out_ch: i32, control_ch: i32) -> (Option<f32>, Option<f32>) {
+    let powers = (read_channel_power(p, in_ch), read_channel_power(p, out_ch));
+    let _ = scpi::send_command(p, &format!("{} {}", scpi::cmds::SELECT_CHANNEL, control_ch));
+    powers
+}
+
+/// 多通道疊圖：逐一切通道量測，量完切回手動控制的通道；讀不到的通道記 NaN，曲線在那裡斷開
+fn read_overlay(p: &mut scpi::Link, channels: &[i32], current: bool, control_ch: i32) -> Vec<f32> {
+    let values = channels.iter().map(|&ch| {
+        scpi::send_command(p, &format!("{} {}", scpi::cmds::SELECT_CHANNEL, ch)).ok()
+            .and_then(|_| read_vi(p))
+            .map_or(f32::NAN, |(v, i)| if current { i } else { v })
+    }).collect();
+    let _ = scpi::send_command(p, &format!("{} {}", scpi::cmds::SELECT_CHANNEL, control_ch));
+    values
+}
+
+/// 連線列選的 (指令結尾, 回應結尾)；看不懂的當預設 CRLF / LF
+fn terminators(ui: &AppWindow) -> (scpi::Terminator, scpi::Terminator) {
+    (
+        scpi::Terminator::parse(&ui.get_tx_terminator()).unwrap_or(scpi::Terminator::CrLf),
+        scpi::Terminator::parse(&ui.get_rx_terminator()).unwrap_or(scpi::Terminator::Lf),
+    )
+}
+
+/// 設定頁的讀取超時 (ms)；空白或 0 用預設值
+fn read_timeout(ui: &AppWindow) -> Duration {
+    match ui.get_read_timeout_ms().trim().parse::<u64>() {
+        Ok(ms) if ms > 0 => Duration::from_millis(ms),
+        _ => scpi::DEFAULT_READ_TIMEOUT,
+    }
+}
+
+/// 設定頁的穩定時間 (ms)
+fn settle_duration(ui: &AppWindow) -> Duration {
+    Duration::from_millis(ui.get_settle_ms().trim().parse::<u64>().unwrap_or(0))
+}
+
+/// 讀一次 MEAS:ALL? 並解析成 (V, A)；剛改過設定時先等輸出穩定
+fn read_vi(p: &mut scpi::Link) -> Option<(f32, f32)> {
+    p.wait_settled();
+    let raw = scpi::send_command(p, scpi::cmds::READ_ALL).ok()??;
+    let (v, i, _) = scpi::parse_meas_all(&raw)?;
+    Some((v as f32, i as f32))
+}
+
+/// I-V 掃描的設定點與每點的穩定時間
+struct IvSweep {
+    setpoints: Vec<f64>,
+    settle: Duration,
+}
+
+/// 掃描結果 (設定電壓, 量到的電壓, 量到的電流)
+type IvPoints = Rc<RefCell<Vec<(f32, f32, f32)>>>;
+
+/// 從 `start` 到 `stop` 均分成 `steps` 個點 (含頭尾)
+fn sweep_setpoints(start: f64, stop: f64, steps: usize) -> Vec<f64> {
+    (0..steps).map(|k| start + (stop - start) * k as f64 / (steps - 1) as f64).collect()
+}
+
+/// I-V 掃描第 `idx` 步：送出設定電壓，等穩定時間後量測
+fn iv_sweep_step(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: Rc<Timer>, sweep: Rc<IvSweep>, points: IvPoints, idx: usize) {
+    let Some(ui) = ui_weak.upgrade() else { return; };
+    if idx >= sweep.setpoints.len() || !ui.get_iv_running() {
+        let done = idx >= sweep.setpoints.len();
+        finish_iv_sweep(&ui, &sp, if done { format!("Done: {} points", points.borrow().len()) } else { "Stopped".to_string() });
+        if done { notify_complete(&ui, &sp, &format!("I-V sweep done: {} points", points.borrow().len())); }
+        return;
+    }
+
+    let cmd = format!("{} {}", scpi::cmds::SET_VOLT, scpi::format_number(sweep.setpoints[idx]));
+    let sp_done = sp.clone();
+    sp.run(move |p| scpi::send_command(p, &cmd), move |res| {
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        let sp = sp_done;
+        match res.and_then(|r| r) {
+            Err(e) => finish_iv_sweep(&ui, &sp, format!("Sweep aborted: {}", e)),
+            Ok(_) => {
+                let t = timer.clone();
+                timer.start(TimerMode::SingleShot, sweep.settle, move || {
+                    iv_sweep_measure(ui_weak.clone(), sp.clone(), t.clone(), sweep.clone(), points.clone(), idx);
+                });
+            }
+        }
+    });
+}
+
+/// I-V 掃描第 `idx` 步的量測，記錄後更新圖表並進行下一步
+fn iv_sweep_measure(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: Rc<Timer>, sweep: Rc<IvSweep>, points: IvPoints, idx: usize) {
+    let sp_done = sp.clone();
+    sp.run(read_vi, move |res| {
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        if iv_sweep_record(&ui, &sp_done, &sweep, &points, idx, res) {
+            iv_sweep_step(ui_weak, sp_done, timer, sweep, points, idx + 1);
+        }
+    });
+}
+
+/// 記下第 `idx` 步量到的點並更新圖表；量不到就結束掃描並回傳 `false`
+fn iv_sweep_record(ui: &AppWindow, sp: &SharedPort, sweep: &IvSweep, points: &IvPoints, idx: usize, res: Result<Option<(f32, f32)>, scpi::ScpiError>) -> bool {
+    // 等讀值時被停掉了：交給下一步收尾 (顯示 Stopped)
+    if !ui.get_iv_running() { return true; }
+    match res {
+        Err(e) => { finish_iv_sweep(ui, sp, format!("Sweep aborted: {}", e)); return false; }
+        Ok(None) => { finish_iv_sweep(ui, sp, "Sweep aborted: no reading".to_string()); return false; }
+        Ok(Some((v, i))) => points.borrow_mut().push((sweep.setpoints[idx] as f32, v, i)),
+    }
+
+    let curve: Vec<(f32, f32)> = points.borrow().iter().map(|&(_, v, i)| (v, i)).collect();
+    ui.set_iv_curve(chart::xy_path(&curve, 750.0, 120.0).into());
+    // CV → CC 轉折點 (knee)：在曲線上打十字並標出座標
+    match iv_knee(&points.borrow(), ui.get_active_current_limit()) {
+        Some(k) => {
+            ui.set_iv_knee(chart::xy_marker(&curve, k, 750.0, 120.0).into());
+            ui.set_iv_knee_text(format!("Knee (CV → CC): {:.3} V, {:.4} A", curve[k].0, curve[k].1).into());
+        }
+        None => {
+            ui.set_iv_knee("".into());
+            ui.set_iv_knee_text("".into());
+        }
+    }
+    let (v, i) = curve[curve.len() - 1];
+    ui.set_iv_status(format!("Step {}/{}: {:.3} V, {:.4} A", idx + 1, sweep.setpoints.len(), v, i).into());
+    true
+}
+
+/// 找 CV → CC 的轉折點：第一個量到的電壓跟不上設定值、或電流頂到上限的點
+///
+/// 判斷方式與輪詢的 CC 偵測相同 (電流在上限 5% 內)；第一點就已經是 CC 時沒有轉折可標。
+fn iv_knee(points: &[(f32, f32, f32)], i_limit: f32) -> Option<usize> {
+    let limit = i_limit.abs();
+    let k = points.iter().position(|&(set, v, i)| {
+        let sagging = set - v > (set.abs() * 0.02).max(0.01);
+        let at_limit = limit > 0.0 && (i.abs() - limit).abs() < limit * 0.05 && i.abs() > 0.01;
+        sagging || at_limit
+    })?;
+    (k > 0).then_some(k)
+}
+
+/// 結束掃描：把電壓還原成掃描前的設定值
+fn finish_iv_sweep(ui: &AppWindow, sp: &SharedPort, status: String) {
+    restore_voltage_setpoint(ui, sp);
+    ui.set_iv_running(false);
+    ui.set_iv_status(status.into());
+}
+
+/// 把儀器的電壓設定改回 UI 上的設定值 (掃描、斜坡暫時改動過)
+fn restore_voltage_setpoint(ui: &AppWindow, sp: &SharedPort) {
+    let restore = format!("{} {}", scpi::cmds::SET_VOLT, scpi::format_number(ui.get_active_voltage_target() as f64));
+    sp.post(move |p| { let _ = scpi::send_command(p, &restore); });
+}
+
+/// 降壓斜坡每一步的間隔
+const RAMP_STEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 降壓斜坡第 `idx` 步：送出下一個電壓；走完後 OUTP OFF，再把電壓設定還原 (下次開輸出還是原本的值)
+fn ramp_down_step(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: Rc<Timer>, setpoints: Rc<Vec<f64>>, idx: usize) {
+    let Some(ui) = ui_weak.upgrade() else { return; };
+    // 被取消 (再按一次 Output、緊急斷電、斷線)：輸出已經由取消的那一方關掉
+    if !ui.get_ramp_down_running() {
+        restore_voltage_setpoint(&ui, &sp);
+        return;
+    }
+
+    let cmd = match setpoints.get(idx) {
+        Some(v) => format!("{} {}", scpi::cmds::SET_VOLT, scpi::format_number(*v)),
+        None => scpi::cmds::OUTP_OFF.to_string(),
+    };
+    let sp_done = sp.clone();
+    sp.run(move |p| scpi::send_command(p, &cmd), move |res| {
+        let Some(ui) = ui_weak.upgrade() else { return; };
+        let sp = sp_done;
+        // 等回應時被取消了：取消的那一方已經關掉輸出
+        if !ui.get_ramp_down_running() {
+            restore_voltage_setpoint(&ui, &sp);
+            return;
+        }
+        match res.and_then(|r| r) {
+            // 中途送不出去就直接關輸出，不要停在半路的電壓
+            Err(e) => {
+                ui.set_ramp_down_running(false);
+                send_urgent(ui_weak.clone(), &sp, scpi::cmds::OUTP_OFF);
+                ui.set_is_output_on(false);
+                restore_voltage_setpoint(&ui, &sp);
+                ui.set_message_text(format!("Ramp-down aborted, output forced off: {}", e).into());
+            }
+            Ok(_) if idx >= setpoints.len() => {
+                ui.set_ramp_down_running(false);
+                ui.set_is_output_on(false);
+                restore_voltage_setpoint(&ui, &sp);
+            }
+            Ok(_) => {
+                let t = timer.clone();
+                timer.start(TimerMode::SingleShot, RAMP_STEP_INTERVAL, move || {
+                    ramp_down_step(ui_weak.clone(), sp.clone(), t.clone(), setpoints.clone(), idx + 1);
+                });
+            }
+        }
+    });
+}
+
+/// 快速連線列的按鈕文字
+fn recent_labels(cfg: &config::AppConfig) -> slint::ModelRc<SharedString> {
+    let labels: Vec<SharedString> = cfg.recent.iter().map(|d| d.label().into()).collect();
+    Rc::new(VecModel::from(labels)).into()
+}
+
+/// 依設定檔重建 Preset 按鈕列表
+fn refresh_preset_names(model: &VecModel<SharedString>, cfg: &config::AppConfig) {
+    let names: Vec<SharedString> = cfg.presets.iter().map(|p| p.name.as_str().into()).collect();
+    model.set_vec(names);
+}
+
+// 🟢 [修改] 主邏輯函式
+/// 輪詢 Timer 會用到的共享狀態 (一起 clone 傳進 trigger_auto_poll)
+#[derive(Clone)]
+struct PollState {
+    capture: Rc<RefCell<capture::Capture>>,
+    reference: Rc<RefCell<RelativeRef>>,
+    golden: GoldenTrace,
+    history: Rc<RefCell<history::History>>,
+    observers: Rc<RefCell<measurement::Observers>>,
+    /// 摘要統計 (量測由 observers 送進來，保護跳脫在輪詢裡另外記)
+    session: Rc<RefCell<summary::SessionSummary>>,
+    calibration: Rc<RefCell<calibration::Calibration>>,
+    /// 目前連線用的 Profile (安全連鎖設定)
+    profile: Rc<RefCell<profile::DeviceProfile>>,
+    chart: Rc<RefCell<ChartBuffers>>,
+    overlay: Rc<RefCell<ChannelOverlay>>,
+}
+
+/// 多通道疊圖：每個通道一條 CHART_WIDTH 點的滾動 Buffer
+#[derive(Default)]
+struct ChannelOverlay {
+    channels: Vec<i32>,
+    /// true = 記錄電流，false = 電壓
+    current: bool,
+    data: Vec<VecDeque<f32>>,
+}
+
+impl ChannelOverlay {
+    /// 通道清單或量測項目變了就重新開始 (NaN = 還沒有資料，不畫)
+    fn configure(&mut self, channels: Vec<i32>, current: bool) {
+        if channels == self.channels && current == self.current { return; }
+        self.data = channels.iter().map(|_| std::iter::repeat_n(f32::NAN, CHART_WIDTH).collect()).collect();
+        self.channels = channels;
+        self.current = current;
+    }
+
+    fn push(&mut self, values: &[f32]) {
+        for (buf, &v) in self.data.iter_mut().zip(values) {
+            buf.pop_front();
+            buf.push_back(v);
+        }
+    }
+}
+
+/// 解析通道清單 ("1, 2" / "1 3")；只收 1 ~ 8，重複的略過
+fn parse_channels(text: &str) -> Vec<i32> {
+    let mut channels = Vec::new();
+    for ch in text.split([',', ' ']).filter_map(|s| s.trim().parse::<i32>().ok()) {
+        if (1..=8).contains(&ch) && !channels.contains(&ch) { channels.push(ch); }
+    }
+    channels
+}
+
+/// 多通道疊圖：曲線與圖例；共用刻度時才畫格線 (各自縮放時格線沒有意義)
+fn render_overlay(ui: &AppWindow, ov: &ChannelOverlay) {
+    const VIEW_W: f32 = 750.0; // 對應 TrendChart 預設的 view-width / view-height
+    const VIEW_H: f32 = 120.0;
+    let unit = if ov.current { "A" } else { "V" };
+    let (paths, shared) = chart::trace_paths(&ov.data, ui.get_overlay_shared_scale(), VIEW_W, VIEW_H);
+    let colors = chart::trace_colors(&ui.get_theme_mode());
+    let traces: Vec<ChartTrace> = ov.channels.iter().zip(&ov.data).zip(paths).enumerate().map(|(k, ((ch, buf), path))| {
+        let latest = buf.back().copied().filter(|v| !v.is_nan());
+        ChartTrace {
+            path: path.into(),
+            color: Color::from_argb_encoded(0xff00_0000 | colors[k % colors.len()]),
+            label: format!("CH{} {}", ch, latest.map(|v| scpi::format_with_unit(v as f64, unit)).unwrap_or("---".into())).into(),
+        }
+    }).collect();
+    ui.set_overlay_traces(Rc::new(VecModel::from(traces)).into());
+
+    let grid = shared.map(|s| chart::grid(s, unit, VIEW_W, VIEW_H)).unwrap_or_default();
+    let labels: Vec<GridLabel> = grid.labels.iter().map(|l| GridLabel { y: l.y, left: l.text.clone().into(), right: "".into() }).collect();
+    ui.set_overlay_grid(grid.lines.into());
+    ui.set_overlay_baseline(grid.baseline.into());
+    ui.set_overlay_labels(Rc::new(VecModel::from(labels)).into());
+}
+
+/// 趨勢圖的滾動 Buffer (CHART_WIDTH 點)；放在共享狀態裡，重新連線時才保留得住
+#[derive(Default)]
+struct ChartBuffers {
+    v: VecDeque<f32>,
+    i: VecDeque<f32>,
+    /// 每次取樣的實際時間 (Timer 不保證準時，FFT 需要真實取樣率)
+    t: VecDeque<Instant>,
+}
+
+impl ChartBuffers {
+    /// 清成一整排 0 (圖表從右邊開始長出來)
+    fn reset(&mut self) {
+        self.v = std::iter::repeat_n(0.0, CHART_WIDTH).collect();
+        self.i = std::iter::repeat_n(0.0, CHART_WIDTH).collect();
+        self.t.clear();
+    }
+
+    fn push(&mut self, v: f32, i: f32, t: Instant) {
+        self.v.pop_front();
+        self.v.push_back(v);
+        self.i.pop_front();
+        self.i.push_back(i);
+        if self.t.len() == CHART_WIDTH { self.t.pop_front(); }
+        self.t.push_back(t);
+    }
+
+    /// 斷線點：NaN 讓曲線在這裡斷開
+    fn mark_gap(&mut self, t: Instant) {
+        if !self.v.is_empty() { self.push(f32::NAN, f32::NAN, t); }
+    }
+}
+
+/// 輪詢在背景執行緒上做的那一段 (安全連鎖 + MEAS:ALL?) 的結果
+struct PollReply {
+    /// 安全連鎖狀態 (None = Profile 沒定義)
+    interlock: Option<bool>,
+    settling: bool,
+    rtt: Duration,
+    res: Result<Option<String>, scpi::ScpiError>,
+    /// 效率量測 (輸入功率, 輸出功率)；沒開或穩定時間內是 None
+    efficiency: Option<(Option<f32>, Option<f32>)>,
+    /// 多通道疊圖各通道的讀值；沒開或穩定時間內是 None
+    overlay: Option<Vec<f32>>,
+}
+
+/// 輪詢 Timer 跨筆保留的狀態 (讀值回來時在事件迴圈上更新)
+struct PollLoop {
+    interval_ms: u64,
+    max_interval_ms: u64,
+    current_interval_ms: u64,
+    stable_count: u32,
+    // 判斷讀值是否「凍結」：上一筆原始回應與連續相同的次數
+    last_raw: Option<String>,
+    identical_count: u32,
+    throttle: chart::RenderThrottle,
+    over_power: u32,
+    /// 連續讀不到回應的次數 (到 RECONNECT_AFTER_FAILURES 就當作斷線)
+    failures: u32,
+    // 一次 MEAS:ALL? 來回的時間 (指數平均)，幫使用者找安全的輪詢間隔
+    rtt_avg_ms: Option<f64>,
+    // 從回應的小數位數推測儀器解析度，讀值照這個位數顯示
+    resolution: scpi::Resolution,
+    timer: std::rc::Weak<RefCell<Timer>>,
+}
+
+fn trigger_auto_poll(ui_weak: slint::Weak<AppWindow>, sp: SharedPort, timer: Rc<RefCell<Timer>>, state: PollState) {
+    let ui = ui_weak.unwrap();
+
+    // 1. 初始化歷史資料 Buffer (勾了 Keep chart 就沿用上次的資料)
+    if !ui.get_keep_chart() || state.chart.borrow().v.is_empty() {
+        state.chart.borrow_mut().reset();
+    }
+
+    // 2. 讀取時間並限制最小間隔 (避免過快導致塞車)
+    let raw_interval = ui.get_polling_interval().parse::<u64>().unwrap_or(100);
+    // 最小間隔由 Profile 決定 (RS-232 物理極限預設 200ms，USB-CDC 可以更快)
+    let min_interval = state.profile.borrow().min_poll_interval_ms.max(1);
+    let interval_ms = raw_interval.max(min_interval);
+
+    if raw_interval < min_interval {
+        ui.set_polling_interval(format!("{}", interval_ms).into());
+    }
+
+    // 更新圖表時間標籤
+    let total_seconds = (interval_ms as f64 * CHART_WIDTH as f64) / 1000.0;
+    ui.set_chart_duration(format!("{:.1}s", total_seconds).into());
+
+    // 自適應輪詢：穩定時逐步放慢 (最多 8 倍、5 秒)，有變化立刻回到設定的間隔
+    let poll = Rc::new(RefCell::new(PollLoop {
+        interval_ms,
+        max_interval_ms: (interval_ms * 8).min(5000).max(interval_ms),
+        current_interval_ms: interval_ms,
+        stable_count: 0,
+        last_raw: None,
+        identical_count: 0,
+        throttle: chart::RenderThrottle::new(chart::MIN_RENDER_INTERVAL),
+        over_power: 0,
+        failures: 0,
+        rtt_avg_ms: None,
+        resolution: scpi::Resolution::default(),
+        timer: Rc::downgrade(&timer),
+    }));
+
+    // 🟢 [修改] 使用變數 interval_ms
+    timer.borrow().start(TimerMode::Repeated, Duration::from_millis(interval_ms), move || {
+        let ui = ui_weak.unwrap();
+        // 儀器在等觸發：MEAS? 會把它中斷，先不輪詢
+        if ui.get_inst_trigger_armed() { return; }
+        // 上一筆還在背景讀，或序列埠正被手動指令 / 循環使用中：這次取樣跳過
+        if sp.try_borrow_mut().is_err() { return; }
+
+        // --- A. SCPI 通訊 (背景執行緒) ---
+        let profile = state.profile.borrow().clone();
+        let retries = ui.get_poll_retries().trim().parse::<u32>().unwrap_or(0);
+        let control_ch = ui.get_control_channel();
+        let efficiency = ui.get_efficiency_enabled().then(|| (ui.get_efficiency_in_ch(), ui.get_efficiency_out_ch()));
+        let overlay = ui.get_overlay_enabled().then(|| {
+            let mut ov = state.overlay.borrow_mut();
+            ov.configure(parse_channels(&ui.get_overlay_channels()), ui.get_overlay_quantity() == "Current");
+            (ov.channels.clone(), ov.current)
+        });
+        let (ui_done, sp_done, state, poll) = (ui_weak.clone(), sp.clone(), state.clone(), poll.clone());
+        sp.run(move |p| {
+            // 安全連鎖：每次取樣都檢查，沒滿足就關輸出
+            let interlock = profile.interlock_ok(p.port.as_mut());
+            let settling = p.settling();
+            // 超時或只收到半行 (轉接器偶爾掉資料) 時重送，不拿截斷的數字去解析，也不讓圖表變成水平線
+            let sent_at = Instant::now();
+            let res = scpi::send_command_retry(p, scpi::cmds::READ_ALL, retries, POLL_RETRY_BACKOFF);
+            let rtt = sent_at.elapsed();
+            // 穩定時間內跳過：單次量測會等到穩定，輪詢的這一輪會被拖很久
+            let (efficiency, overlay) = if settling { (None, None) } else {
+                (efficiency.map(|(in_ch, out_ch)| read_efficiency(p, in_ch, out_ch, control_ch)),
+                 overlay.map(|(channels, current)| read_overlay(p, &channels, current, control_ch)))
+            };
+            PollReply { interlock, settling, rtt, res, efficiency, overlay }
+        }, move |reply| {
+            // 讀值回來前就斷線了：不用處理
+            let (Some(ui), Ok(reply)) = (ui_done.upgrade(), reply) else { return; };
+            poll_step(&ui, &sp_done, &state, &mut poll.borrow_mut(), reply);
+        });
+    });
+}
+
+/// 一筆輪詢讀值回到事件迴圈後：更新讀數、保護檢查、圖表與觸發擷取
+fn poll_step(ui: &AppWindow, sp: &SharedPort, state: &PollState, lp: &mut PollLoop, reply: PollReply) {
+    let PollState { capture, reference, golden, history, observers, session, profile: _, calibration, chart, overlay } = state;
+    let Ok(mut chart_ref) = chart.try_borrow_mut() else { return; };
+    let buffers = &mut *chart_ref;
+    let PollReply { interlock, settling, rtt, res, efficiency, overlay: overlay_values } = reply;
+
+    // 暫存目前的數值
+    let mut curr_v = 0.0f32;
+    let mut curr_i = 0.0f32;
+    let mut read_success = false;
+    let mut curr_mode = "";
+
+    if enforce_interlock(ui, sp, interlock) {
+        session.borrow_mut().trip(chrono::Local::now(), "Interlock open");
+    }
+    if res.is_ok() {
+        let ms = rtt.as_secs_f64() * 1000.0;
+        let avg = lp.rtt_avg_ms.map_or(ms, |a| a * 0.8 + ms * 0.2);
+        lp.rtt_avg_ms = Some(avg);
+        ui.set_poll_rtt_ms(avg as f32);
+    }
+    // 讀取失敗時在狀態列顯示原因 (timeout / port error)，圖表照樣用上一筆填補
+    if let Err(ref e) = res {
+        ui.set_message_text(e.to_string().into());
+        handle_link_lost(ui, e);
+    }
+    // 線拔掉時有些驅動程式不會回報錯誤，只會一直超時：連續幾次讀不到就當作斷線
+    if matches!(res, Ok(Some(_))) {
+        lp.failures = 0;
+    } else {
+        lp.failures += 1;
+        if lp.failures >= RECONNECT_AFTER_FAILURES {
+            lp.failures = 0;
+            let reason = res.as_ref().err().map_or("No response".to_string(), |e| e.to_string());
+            connection_lost(ui, format!("{} ({} polls in a row)", reason, RECONNECT_AFTER_FAILURES));
+        }
+    }
+
+    if let Ok(Some(raw_res)) = res {
+        // 連線正常、資料也有在傳，但數值完全沒變：儀器可能卡住或回傳快取值
+        // (讀失敗時圖表會用上一筆填補，這種情況從圖上看不出來)
+        if lp.last_raw.as_deref() == Some(raw_res.as_str()) {
+            lp.identical_count += 1;
+        } else {
+            lp.identical_count = 0;
+            lp.last_raw = Some(raw_res.clone());
+        }
+        ui.set_reading_stale(lp.identical_count >= STALE_POLLS && ui.get_is_output_on());
+        ui.set_stale_polls(lp.identical_count as i32);
+
+        // 讀不懂的回應當作讀取失敗 (圖表沿用上一筆)，不拿 0 去畫
+        if let Some((v, i, inst_power)) = scpi::parse_meas_all(&raw_res) {
+            lp.resolution.observe(&raw_res);
+            let resolution = lp.resolution;
+            // 1. 解析數值 (原始值，記錄 / 觸發 / CC 判斷都用這個)
+            curr_v = v as f32;
+            curr_i = i as f32;
+            // 校正：之後的顯示 / 記錄 / 觸發都用修正後的值，原始值留給兩點校正用
+            ui.set_last_raw_v(curr_v);
+            ui.set_last_raw_i(curr_i);
+            let cal = *calibration.borrow();
+            curr_v = cal.voltage.apply(curr_v);
+            curr_i = cal.current.apply(curr_i);
+
+            // 2. 更新文字 UI (只有讀成功才更新文字；相對模式顯示與參考點的差)
+            let mut r = reference.borrow_mut();
+            r.last_raw = Some((curr_v, curr_i));
+            let (ref_v, ref_i) = r.offset(ui);
+            // 小數值自動換成 mV / mA / µA 顯示
+            if r.reference.is_some() && ui.get_relative_mode() {
+                ui.set_voltage_reading(signed_with_unit(curr_v - ref_v, "V", resolution.voltage).into());
+                ui.set_current_reading(signed_with_unit(curr_i - ref_i, "A", resolution.current).into());
+            } else {
+                ui.set_voltage_reading(scpi::format_with_resolution(curr_v as f64, "V", resolution.voltage).into());
+                ui.set_current_reading(scpi::format_with_resolution(curr_i as f64, "A", resolution.current).into());
+            }
+
+            // 3. 更新功率 UI
+            // 負功率 = 儀器正在吸收 (sink) 電流
+            // 儀器有回功率就用它的 (它自己量的比較準)；有套校正時改用校正後的 V × I 才一致
+            let power = match inst_power {
+                Some(p) if cal.is_identity() => p as f32,
+                _ => curr_v * curr_i,
+            };
+            ui.set_power_reading(format!("{:.2}", power).into());
+            ui.set_is_sinking(power < -0.001);
+
+            // 軟體過功率保護：電壓、電流各自都沒超標，但乘起來可能燒掉 DUT
+            let limit = ui.get_power_limit();
+            if limit > 0.0 && ui.get_is_output_on() && power > limit {
+                lp.over_power += 1;
+            } else {
+                lp.over_power = 0;
+            }
+            if lp.over_power >= POWER_TRIP_POLLS {
+                lp.over_power = 0;
+                sp.post_urgent(|p| { let _ = scpi::send_command(p, scpi::cmds::OUTP_OFF); });
+                ui.set_is_output_on(false);
+                let reason = format!("Power limit exceeded ({:.2} W > {} W)", power, scpi::format_number(limit as f64));
+                session.borrow_mut().trip(chrono::Local::now(), &reason);
+                ui.set_message_text(format!("{}: output turned off", reason).into());
+            }
+
+            // 🟢 [修正] CC/CV 智能判斷邏輯
+            // 1. 取得生效的電流上限 (Active Limit)
+            let i_limit_active = ui.get_active_current_limit();
+
+            // 2. 判斷是否 Output ON (沒開電就不顯示模式)
+            let output_on = ui.get_is_output_on();
+
+            // 3. 判斷 CC (電流接近上限 95% 且大於 10mA 避免雜訊)
+            // 可吸收電流 (雙象限) 的電源會回報負電流，用絕對值跟上限比
+            let i_abs = curr_i.abs();
+            let is_cc = (i_abs - i_limit_active.abs()).abs() < (i_limit_active.abs() * 0.05) && i_abs > 0.01;
+
+            let mode = if !output_on {
+                "" // 沒開電，燈號熄滅
+            } else if is_cc {
+                "CC" // 限流模式
+            } else {
+                "CV" // 定壓模式
+            };
+            ui.set_psu_mode(mode.into());
+            ui.set_mode_explanation(mode_explanation(mode, curr_v, curr_i, ui.get_active_voltage_target(), i_limit_active).into());
+            curr_mode = mode;
+
+            // 🟢 [重點修改 2] 標記讀取成功
+            read_success = true;
+        }
+    }
+
+    // --- A2. 效率量測 (兩個通道分別當 DUT 的輸入與輸出，在背景的同一輪讀完) ---
+    if ui.get_efficiency_enabled() {
+        if let Some((p_in, p_out)) = efficiency {
+            let fmt = |v: Option<f32>| v.map(|w| format!("{:.2}", w)).unwrap_or("---".into());
+            ui.set_power_in_reading(fmt(p_in).into());
+            ui.set_power_out_reading(fmt(p_out).into());
+
+            // 輸入功率太小時不計算，避免除以 0 出現誇張數值
+            let eff = match (p_in, p_out) {
+                (Some(pi), Some(po)) if pi.abs() > 0.001 => format!("{:.1}%", po / pi * 100.0),
+                _ => "---".to_string(),
+            };
+            ui.set_efficiency_reading(eff.into());
+        }
+    }
+
+    // --- A3. 多通道疊圖 (背景的同一輪逐一切通道量測) ---
+    if let Some(values) = overlay_values.filter(|_| ui.get_overlay_enabled()) {
+        overlay.borrow_mut().push(&values);
+    }
+
+    ui.set_settling(settling);
+    // 暫態中的讀值和讀取失敗一樣處理：圖表延續上一筆，歷史 / 統計不記
+    let read_success = read_success && !settling;
+
+    // --- B. 圖表更新邏輯 ---
+
+    // 🟢 [重點修改 3] 如果讀取失敗 (塞車或超時)，使用「上一次的值」填補
+    // 這樣圖表會變成「水平線」繼續往左跑，而不會掉到 0，也不會因為沒 push 導致不同步
+    if !read_success {
+         // 拿 Buffer 最後一筆資料，如果 Buffer 是空的就用 0.0
+         curr_v = *buffers.v.back().unwrap_or(&0.0);
+         curr_i = *buffers.i.back().unwrap_or(&0.0);
+    }
+
+    let (prev_v, prev_i) = (*buffers.v.back().unwrap_or(&0.0), *buffers.i.back().unwrap_or(&0.0));
+
+    // 🟢 [重點修改 4] 無條件推進 Buffer (保證 V 和 I 永遠同步)
+    // 不管 read_success 是 true 還是 false，這裡都要執行
+
+    let now = Instant::now();
+    buffers.push(curr_v, curr_i, now);
+    let (history_v, history_i, history_t) = (&buffers.v, &buffers.i, &buffers.t);
+
+    // 完整歷史只記真的讀到的點 (填補的水平線不算)
+    if read_success {
+        history.borrow_mut().push(curr_v, curr_i, now);
+        let m = measurement::Measurement::new(curr_v, curr_i, curr_mode, logger::wall_clock(now));
+        // 每個 Sink (HTTP、CSV、迷你視窗...) 各自處理，失敗的只回報原因
+        if let Ok(mut obs) = observers.try_borrow_mut() {
+            if let Some(e) = obs.publish(&m).pop() { ui.set_message_text(e.into()); }
+        }
+    }
+
+    // 自適應輪詢：連續 5 筆變化都很小就把間隔加倍
+    let next_interval_ms = if !ui.get_adaptive_polling() {
+        lp.interval_ms
+    } else if !read_success {
+        lp.current_interval_ms
+    } else if is_significant_change(prev_v, curr_v) || is_significant_change(prev_i, curr_i) {
+        lp.stable_count = 0;
+        lp.interval_ms
+    } else {
+        lp.stable_count += 1;
+        if lp.stable_count >= 5 {
+            lp.stable_count = 0;
+            (lp.current_interval_ms * 2).min(lp.max_interval_ms)
+        } else {
+            lp.current_interval_ms
+        }
+    };
+    if next_interval_ms != lp.current_interval_ms {
+        lp.current_interval_ms = next_interval_ms;
+        if let Some(t) = lp.timer.upgrade() {
+            if let Ok(t) = t.try_borrow() { t.set_interval(Duration::from_millis(lp.current_interval_ms)); }
+        }
+    }
+    if ui.get_adaptive_polling() && history_t.len() > 1 {
+        // 取樣間隔不固定，用實際時間戳換算圖表涵蓋的時間
+        let span = history_t[history_t.len() - 1].duration_since(history_t[0]).as_secs_f64();
+        ui.set_chart_duration(format!("~{:.1}s", span).into());
+    }
+
+    // 3~4. 趨勢圖 (視窗太小、圖表收起來時不產生 SVG，省 CPU；重播中圖表讓給重播)
+    // 重畫有節流：buffer 很大時不會每次輪詢都重建整條 SVG 字串
+    let render_started = Instant::now();
+    let redraw = ui.get_chart_visible() && !ui.get_replay_loaded() && lp.throttle.due(render_started);
+    if redraw {
+        render_trend(ui, history_v, history_i, reference, golden);
+        render_markers(ui, history_v.len(), history_t, &history.borrow().markers);
+    }
+    // 疊圖在 Channels 分頁，不受主圖表收起來影響
+    if ui.get_overlay_enabled() && lp.throttle.due(render_started) {
+        render_overlay(ui, &overlay.borrow());
+    }
+
+    // 5. 觸發擷取 (邊緣偵測 + 寫 CSV)
+    let clock_offset = ui.get_clock_offset_known().then(|| ui.get_clock_offset() as f64);
+    let cal = Some(*calibration.borrow()).filter(|c| !c.is_identity());
+    let sample = capture::Sample { history_v, history_i, history_t, mode: curr_mode, clock_offset, calibration: cal };
+    match capture.borrow_mut().on_sample(&sample) {
+        Ok(Some(msg)) => {
+            ui.set_trigger_armed(false);
+            ui.set_capture_running(true);
+            ui.set_trigger_status(msg.into());
+        }
+        Ok(None) => {}
+        Err(e) => ui.set_trigger_status(format!("Capture Error: {}", e).into()),
+    }
+
+    // 6. 漣波頻譜 (只用真的取樣過的點)
+    if redraw && ui.get_show_spectrum() {
+        update_spectrum(ui, history_v, history_t, chart_area(ui).0);
+    }
+    if redraw { lp.throttle.record(render_started); }
+}
+
+/// 主畫面趨勢圖的標記 (`times` 對齊長度 `len` 的 Buffer 尾端；捲出畫面的不畫)
+fn render_markers(ui: &AppWindow, len: usize, times: &VecDeque<Instant>, markers: &[history::Marker]) {
+    let (chart_w, chart_h) = chart_area(ui);
+    let last = (len.max(2) - 1) as f32;
+    let placed: Vec<(f32, &str)> = markers.iter()
+        .filter_map(|m| chart::marker_index(times, m.t).map(|k| ((len - times.len() + k) as f32 / last * chart_w, m.note.as_str())))
+        .collect();
+    let xs: Vec<f32> = placed.iter().map(|&(x, _)| x).collect();
+    ui.set_chart_markers_path(chart::marker_path(&xs, chart_h).into());
+    ui.set_chart_markers(marker_labels(&placed));
+}
+
+/// 標記文字 → Slint model
+fn marker_labels(placed: &[(f32, &str)]) -> slint::ModelRc<ChartMarker> {
+    let labels: Vec<ChartMarker> = placed.iter().map(|&(x, text)| ChartMarker { x, text: text.into() }).collect();
+    Rc::new(VecModel::from(labels)).into()
+}
+
+/// 重播目前位置：趨勢圖顯示到這一筆為止，並列出原始時間戳
+fn show_replay_frame(ui: &AppWindow, r: &replay::Replay, reference: &RefCell<RelativeRef>, golden: &GoldenTrace) {
+    if ui.get_chart_visible() {
+        let (v, i) = r.window(CHART_WIDTH);
+        render_trend(ui, &v, &i, reference, golden);
+        // 即時資料的標記對不上重播的時間軸
+        render_markers(ui, 0, &VecDeque::new(), &[]);
+    }
+    let s = r.current();
+    ui.set_replay_status(format!("{}  {} / {}  {}  [{}/{}]",
+        s.ts.format(logger::TS_FORMAT), scpi::format_with_unit(s.v as f64, "V"), scpi::format_with_unit(s.i as f64, "A"),
+        s.mode, r.position() + 1, r.len()).into());
+    ui.set_replay_progress(r.progress());
+    ui.set_replay_playing(r.playing);
+}
+
+/// 主畫面趨勢圖：曲線、參考曲線、格線與標籤
+fn render_trend(ui: &AppWindow, history_v: &VecDeque<f32>, history_i: &VecDeque<f32>, reference: &RefCell<RelativeRef>, golden: &GoldenTrace) {
+    // 3. 生成 SVG (曲線 + 格線共用同一組刻度)
+    // 座標系跟著圖表實際大小走，視窗縮放後線條與標籤才不會變形
+    let (chart_w, chart_h) = chart_area(ui);
+    ui.set_chart_view_width(chart_w);
+    ui.set_chart_view_height(chart_h);
+
+    // 相對模式 + 「Δ on chart」時畫與參考點的差；歷史資料本身保持原始值
+    let relative_chart = ui.get_relative_chart() && ui.get_relative_mode() && reference.borrow().reference.is_some();
+    let (ref_v, ref_i) = if relative_chart { reference.borrow().offset(ui) } else { (0.0, 0.0) };
+    let shift = |buf: &VecDeque<f32>, by: f32| -> VecDeque<f32> { buf.iter().map(|x| x - by).collect() };
+    let fit = |buf: &VecDeque<f32>| if relative_chart { chart::Scale::symmetric(buf) } else { chart::Scale::auto(buf) };
+
+    let plot_v = shift(history_v, ref_v);
+    let plot_i = shift(history_i, ref_i);
+    let mut scale_v = fit(&plot_v);
+    let mut scale_i = fit(&plot_i);
+
+    // 匯入的參考曲線 (golden run)：和即時曲線共用刻度，才能直接比較
+    let golden_plot = golden.borrow().as_ref().map(|(gv, gi)| (shift(gv, ref_v), shift(gi, ref_i)));
+    if let Some((gv, gi)) = &golden_plot {
+        scale_v = scale_v.union(fit(gv));
+        scale_i = scale_i.union(fit(gi));
+    }
+    // 容許範圍色帶 (例如 12 V ±2%)：刻度要涵蓋整條色帶，偏出去才看得出來
+    let band = ui.get_band_enabled().then(|| {
+        let nominal = scpi::parse_number(&ui.get_band_nominal()).unwrap_or(f64::NAN) as f32;
+        let tol = (scpi::parse_number(&ui.get_band_tolerance()).unwrap_or(f64::NAN) as f32 / 100.0).abs();
+        let current = ui.get_band_quantity() == "Current";
+        let by = if current { ref_i } else { ref_v };
+        let span = nominal.abs() * tol;
+        (current, nominal - span - by, nominal + span - by)
+    }).filter(|&(_, lo, hi)| lo.is_finite() && hi.is_finite());
+    if let Some((current, lo, hi)) = band {
+        let covered = chart::Scale { min: lo, max: hi };
+        if current { scale_i = scale_i.union(covered); } else { scale_v = scale_v.union(covered); }
+    }
+
+    // 手動刻度：鎖住 Y 軸，數值不會因為自動縮放而跳動 (範圍無效的那條線維持自動)
+    if ui.get_axis_manual() {
+        let bound = |s: slint::SharedString| s.trim().parse::<f32>().unwrap_or(f32::NAN);
+        if let Some(s) = chart::Scale::fixed(bound(ui.get_axis_v_min()), bound(ui.get_axis_v_max())) { scale_v = s; }
+        if let Some(s) = chart::Scale::fixed(bound(ui.get_axis_i_min()), bound(ui.get_axis_i_max())) { scale_i = s; }
+    }
+    let (ref_path_v, ref_path_i) = match &golden_plot {
+        Some((gv, gi)) => (chart::svg_path_scaled(gv, scale_v, chart_w, chart_h), chart::svg_path_scaled(gi, scale_i, chart_w, chart_h)),
+        None => (String::new(), String::new()),
+    };
+    let path_v_str = chart::svg_path_scaled(&plot_v, scale_v, chart_w, chart_h);
+    let path_i_str = chart::svg_path_scaled(&plot_i, scale_i, chart_w, chart_h);
+    let (band_path, outside) = match band {
+        Some((current, lo, hi)) => {
+            let (plot, scale) = if current { (&plot_i, scale_i) } else { (&plot_v, scale_v) };
+            // 最新一筆真的讀值在色帶外就變紅
+            let last = plot.iter().rev().find(|x| !x.is_nan()).copied();
+            (chart::band_path(scale, lo, hi, chart_w, chart_h), last.is_some_and(|x| x < lo || x > hi))
+        }
+        None => (String::new(), false),
+    };
+    // 格線跟著電壓刻度，右側標出同高度的電流值
+    let grid = chart::grid(scale_v, "V", chart_w, chart_h);
+    let labels = grid_labels(&grid, scale_i, chart_h);
+
+    // 4. 更新 UI
+    ui.set_chart_data_v(path_v_str.into());
+    ui.set_chart_data_i(path_i_str.into());
+    ui.set_chart_ref_v(ref_path_v.into());
+    ui.set_chart_ref_i(ref_path_i.into());
+    ui.set_chart_grid(grid.lines.into());
+    ui.set_chart_baseline(grid.baseline.into());
+    ui.set_chart_labels(labels);
+    ui.set_chart_band(band_path.into());
+    ui.set_band_outside(outside);
+}
+
+/// 格線標籤：左邊電壓 (格線本身的值)，右邊同高度的電流
+fn grid_labels(grid: &chart::Grid, scale_i: chart::Scale, height: f32) -> slint::ModelRc<GridLabel> {
+    let labels: Vec<GridLabel> = grid.labels.iter().map(|l| GridLabel {
+        y: l.y,
+        left: l.text.clone().into(),
+        right: scpi::format_with_unit_digits(scale_i.value_at(l.y, height) as f64, "A", 3).into(),
+    }).collect();
+    Rc::new(VecModel::from(labels)).into()
+}
+
+/// 相對模式的差值：正數也帶 + 號
+fn signed_with_unit(delta: f32, unit: &str, decimals: Option<usize>) -> String {
+    let text = scpi::format_with_resolution(delta as f64, unit, decimals);
+    if delta >= 0.0 { format!("+{}", text) } else { text }
+}
+
+/// 自適應輪詢用：變化超過 0.5% (至少 1 mV / 1 mA) 才算「有在變」
+fn is_significant_change(prev: f32, curr: f32) -> bool {
+    (curr - prev).abs() > (prev.abs() * 0.005).max(0.001)
+}
+
+/// 主畫面趨勢圖目前的繪製大小 (邏輯像素)；版面還沒排好時用預設的 750 x 120
+fn chart_area(ui: &AppWindow) -> (f32, f32) {
+    let w = ui.get_chart_area_width();
+    let h = ui.get_chart_area_height();
+    if w > 1.0 && h > 1.0 { (w, h) } else { (750.0, 120.0) }
+}
+
+/// 依目前縮放範圍重畫分離出來的大圖
+fn refresh_chart_window(win: &ChartWindow, hist: &history::History, view: &history::View) {
+    const VIEW_W: f32 = 1000.0; // 對應 ChartWindow 的 view-width / view-height
+    const VIEW_H: f32 = 400.0;
+
+    let (start, end) = view.range(hist.len());
+    if hist.is_empty() || end <= start {
+        win.set_path_v("".into());
+        win.set_path_i("".into());
+        win.set_info_text("No data yet".into());
+        return;
+    }
+    let v: Vec<f32> = hist.v.range(start..end).copied().collect();
+    let i: Vec<f32> = hist.i.range(start..end).copied().collect();
+    let (v, i) = (chart::decimate(&v, chart::MAX_PATH_POINTS), chart::decimate(&i, chart::MAX_PATH_POINTS));
+
+    let scale_v = chart::Scale::auto(&v);
+    let scale_i = chart::Scale::auto(&i);
+    let grid = chart::grid(scale_v, "V", VIEW_W, VIEW_H);
+    win.set_path_v(chart::svg_path_scaled(&v, scale_v, VIEW_W, VIEW_H).into());
+    win.set_path_i(chart::svg_path_scaled(&i, scale_i, VIEW_W, VIEW_H).into());
+    win.set_labels(grid_labels(&grid, scale_i, VIEW_H));
+    win.set_path_grid(grid.lines.into());
+    win.set_path_baseline(grid.baseline.into());
+
+    // 只畫落在目前範圍內的標記
+    let span = (end - start).max(2) - 1;
+    let placed: Vec<(f32, &str)> = hist.markers.iter()
+        .filter_map(|m| chart::marker_index(&hist.t, m.t).filter(|k| (start..end).contains(k)).map(|k| ((k - start) as f32 / span as f32 * VIEW_W, m.note.as_str())))
+        .collect();
+    let xs: Vec<f32> = placed.iter().map(|&(x, _)| x).collect();
+    win.set_path_markers(chart::marker_path(&xs, VIEW_H).into());
+    win.set_markers(marker_labels(&placed));
+
+    let (t0, t1) = (hist.t[start], hist.t[end - 1]);
+    win.set_info_text(format!(
+        "{} samples, {:.1}s ({} - {})",
+        end - start,
+        t1.duration_since(t0).as_secs_f32(),
+        logger::wall_clock(t0).format("%H:%M:%S"),
+        logger::wall_clock(t1).format("%H:%M:%S"),
+    ).into());
+}
+
+/// 對電壓歷史做 FFT，更新頻譜圖與主要漣波頻率
+fn update_spectrum(ui: &AppWindow, history_v: &VecDeque<f32>, history_t: &VecDeque<Instant>, chart_w: f32) {
+    let (Some(first), Some(last)) = (history_t.front(), history_t.back()) else { return; };
+    // 斷線缺口 (NaN) 之前的資料不算，只分析最後一段連續取樣
+    let tail: Vec<f32> = history_v.iter().skip(history_v.len() - history_t.len()).copied().collect();
+    let start = tail.iter().rposition(|x| x.is_nan()).map_or(0, |k| k + 1);
+    let samples = &tail[start..];
+    let first = history_t.get(start).unwrap_or(first);
+    let span = last.duration_since(*first).as_secs_f32();
+    if samples.len() < 4 || span <= 0.0 { return; }
+    let sample_rate = (samples.len() - 1) as f32 / span;
+
+    let (mags, ripple) = spectrum::analyze(samples, sample_rate);
+
+    // generate_svg_path 的刻度最小是 1.0，漣波通常只有 mV 等級，先正規化到 0~1
+    let peak = mags.iter().fold(0.0f32, |a, &b| a.max(b));
+    let mags: VecDeque<f32> = mags.iter().map(|m| if peak > 0.0 { m / peak } else { 0.0 }).collect();
+    ui.set_chart_data_spectrum(chart::generate_svg_path(&mags, chart_w, 60.0).into());
+    ui.set_ripple_text(match ripple {
+        Some(r) => format!("{:.2} Hz, {:.1} mV", r.freq_hz, r.amplitude * 1000.0),
+        None => "---".to_string(),
+    }.into());
 }
\ No newline at end of file
@@ -0,0 +1,77 @@
+//! # Measurement Events
+//!
+//! Structured per-poll measurements and a small registry of sinks they fan
+//! out to, so tools embedding this crate can consume acquired data without
+//! the Slint UI. The app itself registers its sinks (the HTTP endpoint, the
+//! continuous CSV log, the mini monitor) the same way.
+
+use chrono::{DateTime, Local};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// 一筆輪詢到的量測
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub v: f32,
+    pub i: f32,
+    pub p: f32,
+    /// "CV" / "CC"；Output 關閉時為空字串
+    pub mode: String,
+    pub ts: DateTime<Local>,
+}
+
+impl Measurement {
+    pub fn new(v: f32, i: f32, mode: &str, ts: DateTime<Local>) -> Self {
+        Self { v, i, p: v * i, mode: mode.to_string(), ts }
+    }
+}
+
+/// 收量測的地方 (CSV、HTTP、畫面...)
+pub trait MeasurementSink {
+    /// 寫入一筆；失敗時回傳原因 (不影響其他 Sink)
+    fn record(&mut self, m: &Measurement) -> Result<(), String>;
+
+    /// 關閉中的 Sink 不會收到量測
+    fn enabled(&self) -> bool { true }
+}
+
+/// 一般的 closure 也可以直接當 Sink
+impl<F: FnMut(&Measurement)> MeasurementSink for F {
+    fn record(&mut self, m: &Measurement) -> Result<(), String> {
+        self(m);
+        Ok(())
+    }
+}
+
+/// 共用的 Sink (UI 那邊也要開關它)
+impl<S: MeasurementSink> MeasurementSink for Rc<RefCell<S>> {
+    fn record(&mut self, m: &Measurement) -> Result<(), String> { self.borrow_mut().record(m) }
+
+    fn enabled(&self) -> bool { self.borrow().enabled() }
+}
+
+pub type Observer = Box<dyn MeasurementSink>;
+
+/// 每筆量測都會依註冊順序送進去的 Sink 清單
+#[derive(Default)]
+pub struct Observers {
+    list: Vec<Observer>,
+}
+
+impl Observers {
+    pub fn subscribe(&mut self, sink: impl MeasurementSink + 'static) {
+        self.list.push(Box::new(sink));
+    }
+
+    /// 送給所有開著的 Sink，回傳失敗的原因
+    pub fn publish(&mut self, m: &Measurement) -> Vec<String> {
+        self.list.iter_mut()
+            .filter(|s| s.enabled())
+            .filter_map(|s| s.record(m).err())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize { self.list.len() }
+
+    pub fn is_empty(&self) -> bool { self.list.is_empty() }
+}
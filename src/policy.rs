@@ -0,0 +1,143 @@
+//! # Command Policy
+//!
+//! Per-profile safety rules for commands typed into the manual command box:
+//! a deny-list, an optional allow-list "locked" mode and a voltage cap.
+
+use serde::{Deserialize, Serialize};
+
+use crate::scpi;
+
+/// 手動指令的阻擋規則 (放在 Device Profile 裡)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CommandPolicy {
+    /// 禁止的指令開頭，例如 "*RST"、"SYST:COMM"
+    pub deny: Vec<String>,
+    /// locked 模式下唯一允許的指令開頭
+    pub allow: Vec<String>,
+    /// true = 只有 allow 清單裡的指令能送
+    pub locked: bool,
+    /// 手動設定電壓的上限 (V)；0 = 不限制
+    pub max_voltage: f64,
+}
+
+impl CommandPolicy {
+    /// 檢查一行指令 (可能用 `;` 串了好幾道)，被擋下時回傳原因
+    pub fn check(&self, line: &str) -> Result<(), String> {
+        for cmd in line.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+            self.check_one(cmd)?;
+        }
+        Ok(())
+    }
+
+    fn check_one(&self, cmd: &str) -> Result<(), String> {
+        let (header, arg) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+        let header = normalize_header(header);
+
+        if let Some(p) = self.deny.iter().find(|p| rule_matches(&header, p)) {
+            return Err(format!("Blocked by profile policy: {} (deny-list \"{}\")", cmd, p));
+        }
+        if self.locked && !self.allow.iter().any(|p| rule_matches(&header, p)) {
+            return Err(format!("Blocked by profile policy: {} (not in allow-list)", cmd));
+        }
+        if self.max_voltage > 0.0 {
+            if let Some(volt) = voltage_argument(&header, arg) {
+                // "MAX" / "MIN" 之類的關鍵字無法判斷數值，保守起見一律擋下
+                match scpi::parse_number(volt) {
+                    Some(v) if v <= self.max_voltage => {}
+                    _ => return Err(format!("Blocked by profile policy: {} (voltage cap {} V)", cmd, scpi::format_number(self.max_voltage))),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 指令開頭正規化成 `scpi::header_nodes` 的格式 ("SOUR1:VOLT" → "VOLT")
+fn normalize_header(header: &str) -> String {
+    scpi::header_nodes(header).join(":")
+}
+
+/// 設定電壓的指令回傳電壓參數，其他指令回傳 None
+///
+/// `VOLT 5` / `VOLT:LEV:IMM:AMPL 5` 取整個參數 (VOLT:PROT 不算)；
+/// `APPL 5,1` / `APPL CH1,5,1` 取電壓那一欄 (開頭的通道名稱跳過)。
+fn voltage_argument<'a>(header: &str, arg: &'a str) -> Option<&'a str> {
+    let mut nodes = header.split(':');
+    let first = nodes.next().unwrap_or("");
+    if !nodes.all(scpi::is_level_node) { return None; }
+    match first {
+        "VOLT" | "VOLTAGE" => Some(arg),
+        "APPL" | "APPLY" => {
+            let mut fields = arg.split(',').map(str::trim);
+            let volt = fields.next().unwrap_or("");
+            if is_channel_name(volt) { Some(fields.next().unwrap_or("")) } else { Some(volt) }
+        }
+        _ => None,
+    }
+}
+
+/// `CH1`、`P25V` 這類通道名稱 (MIN / MAX / DEF 是數值關鍵字，不算)
+fn is_channel_name(field: &str) -> bool {
+    let f = field.to_uppercase();
+    f.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !matches!(f.as_str(), "MIN" | "MINIMUM" | "MAX" | "MAXIMUM" | "DEF" | "DEFAULT" | "UP" | "DOWN")
+}
+
+/// 規則用前綴比對，縮寫 ("VOLT") 也會擋到完整寫法 ("VOLTAGE")
+fn rule_matches(header: &str, pattern: &str) -> bool {
+    let p = normalize_header(pattern.trim());
+    !p.is_empty() && header.starts_with(&p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capped(max_voltage: f64) -> CommandPolicy {
+        CommandPolicy { max_voltage, ..Default::default() }
+    }
+
+    #[test]
+    fn voltage_cap_blocks_volt_forms() {
+        let p = capped(30.0);
+        assert!(p.check("VOLT 12").is_ok());
+        assert!(p.check("VOLT 60").is_err());
+        assert!(p.check(":SOUR:VOLT:LEV:IMM 60").is_err());
+        assert!(p.check("sour1:volt 60").is_err());
+        assert!(p.check("SOURCE2:VOLTAGE:LEVEL 60").is_err());
+        assert!(p.check("VOLT MAX").is_err());
+        assert!(p.check("VOLT:PROT 60").is_ok());
+        assert!(p.check("VOLT?").is_ok());
+    }
+
+    #[test]
+    fn voltage_cap_blocks_apply() {
+        let p = capped(30.0);
+        assert!(p.check("APPL 60,1").is_err());
+        assert!(p.check("APPLY 60,1").is_err());
+        assert!(p.check("SOUR1:APPL 60,1").is_err());
+        assert!(p.check("APPL CH1,60,1").is_err());
+        assert!(p.check("APPL P25V, 60, 1").is_err());
+        assert!(p.check("APPL MAX,1").is_err());
+        assert!(p.check("APPL 12,1").is_ok());
+        assert!(p.check("APPL CH2,12,1").is_ok());
+        assert!(p.check("APPL?").is_ok());
+    }
+
+    #[test]
+    fn voltage_cap_checks_every_compound_part() {
+        let p = capped(30.0);
+        assert!(p.check("CURR 1;VOLT 12").is_ok());
+        assert!(p.check("CURR 1;APPL 60,1").is_err());
+        assert!(capped(0.0).check("APPL 60,1").is_ok());
+    }
+
+    #[test]
+    fn deny_rules_ignore_numeric_suffixes() {
+        let p = CommandPolicy { deny: vec!["OUTP".into(), "SOUR:CURR:PROT".into()], ..Default::default() };
+        assert!(p.check("OUTP1 ON").is_err());
+        assert!(p.check("SOUR2:CURR:PROT 5").is_err());
+        assert!(p.check("CURR 1").is_ok());
+    }
+}
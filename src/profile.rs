@@ -0,0 +1,454 @@
+//! # Device Profiles
+//!
+//! Instrument-specific link behaviour (pacing, delays, ...) and optional
+//! commands that only some instruments support. A few profiles
+//! are built in; extra ones can be dropped as JSON files into the
+//! `profiles` folder next to the config file, either by hand or with the
+//! in-app profile editor ([`DeviceProfile::edit_fields`] / [`DeviceProfile::from_edit_fields`]).
+
+use serde::{Deserialize, Serialize};
+use serialport::SerialPort;
+use std::time::Duration;
+
+use crate::config;
+use crate::policy::CommandPolicy;
+use crate::scpi::{self, Link, LinkOptions};
+
+/// 儀器額外支援的一個量測項目 (例如電阻、AC 電源的頻率)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MeasurementChannel {
+    pub name: String,
+    /// 查詢指令，例如 "MEAS:RES?"
+    pub query: String,
+    /// 顯示單位 (會自動加 m / µ / k 前綴)；空字串 = 純數字
+    pub unit: String,
+}
+
+/// 某一型儀器的通訊設定
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DeviceProfile {
+    pub name: String,
+    /// 每道指令寫完後等待的時間 (ms)，給反應慢的儀器喘口氣
+    pub post_write_delay_ms: u64,
+    /// 每個查詢寫完後、讀回應前固定等待的時間 (ms)；0 = 馬上讀
+    pub read_delay_ms: u64,
+    /// 逐 byte 寫入時每個 byte 之間的間隔 (ms)；0 = 一次寫完
+    pub inter_byte_delay_ms: u64,
+    /// 自動輪詢的最短間隔 (ms)；真正的 RS-232 用 200，USB-CDC 可以低很多
+    pub min_poll_interval_ms: u64,
+    /// 單一回應的長度上限 (byte)；0 = 預設 4096
+    pub max_response_bytes: usize,
+    /// 開 Port 後要設定的 DTR / RTS 狀態；null = 不動 (沿用驅動預設)
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    /// 開 Port 後等多久才送第一道指令 (ms)；給開 Port 就會重開機的轉接器 / Arduino
+    pub open_settle_ms: u64,
+    /// 輸出開啟延遲指令 (例如 "OUTP:DEL:RISE")；空字串 = 儀器不支援
+    pub output_delay_cmd: String,
+    /// 支援 `VOLT?;:CURR?` 複合查詢，連線同步時合併查詢以減少來回
+    pub compound_queries: bool,
+    /// 風扇模式指令 (例如 "SYST:FAN:MODE")；空字串 = 儀器不支援
+    pub fan_mode_cmd: String,
+    /// 風扇模式可選的值
+    pub fan_modes: Vec<String>,
+    /// 過溫保護門檻指令 (°C，例如 "SYST:TEMP:PROT")；空字串 = 儀器不支援
+    pub otp_cmd: String,
+    /// 儀器內建的過功率保護指令 (W，例如 "POW:PROT")；空字串 = 儀器不支援，只靠軟體檢查
+    pub power_prot_cmd: String,
+    /// 額定最大值查詢 (例如 "VOLT? MAX"、"CURR? MAX")，用來顯示設定值離上限還有多少；空字串 = 不查
+    pub max_voltage_query: String,
+    pub max_current_query: String,
+    /// 電流量測檔位指令 (例如 "SENS:CURR:RANG")；空字串 = 儀器不支援
+    pub current_range_cmd: String,
+    /// 電流檔位可選的值 (低檔位解析度較好，量漏電流用)
+    pub current_ranges: Vec<String>,
+    /// 量測積分時間指令 (電源週期數，例如 "SENS:NPLC")；空字串 = 儀器不支援
+    pub nplc_cmd: String,
+    /// 市電頻率 (Hz)，NPLC 換算成時間用
+    pub line_freq_hz: f64,
+    /// 多通道耦合指令 (例如 "OUTP:SER"、"OUTP:PAR"、"OUTP:TRAC"，帶 ON/OFF)；空字串 = 儀器不支援該模式
+    pub series_cmd: String,
+    pub parallel_cmd: String,
+    pub track_cmd: String,
+    /// V / I / P 以外的量測項目，顯示在 MEASUREMENTS 卡片 (跟 AC 遙測一起慢速更新)
+    pub measurements: Vec<MeasurementChannel>,
+    /// AC 輸入電壓查詢 (例如 "MEAS:VOLT:AC:INP?")；空字串 = 儀器不支援
+    pub ac_voltage_query: String,
+    /// AC 輸入頻率查詢 (例如 "MEAS:FREQ:INP?")；空字串 = 儀器不支援
+    pub ac_freq_query: String,
+    /// 儀器時鐘的日期 / 時間指令 (例如 "SYST:DATE"、"SYST:TIME")；任一個空字串 = 儀器不支援
+    pub date_cmd: String,
+    pub time_cmd: String,
+    /// 量測觸發子系統：觸發來源指令 (例如 "TRIG:SOUR")、啟動 (例如 "INIT")、讀回結果 (例如 "FETC:VOLT?")。
+    /// 來源、啟動指令與至少一個 fetch 查詢都有定義才支援
+    pub trigger_source_cmd: String,
+    /// 觸發來源可選的值 (BUS = 軟體 `*TRG`、EXT = 外部觸發輸入、IMM = 立即)
+    pub trigger_sources: Vec<String>,
+    pub init_cmd: String,
+    /// 軟體觸發 (來源是 BUS 時用)
+    pub trigger_cmd: String,
+    /// 取消已啟動的觸發
+    pub abort_cmd: String,
+    pub fetch_voltage_query: String,
+    pub fetch_current_query: String,
+    /// 連線時切到遠端模式的指令 (有些儀器不先 SYST:REM 會忽略設定指令)；空字串 = 不送
+    pub remote_cmd: String,
+    /// 連線 (進入遠端模式) 後、同步之前依序送出的指令，例如設定量測檔位或關掉蜂鳴器
+    pub on_connect_commands: Vec<String>,
+    /// 斷線時把面板還給使用者的指令；空字串 = 不送
+    pub local_cmd: String,
+    /// 查詢目前是本地 / 遠端模式 (使用者可能按了面板的 Local 鍵)；空字串 = 儀器不支援
+    pub remote_state_query: String,
+    /// 安全連鎖接在哪條 modem 狀態線 ("CTS" / "DSR" / "DCD" / "RI")；線沒有 assert 就不准開輸出。空字串 = 不使用
+    pub interlock_line: String,
+    /// 設定指令之後用 `SYST:ERR?` 讀錯誤佇列，把被儀器拒絕的指令顯示出來；儀器不支援就關掉
+    pub error_queue: bool,
+    /// 手動指令框的阻擋規則 (共用實驗室用)
+    pub policy: CommandPolicy,
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        Self {
+            name: "Generic SCPI".to_string(),
+            post_write_delay_ms: 0,
+            read_delay_ms: 0,
+            inter_byte_delay_ms: 0,
+            min_poll_interval_ms: 200,
+            max_response_bytes: 0,
+            dtr: None,
+            rts: None,
+            open_settle_ms: 0,
+            output_delay_cmd: String::new(),
+            compound_queries: false,
+            fan_mode_cmd: String::new(),
+            fan_modes: vec!["AUTO".to_string(), "LOW".to_string(), "HIGH".to_string()],
+            otp_cmd: String::new(),
+            power_prot_cmd: String::new(),
+            max_voltage_query: String::new(),
+            max_current_query: String::new(),
+            current_range_cmd: String::new(),
+            current_ranges: vec!["AUTO".to_string(), "LOW".to_string(), "HIGH".to_string()],
+            nplc_cmd: String::new(),
+            line_freq_hz: 50.0,
+            series_cmd: String::new(),
+            parallel_cmd: String::new(),
+            track_cmd: String::new(),
+            measurements: Vec::new(),
+            ac_voltage_query: String::new(),
+            ac_freq_query: String::new(),
+            date_cmd: String::new(),
+            time_cmd: String::new(),
+            trigger_source_cmd: String::new(),
+            trigger_sources: vec!["BUS".to_string(), "EXT".to_string(), "IMM".to_string()],
+            init_cmd: String::new(),
+            trigger_cmd: "*TRG".to_string(),
+            abort_cmd: "ABOR".to_string(),
+            fetch_voltage_query: String::new(),
+            fetch_current_query: String::new(),
+            remote_cmd: scpi::cmds::REMOTE.to_string(),
+            on_connect_commands: Vec::new(),
+            local_cmd: scpi::cmds::UNLOCK.to_string(),
+            remote_state_query: String::new(),
+            interlock_line: String::new(),
+            error_queue: true,
+            policy: CommandPolicy::default(),
+        }
+    }
+}
+
+impl DeviceProfile {
+    /// 轉成 scpi 模組使用的連線參數
+    pub fn link_options(&self) -> LinkOptions {
+        LinkOptions {
+            post_write_delay: Duration::from_millis(self.post_write_delay_ms),
+            read_delay: Duration::from_millis(self.read_delay_ms),
+            inter_byte_delay: Duration::from_millis(self.inter_byte_delay_ms),
+            compound_queries: self.compound_queries,
+            max_response_len: self.max_response_bytes,
+            // 每次連線的臨時選項，由 UI 決定
+            write_terminator: scpi::Terminator::CrLf,
+            read_terminator: scpi::Terminator::Lf,
+            read_timeout: Duration::ZERO,
+        }
+    }
+
+    /// 開 Port 之後、第一道指令之前：設定 DTR / RTS，再等裝置開機完成
+    pub fn prepare_port(&self, port: &mut dyn SerialPort) -> serialport::Result<()> {
+        if let Some(on) = self.dtr { port.write_data_terminal_ready(on)?; }
+        if let Some(on) = self.rts { port.write_request_to_send(on)?; }
+        if self.open_settle_ms > 0 {
+            std::thread::sleep(Duration::from_millis(self.open_settle_ms));
+        }
+        Ok(())
+    }
+
+    /// 安全連鎖是否滿足；沒設定時回傳 None。讀不到或線名打錯一律當作未滿足 (fail-safe)
+    pub fn interlock_ok(&self, port: &mut dyn SerialPort) -> Option<bool> {
+        let state = match self.interlock_line.trim().to_uppercase().as_str() {
+            "" => return None,
+            "CTS" => port.read_clear_to_send(),
+            "DSR" => port.read_data_set_ready(),
+            "DCD" | "CD" => port.read_carrier_detect(),
+            "RI" => port.read_ring_indicator(),
+            _ => Ok(false),
+        };
+        Some(state.unwrap_or(false))
+    }
+
+    /// 儀器支援的通道耦合模式 (顯示名稱, 指令)，依 Series / Parallel / Track 排序
+    pub fn coupling_modes(&self) -> Vec<(&'static str, &str)> {
+        [("Series", &self.series_cmd), ("Parallel", &self.parallel_cmd), ("Track", &self.track_cmd)]
+            .into_iter()
+            .filter(|(_, cmd)| !cmd.is_empty())
+            .map(|(name, cmd)| (name, cmd.as_str()))
+            .collect()
+    }
+
+    /// 有定義任何 AC 輸入查詢
+    pub fn has_ac_telemetry(&self) -> bool {
+        !self.ac_voltage_query.is_empty() || !self.ac_freq_query.is_empty()
+    }
+
+    /// 可以讀寫儀器時鐘
+    pub fn has_clock(&self) -> bool {
+        !self.date_cmd.is_empty() && !self.time_cmd.is_empty()
+    }
+
+    /// 支援用儀器的觸發子系統量測 (arm → trigger → fetch)
+    pub fn has_trigger(&self) -> bool {
+        !self.trigger_source_cmd.is_empty() && !self.init_cmd.is_empty()
+            && (!self.fetch_voltage_query.is_empty() || !self.fetch_current_query.is_empty())
+    }
+
+    /// 設定觸發來源並啟動量測，之後儀器等觸發事件發生才量
+    pub fn arm_trigger(&self, link: &mut Link, source: &str) -> Result<(), scpi::ScpiError> {
+        scpi::send_command(link, &format!("{} {}", self.trigger_source_cmd, source))?;
+        scpi::send_command(link, &self.init_cmd)?;
+        Ok(())
+    }
+
+    /// 讀回觸發量到的 V / I (沒定義的查詢回傳 None)；還沒觸發時儀器不會回應，會得到 Timeout
+    pub fn fetch_triggered(&self, link: &mut Link) -> Result<(Option<f64>, Option<f64>), scpi::ScpiError> {
+        let mut fetch = |query: &str| -> Result<Option<f64>, scpi::ScpiError> {
+            if query.is_empty() { return Ok(None); }
+            let resp = scpi::send_command(link, query)?.unwrap_or_default();
+            Ok(scpi::parse_measurement(&resp))
+        };
+        Ok((fetch(&self.fetch_voltage_query)?, fetch(&self.fetch_current_query)?))
+    }
+
+    /// 連線 (或切換 Profile) 後、同步之前：進入遠端模式
+    pub fn enter_remote(&self, link: &mut Link) {
+        if !self.remote_cmd.is_empty() {
+            let _ = scpi::send_command(link, &self.remote_cmd);
+        }
+    }
+
+    /// 接在 `enter_remote` 後面：送出 on-connect 指令，回傳失敗的那幾道 (不中斷後面的指令)
+    pub fn run_on_connect(&self, link: &mut Link) -> Vec<String> {
+        let mut failed = Vec::new();
+        for cmd in self.on_connect_commands.iter().map(|c| c.trim()).filter(|c| !c.is_empty()) {
+            if let Err(e) = scpi::send_command(link, cmd) {
+                failed.push(format!("{} ({})", cmd, e));
+            }
+        }
+        failed
+    }
+
+    /// 查詢儀器是否被切回本地模式；不支援或讀不到時回傳 None
+    pub fn is_local(&self, link: &mut Link) -> Option<bool> {
+        if self.remote_state_query.is_empty() { return None; }
+        let resp = scpi::send_command(link, &self.remote_state_query).ok().flatten()?;
+        match resp.trim().to_uppercase().as_str() {
+            "LOC" | "LOCAL" | "0" => Some(true),
+            "REM" | "REMOTE" | "RWL" | "RWLOCK" | "1" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// 斷線前：回到本地 (面板) 模式
+    pub fn return_local(&self, link: &mut Link) {
+        if !self.local_cmd.is_empty() {
+            let _ = scpi::send_command(link, &self.local_cmd);
+        }
+    }
+}
+
+/// Profile 編輯器裡的一個欄位
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    Text,
+    Number,
+    Bool,
+    /// 可以留空 (= 不設定) 的 true / false，例如 DTR / RTS
+    OptionalBool,
+    /// 字串清單，編輯時用 ';' 分隔
+    List,
+}
+
+#[derive(Clone, Debug)]
+pub struct EditField {
+    pub key: String,
+    pub value: String,
+    pub kind: FieldKind,
+}
+
+impl DeviceProfile {
+    /// 攤平成編輯器的欄位清單 (name 在最前面，其餘依欄位名稱排序)；
+    /// 巢狀的設定 (policy、measurements) 不在這裡編，存檔時沿用原值
+    pub fn edit_fields(&self) -> Vec<EditField> {
+        let (serde_json::Value::Object(map), serde_json::Value::Object(defaults)) =
+            (serde_json::to_value(self).unwrap_or_default(), serde_json::to_value(DeviceProfile::default()).unwrap_or_default())
+        else { return Vec::new(); };
+        let mut fields: Vec<EditField> = map.into_iter().filter_map(|(key, v)| {
+            let optional = defaults.get(&key).is_some_and(|d| d.is_null());
+            let (value, kind) = match v {
+                serde_json::Value::Null => (String::new(), FieldKind::OptionalBool),
+                serde_json::Value::Bool(b) if optional => (b.to_string(), FieldKind::OptionalBool),
+                serde_json::Value::Bool(b) => (b.to_string(), FieldKind::Bool),
+                serde_json::Value::Number(n) => (n.to_string(), FieldKind::Number),
+                serde_json::Value::String(s) => (s, FieldKind::Text),
+                serde_json::Value::Array(items) if items.iter().all(|i| i.is_string()) => {
+                    (items.iter().filter_map(|i| i.as_str()).collect::<Vec<_>>().join("; "), FieldKind::List)
+                }
+                _ => return None,
+            };
+            Some(EditField { key, value, kind })
+        }).collect();
+        fields.sort_by_key(|f| f.key != "name");
+        fields
+    }
+
+    /// 把編輯器的欄位套回 `self` 的副本；格式錯誤時回傳哪個欄位有問題
+    pub fn from_edit_fields(&self, fields: &[EditField]) -> Result<DeviceProfile, String> {
+        let mut json = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        let map = json.as_object_mut().ok_or("Profile is not an object")?;
+        for f in fields {
+            let text = f.value.trim();
+            let value = match f.kind {
+                FieldKind::Text => serde_json::Value::String(text.to_string()),
+                FieldKind::List => serde_json::Value::Array(
+                    text.split(';').map(str::trim).filter(|s| !s.is_empty()).map(|s| serde_json::Value::String(s.to_string())).collect(),
+                ),
+                FieldKind::Bool | FieldKind::OptionalBool => match text.to_lowercase().as_str() {
+                    "" if f.kind == FieldKind::OptionalBool => serde_json::Value::Null,
+                    "true" | "on" | "1" => serde_json::Value::Bool(true),
+                    "false" | "off" | "0" => serde_json::Value::Bool(false),
+                    _ => return Err(format!("{}: expected true or false", f.key)),
+                },
+                FieldKind::Number => {
+                    // 整數欄位 (ms、byte 數) 不能有小數，交給 serde 判斷
+                    let n: serde_json::Number = text.parse().map_err(|_| format!("{}: not a number", f.key))?;
+                    serde_json::Value::Number(n)
+                }
+            };
+            map.insert(f.key.clone(), value);
+        }
+        serde_json::from_value(json).map_err(|e| format!("Invalid profile: {}", e))
+    }
+
+    /// 存檔前檢查：要有名稱，選配功能的指令要填齊
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() { return Err("Profile name must not be empty".to_string()); }
+        if self.min_poll_interval_ms == 0 { return Err("min_poll_interval_ms must be at least 1".to_string()); }
+        // 成對才有意義的指令：填了一個，另一個也要填
+        for (a, b, a_key, b_key) in [
+            (&self.date_cmd, &self.time_cmd, "date_cmd", "time_cmd"),
+            (&self.trigger_source_cmd, &self.init_cmd, "trigger_source_cmd", "init_cmd"),
+        ] {
+            if a.is_empty() != b.is_empty() {
+                let (set, missing) = if a.is_empty() { (b_key, a_key) } else { (a_key, b_key) };
+                return Err(format!("{} is set, so {} must not be empty", set, missing));
+            }
+        }
+        if !self.trigger_source_cmd.is_empty() && self.fetch_voltage_query.is_empty() && self.fetch_current_query.is_empty() {
+            return Err("trigger_source_cmd is set, so fetch_voltage_query or fetch_current_query must not be empty".to_string());
+        }
+        // 有指令但沒有可選的值，下拉選單會是空的
+        for (cmd, values, key) in [
+            (&self.fan_mode_cmd, &self.fan_modes, "fan_modes"),
+            (&self.current_range_cmd, &self.current_ranges, "current_ranges"),
+            (&self.trigger_source_cmd, &self.trigger_sources, "trigger_sources"),
+        ] {
+            if !cmd.is_empty() && values.is_empty() { return Err(format!("{} must list at least one value", key)); }
+        }
+        Ok(())
+    }
+}
+
+/// 內建 Profile
+fn builtin() -> Vec<DeviceProfile> {
+    vec![
+        DeviceProfile::default(),
+        // 老儀器 / 光耦隔離轉接器：字元太快會掉字
+        DeviceProfile {
+            name: "Slow RS-232 (paced)".to_string(),
+            post_write_delay_ms: 50,
+            inter_byte_delay_ms: 2,
+            ..DeviceProfile::default()
+        },
+        // USB 虛擬序列埠：沒有鮑率的物理限制，10 Hz 以上都沒問題
+        DeviceProfile {
+            name: "USB-CDC (fast)".to_string(),
+            min_poll_interval_ms: 20,
+            ..DeviceProfile::default()
+        },
+        // 開 Port 時 DTR 會把板子重開機 (Arduino 類的電子負載)
+        DeviceProfile {
+            name: "Arduino-based (DTR reset)".to_string(),
+            dtr: Some(false),
+            rts: Some(false),
+            open_settle_ms: 2000,
+            ..DeviceProfile::default()
+        },
+        DeviceProfile {
+            name: "Keysight E36300".to_string(),
+            output_delay_cmd: "OUTP:DEL:RISE".to_string(),
+            compound_queries: true,
+            max_voltage_query: "VOLT? MAX".to_string(),
+            max_current_query: "CURR? MAX".to_string(),
+            ..DeviceProfile::default()
+        },
+    ]
+}
+
+fn profiles_dir() -> Option<std::path::PathBuf> {
+    Some(config::config_dir()?.join("profiles"))
+}
+
+/// 存成使用者 Profile (profiles/<name>.json)；內建 Profile 也可以這樣覆寫
+pub fn save_user(profile: &DeviceProfile) -> Result<(), anyhow::Error> {
+    let dir = profiles_dir().ok_or_else(|| anyhow::anyhow!("No config directory"))?;
+    std::fs::create_dir_all(&dir)?;
+    // 名稱裡的空白、括號、斜線等不適合當檔名
+    let file: String = profile.name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    std::fs::write(dir.join(format!("{}.json", file)), serde_json::to_string_pretty(profile)?)?;
+    Ok(())
+}
+
+/// 內建 + 使用者自訂 (profiles/*.json) 的所有 Profile，同名時以使用者的為準
+pub fn load_all() -> Vec<DeviceProfile> {
+    let mut all = builtin();
+    let Some(dir) = profiles_dir() else { return all; };
+    let Ok(entries) = std::fs::read_dir(dir) else { return all; };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") { continue; }
+        let Some(p) = std::fs::read_to_string(&path).ok()
+            .and_then(|s| serde_json::from_str::<DeviceProfile>(&s).ok()) else {
+            eprintln!("Profile Load Error: {}", path.display());
+            continue;
+        };
+        match all.iter_mut().find(|x| x.name == p.name) {
+            Some(existing) => *existing = p,
+            None => all.push(p),
+        }
+    }
+    all
+}
@@ -0,0 +1,101 @@
+//! # Capture Replay
+//!
+//! Plays a logged CSV session back through the trend chart at a chosen
+//! speed (or one sample at a time), following the original timestamps so
+//! gaps and bursts look the way they did live.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::logger::LoggedSample;
+
+/// 可選的播放速度
+pub const SPEEDS: [f64; 3] = [0.5, 1.0, 2.0];
+
+pub struct Replay {
+    samples: Vec<LoggedSample>,
+    /// 目前播到第幾筆 (已顯示)
+    pos: usize,
+    /// 播放時鐘：距離第一筆的秒數 (原始時間軸)
+    clock: f64,
+    pub speed: f64,
+    pub playing: bool,
+}
+
+impl Replay {
+    /// 從第一筆開始、暫停中；沒有資料時回傳 None
+    pub fn new(samples: Vec<LoggedSample>) -> Option<Self> {
+        if samples.is_empty() { return None; }
+        Some(Self { samples, pos: 0, clock: 0.0, speed: 1.0, playing: false })
+    }
+
+    pub fn len(&self) -> usize { self.samples.len() }
+
+    pub fn is_empty(&self) -> bool { self.samples.is_empty() }
+
+    pub fn position(&self) -> usize { self.pos }
+
+    pub fn current(&self) -> &LoggedSample { &self.samples[self.pos] }
+
+    /// 第 k 筆距離第一筆的秒數
+    fn offset(&self, k: usize) -> f64 {
+        (self.samples[k].ts - self.samples[0].ts).num_milliseconds() as f64 / 1000.0
+    }
+
+    /// 整段錄製的長度 (秒)
+    pub fn duration(&self) -> f64 { self.offset(self.len() - 1) }
+
+    /// 經過實際時間 `dt` (乘上速度) 後往前播；位置有變時回傳 true，播到最後自動暫停
+    pub fn tick(&mut self, dt: Duration) -> bool {
+        if !self.playing { return false; }
+        self.clock += dt.as_secs_f64() * self.speed;
+        let start = self.pos;
+        while self.pos + 1 < self.len() && self.offset(self.pos + 1) <= self.clock {
+            self.pos += 1;
+        }
+        if self.pos + 1 == self.len() { self.playing = false; }
+        self.pos != start
+    }
+
+    /// 一次走 `n` 筆 (負值往回)，會先暫停
+    pub fn step(&mut self, n: isize) {
+        self.playing = false;
+        let pos = (self.pos as isize + n).clamp(0, self.len() as isize - 1) as usize;
+        self.jump(pos);
+    }
+
+    /// 跳到整段的 `frac` (0 開頭 ~ 1 結尾)
+    pub fn seek(&mut self, frac: f32) {
+        let pos = (frac.clamp(0.0, 1.0) * (self.len() - 1) as f32).round() as usize;
+        self.jump(pos);
+    }
+
+    /// 播完之後再按播放就從頭開始
+    pub fn play(&mut self) {
+        if self.pos + 1 == self.len() { self.jump(0); }
+        self.playing = true;
+    }
+
+    fn jump(&mut self, pos: usize) {
+        self.pos = pos;
+        self.clock = self.offset(pos);
+    }
+
+    /// 目前位置在整段中的比例 (給拖拉條)
+    pub fn progress(&self) -> f32 {
+        if self.len() < 2 { return 0.0; }
+        self.pos as f32 / (self.len() - 1) as f32
+    }
+
+    /// 以目前位置為右緣、最多 `n` 筆的電壓 / 電流 (前面不足時補 NaN 讓曲線從右側長出來)
+    pub fn window(&self, n: usize) -> (VecDeque<f32>, VecDeque<f32>) {
+        let end = self.pos + 1;
+        let start = end.saturating_sub(n);
+        let pad = n - (end - start);
+        let mut v: VecDeque<f32> = std::iter::repeat_n(f32::NAN, pad).collect();
+        let mut i = v.clone();
+        v.extend(self.samples[start..end].iter().map(|s| s.v));
+        i.extend(self.samples[start..end].iter().map(|s| s.i));
+        (v, i)
+    }
+}
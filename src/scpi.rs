@@ -3,9 +3,12 @@
 //! This module defines the SCPI command set and low-level serial communication 
 //! functions required to interact with a Programmable Power Supply (PSU).
 
+use std::collections::VecDeque;
 use std::io::{Read, Write};
-use std::time::Duration;
-use serialport::SerialPort;
+use std::time::{Duration, Instant};
+use serialport::{DataBits, Parity, SerialPort, SerialPortBuilder, StopBits};
+
+use crate::txlog::TxLog;
 
 // ==========================================
 // SCPI 指令清單 (集中管理，一眼就能看到指令)
@@ -15,6 +18,7 @@ pub mod cmds {
     pub const IDN: &str        = "*IDN?";
     pub const RESET: &str      = "*RST";
     pub const UNLOCK: &str     = "SYST:COMM:RLST LOC";
+    pub const REMOTE: &str     = "SYST:REM";
     pub const SET_VOLT: &str   = "VOLT";
     pub const SET_CURR: &str   = "CURR";
     pub const READ_ALL: &str   = "MEAS:ALL?";
@@ -25,51 +29,898 @@ pub mod cmds {
     pub const OUTP_OFF: &str   = "OUTP OFF";
     pub const GET_SET_VOLT: &str = "SOUR:VOLT:LEV:IMM:AMPL?";
     pub const GET_SET_CURR: &str = "SOUR:CURR:LEV:IMM:AMPL?";
+    pub const SELECT_CHANNEL: &str = "INST:NSEL";
+    pub const ESR: &str        = "*ESR?";
+    pub const CLS: &str        = "*CLS";
+    pub const BEEP: &str       = "SYST:BEEP";
+    pub const SYST_ERR: &str   = "SYST:ERR?";
+    pub const OPC: &str        = "*OPC?";
+}
+
+/// SCPI 通訊錯誤
+#[derive(Debug)]
+pub enum ScpiError {
+    /// 寫入序列埠失敗
+    Write(std::io::Error),
+    /// 讀取序列埠失敗
+    Read(std::io::Error),
+    /// 超時，完全沒收到任何資料
+    Timeout,
+    /// 收到結束字元，但內容是空的
+    Empty,
+    /// 超時前只收到半行 (沒有結束字元)，內容不能當成完整回應解析
+    Partial(String),
+    /// 回應超過長度上限還沒結束 (儀器故障或設定錯誤)，只保留已收到的部分
+    Truncated(String),
+    /// 尚未連線
+    Disconnected,
+    /// 序列埠正被別的 callback / timer 使用中
+    Busy,
+}
+
+impl std::fmt::Display for ScpiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_link_lost() { return write!(f, "Connection lost: {}", self.io_error().map(|e| e.to_string()).unwrap_or_default()); }
+        match self {
+            ScpiError::Write(e) => write!(f, "Port error (write): {}", e),
+            ScpiError::Read(e) => write!(f, "Port error (read): {}", e),
+            ScpiError::Timeout => write!(f, "Timeout: no response"),
+            ScpiError::Empty => write!(f, "Empty response"),
+            ScpiError::Partial(s) => write!(f, "Incomplete response (no terminator): {:?}", s),
+            ScpiError::Truncated(s) => write!(f, "Response too long, truncated after {} bytes", s.len()),
+            ScpiError::Disconnected => write!(f, "Not connected"),
+            ScpiError::Busy => write!(f, "Port busy: another command is in progress"),
+        }
+    }
+}
+
+impl std::error::Error for ScpiError {}
+
+impl ScpiError {
+    fn io_error(&self) -> Option<&std::io::Error> {
+        match self {
+            ScpiError::Write(e) | ScpiError::Read(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// 序列埠本身不見了 (USB 線拔掉、轉接器斷電)，之後的指令都不會成功，要重新連線
+    ///
+    /// 跟 `Timeout` 不同：儀器沒回應時 Port 還在，重送就好。
+    pub fn is_link_lost(&self) -> bool {
+        use std::io::ErrorKind;
+        let Some(e) = self.io_error() else { return false; };
+        matches!(e.kind(),
+            ErrorKind::BrokenPipe | ErrorKind::NotConnected | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset | ErrorKind::NotFound | ErrorKind::PermissionDenied | ErrorKind::UnexpectedEof)
+            // Linux: EIO / ENXIO / ENODEV；Windows: ERROR_GEN_FAILURE / ERROR_BAD_COMMAND / ERROR_DEVICE_NOT_CONNECTED
+            || matches!(e.raw_os_error(), Some(5 | 6 | 19) if cfg!(unix))
+            || matches!(e.raw_os_error(), Some(22 | 31 | 1167) if cfg!(windows))
+    }
+}
+
+/// 連線參數 (由 Device Profile 決定)
+#[derive(Debug, Clone, Default)]
+pub struct LinkOptions {
+    /// 寫完指令後等待多久才繼續
+    pub post_write_delay: Duration,
+    /// 查詢寫完後、開始讀回應前再等多久 (讀太早會拿到空的或半截的回應)
+    pub read_delay: Duration,
+    /// 逐 byte 寫入的間隔；0 = 整串一次寫出
+    pub inter_byte_delay: Duration,
+    /// 儀器支援複合查詢 (`VOLT?;:CURR?`)，多個查詢合併成一次來回
+    pub compound_queries: bool,
+    /// 單一回應最多收幾個 byte；0 = 使用 `DEFAULT_MAX_RESPONSE`
+    pub max_response_len: usize,
+    /// 指令結尾 (預設 `\r\n`；有些儀器遇到 `\r` 會出錯，要改成只送 `\n`)
+    pub write_terminator: Terminator,
+    /// 回應的結束字元 (預設收到 `\n` 就算完整；有些儀器只回 `\r`)
+    pub read_terminator: Terminator,
+    /// 等一個完整回應最多多久；0 = 使用 `DEFAULT_READ_TIMEOUT`
+    pub read_timeout: Duration,
+}
+
+/// 指令 / 回應的行結尾
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Terminator {
+    Lf,
+    Cr,
+    #[default]
+    CrLf,
+    /// 寫：不加結尾；讀：沒有結束字元，等到超時為止收到的全部就是回應
+    None,
+}
+
+impl Terminator {
+    /// UI 下拉選單的順序
+    pub const ALL: [Terminator; 4] = [Terminator::CrLf, Terminator::Lf, Terminator::Cr, Terminator::None];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Terminator::Lf => "LF",
+            Terminator::Cr => "CR",
+            Terminator::CrLf => "CRLF",
+            Terminator::None => "None",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str().eq_ignore_ascii_case(text.trim()))
+    }
+
+    /// 寫出指令時接在後面的字元
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Terminator::Lf => "\n",
+            Terminator::Cr => "\r",
+            Terminator::CrLf => "\r\n",
+            Terminator::None => "",
+        }
+    }
+
+    /// 讀回應時看到這個 byte 就算一行結束 (CRLF 看最後的 `\n`)
+    pub fn break_byte(self) -> Option<u8> {
+        match self {
+            Terminator::Lf | Terminator::CrLf => Some(b'\n'),
+            Terminator::Cr => Some(b'\r'),
+            Terminator::None => None,
+        }
+    }
+}
+
+/// 預設的回應長度上限 (一般 SCPI 回應遠小於這個值)
+pub const DEFAULT_MAX_RESPONSE: usize = 4096;
+
+/// 預設的讀取超時 (大部分電源 100 ms 內就會回應)
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 自動偵測鮑率時依序嘗試的速率 (最常見的排前面)
+pub const COMMON_BAUD_RATES: [u32; 6] = [9600, 115200, 19200, 38400, 57600, 4800];
+
+/// 序列埠資料格式 (資料位元、同位檢查、停止位元)，用 `8N1` / `7E1` 這種寫法表示
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Framing {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self { data_bits: DataBits::Eight, parity: Parity::None, stop_bits: StopBits::One }
+    }
+}
+
+impl Framing {
+    /// 解析 `8N1`、`7E1`、`8N2` 這類寫法 (大小寫不拘)；看不懂就回傳 None
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut chars = text.trim().chars();
+        let data_bits = match chars.next()? {
+            '5' => DataBits::Five,
+            '6' => DataBits::Six,
+            '7' => DataBits::Seven,
+            '8' => DataBits::Eight,
+            _ => return None,
+        };
+        let parity = match chars.next()?.to_ascii_uppercase() {
+            'N' => Parity::None,
+            'E' => Parity::Even,
+            'O' => Parity::Odd,
+            _ => return None,
+        };
+        let stop_bits = match chars.next()? {
+            '1' => StopBits::One,
+            '2' => StopBits::Two,
+            _ => return None,
+        };
+        chars.next().is_none().then_some(Self { data_bits, parity, stop_bits })
+    }
+
+    /// 開 Port 前套用到 builder 上
+    pub fn apply(self, builder: SerialPortBuilder) -> SerialPortBuilder {
+        builder.data_bits(self.data_bits).parity(self.parity).stop_bits(self.stop_bits)
+    }
+}
+
+impl std::fmt::Display for Framing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parity = match self.parity { Parity::None => 'N', Parity::Even => 'E', Parity::Odd => 'O' };
+        let stop = if self.stop_bits == StopBits::Two { 2 } else { 1 };
+        write!(f, "{}{}{}", u8::from(self.data_bits), parity, stop)
+    }
+}
+
+/// 回應看起來像亂碼 (鮑率不對時的典型症狀)：超過 1/5 的字元不是可列印的 ASCII
+pub fn looks_garbled(resp: &str) -> bool {
+    let total = resp.chars().count();
+    if total == 0 { return false; }
+    // 無效的 UTF-8 會變成 U+FFFD，也算在非 ASCII 裡
+    let bad = resp.chars().filter(|c| !(c.is_ascii_graphic() || *c == ' ' || *c == '\t')).count();
+    bad * 5 > total
+}
+
+/// 通訊除錯紀錄 (TX / RX 每一筆)
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub enabled: bool,
+    /// 除了整理過的字串，也記下原始內容 (含空白、\r\n 等結束字元)
+    pub preserve_raw: bool,
+    pub lines: VecDeque<String>,
+}
+
+/// 最多保留幾筆紀錄
+const TRACE_LEN: usize = 200;
+
+impl Trace {
+    fn log(&mut self, dir: &str, raw: &[u8]) {
+        if !self.enabled { return; }
+        let text = String::from_utf8_lossy(raw);
+        let line = if self.preserve_raw {
+            format!("{} {}    raw: {:?}", dir, text.trim(), text)
+        } else {
+            format!("{} {}", dir, text.trim())
+        };
+        if self.lines.len() == TRACE_LEN { self.lines.pop_front(); }
+        self.lines.push_back(line);
+    }
+}
+
+/// 已開啟的序列埠 + 該儀器的通訊參數
+pub struct Link {
+    pub port: Box<dyn SerialPort>,
+    pub opts: LinkOptions,
+    pub trace: Trace,
+    /// 上次送出 VOLT / CURR 設定的時間
+    pub last_setpoint_change: Option<Instant>,
+    /// 設定改變後輸出要多久才穩定 (這段時間內的量測不可信)；0 = 不等
+    pub settle: Duration,
+    /// 交易紀錄檔 (除錯用，預設關閉)
+    pub txlog: Option<TxLog>,
+}
+
+impl Link {
+    pub fn new(port: Box<dyn SerialPort>, opts: LinkOptions) -> Self {
+        Self { port, opts, trace: Trace::default(), last_setpoint_change: None, settle: Duration::ZERO, txlog: None }
+    }
+
+    /// 穩定時間還剩多久
+    pub fn settle_remaining(&self) -> Duration {
+        self.last_setpoint_change.map(|t| self.settle.saturating_sub(t.elapsed())).unwrap_or_default()
+    }
+
+    /// 剛改過設定，輸出可能還在暫態
+    pub fn settling(&self) -> bool { !self.settle_remaining().is_zero() }
+
+    /// 單次量測前呼叫：還在穩定時間內就等到結束
+    pub fn wait_settled(&self) {
+        let remaining = self.settle_remaining();
+        if !remaining.is_zero() { std::thread::sleep(remaining); }
+    }
+}
+
+/// 指令開頭拆成節點：轉大寫、去掉開頭的 ':'、節點的數字後綴 ("SOUR1" → "SOUR") 與可省略的 SOURce 節點
+///
+/// 例如 `"sour1:volt:lev"` → `["VOLT", "LEV"]`、`":VOLT2?"` → `["VOLT?"]`
+pub fn header_nodes(header: &str) -> Vec<String> {
+    let mut nodes: Vec<String> = header.trim_start_matches(':').to_uppercase().split(':')
+        .map(|n| {
+            let (n, query) = n.strip_suffix('?').map_or((n, ""), |n| (n, "?"));
+            format!("{}{}", n.trim_end_matches(|c: char| c.is_ascii_digit()), query)
+        })
+        .collect();
+    if nodes.len() > 1 && matches!(nodes[0].as_str(), "SOUR" | "SOURCE") { nodes.remove(0); }
+    nodes
+}
+
+/// 設定值指令後面可以接的節點 (`VOLT:LEV:IMM:AMPL` 這類寫法)
+pub fn is_level_node(node: &str) -> bool {
+    matches!(node, "LEV" | "LEVEL" | "IMM" | "IMMEDIATE" | "AMPL" | "AMPLITUDE")
+}
+
+/// 會改變輸出設定值的指令 (VOLT / CURR / APPL，含 SOUR: 前綴與 LEV:IMM 寫法；查詢與 PROT 不算)
+pub fn is_setpoint_write(cmd: &str) -> bool {
+    cmd.split(';').any(|part| {
+        let header = part.split_whitespace().next().unwrap_or("");
+        if header.contains('?') { return false; }
+        let nodes = header_nodes(header);
+        let Some((first, rest)) = nodes.split_first() else { return false; };
+        matches!(first.as_str(), "VOLT" | "VOLTAGE" | "CURR" | "CURRENT" | "APPL" | "APPLY")
+            && rest.iter().all(|n| is_level_node(n))
+    })
+}
+
+/// 一次回應：整理過的字串 + 原始 bytes
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// 去掉前後空白與結束字元，平常用這個
+    pub text: String,
+    /// 未修剪的原始資料 (除錯 framing 用)
+    pub raw: Vec<u8>,
+}
+
+/// 儀器錯誤佇列 (`SYST:ERR?`) 裡的一筆，例如 `-222,"Data out of range"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrumentError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl std::fmt::Display for InstrumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, {}", self.code, self.message)
+    }
+}
+
+/// 解析 `SYST:ERR?` 回應 (`碼,"說明"`)；碼不是整數時回傳 None
+pub fn parse_error_reply(resp: &str) -> Option<InstrumentError> {
+    let (code, message) = resp.trim().split_once(',').unwrap_or((resp.trim(), ""));
+    let code = code.trim().trim_start_matches('+').parse::<i32>().ok()?;
+    Some(InstrumentError { code, message: message.trim().trim_matches('"').to_string() })
+}
+
+/// 錯誤佇列最多讀幾筆 (一般儀器的佇列深度約 10~30；防止回應壞掉時無限迴圈)
+const MAX_ERROR_QUEUE: usize = 32;
+
+/// 重複送 `SYST:ERR?` 直到讀到 `0,"No error"`，回傳中間所有非 0 的錯誤 (舊的在前)
+///
+/// 回應不是 `碼,"說明"` 的格式時就停下來，已經讀到的照樣回傳。
+pub fn drain_error_queue(link: &mut Link) -> Result<Vec<InstrumentError>, ScpiError> {
+    let mut errors = Vec::new();
+    for _ in 0..MAX_ERROR_QUEUE {
+        let resp = send_command(link, cmds::SYST_ERR)?.unwrap_or_default();
+        match parse_error_reply(&resp) {
+            Some(e) if e.code != 0 => errors.push(e),
+            _ => break,
+        }
+    }
+    Ok(errors)
+}
+
+/// IEEE 488.2 標準事件狀態暫存器 (`*ESR?`) 各 bit 的意義，bit 0 在前
+const ESR_BITS: [&str; 8] = [
+    "Operation Complete",
+    "Request Control",
+    "Query Error",
+    "Device-Dependent Error",
+    "Execution Error",
+    "Command Error",
+    "User Request",
+    "Power On",
+];
+
+/// 解析 `*ESR?` 回應 (例如 "+32")，回傳有設定的 bit 名稱；回應不是數字時回傳 None
+pub fn decode_esr(resp: &str) -> Option<Vec<&'static str>> {
+    let value = parse_number(resp.trim().trim_start_matches('+'))?;
+    if !(0.0..=255.0).contains(&value) { return None; }
+    let bits = value as u8;
+    Some(ESR_BITS.iter().enumerate().filter(|(k, _)| bits & (1 << k) != 0).map(|(_, name)| *name).collect())
+}
+
+/// `*IDN?` 回應的四個欄位 (缺少的欄位是空字串)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Identity {
+    pub manufacturer: String,
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+}
+
+impl Identity {
+    /// 解析 `製造商,型號,序號,韌體`；欄位不足時補空字串，多出來的併進韌體欄位
+    pub fn parse(idn: &str) -> Self {
+        let mut fields = idn.trim().splitn(4, ',').map(|f| f.trim().to_string());
+        Self {
+            manufacturer: fields.next().unwrap_or_default(),
+            model: fields.next().unwrap_or_default(),
+            serial: fields.next().unwrap_or_default(),
+            firmware: fields.next().unwrap_or_default(),
+        }
+    }
+}
+
+/// 解析使用者輸入的數字
+///
+/// 接受 `12`、`12.5`、`12,5` (逗號當小數點)、`1e1`；千分位、空字串、NaN/inf 一律拒絕。
+pub fn parse_number(input: &str) -> Option<f64> {
+    let text = input.trim();
+    // 只有一個逗號且沒有小數點時才當成小數逗號 ("12,5")，其他有逗號的 ("1,000.5") 不猜
+    let text = match (text.matches(',').count(), text.contains('.')) {
+        (0, _) => text.to_string(),
+        (1, false) => text.replace(',', "."),
+        _ => return None,
+    };
+    text.parse::<f64>().ok().filter(|v| v.is_finite())
 }
 
-/// 讀取序列埠回應
-pub fn read_serial_response(port: &mut Box<dyn SerialPort>) -> Option<String> {
+/// 解析 `MEAS:ALL?` 回應：`V,I` 或 `V,I,P`，分隔符號可以是 `,` 或 `;` (有些儀器會多回功率)
+///
+/// 欄位可以帶單位 (`12.000V,1.500A`)，見 `parse_measurement`。
+pub fn parse_meas_all(resp: &str) -> Option<(f64, f64, Option<f64>)> {
+    // 部分儀器回應開頭會夾雜 '«' 之類的雜訊字元
+    let clean = resp.replace('«', "");
+    let mut fields = clean.split([',', ';']).map(str::trim).filter(|f| !f.is_empty());
+    let v = parse_measurement(fields.next()?)?;
+    let i = parse_measurement(fields.next()?)?;
+    let p = fields.next().and_then(parse_measurement);
+    Some((v, i, p))
+}
+
+/// 一個回應欄位用到小數點後第幾位 (換算成基本單位)，例如 `"5.000"` → 3、`"+1.2345E-03"` → 7、`"1.500mA"` → 6
+///
+/// 尾端的 0 也算：儀器補 0 代表它真的有那個解析度。
+pub fn decimals_of(field: &str) -> Option<usize> {
+    let (field, prefix_exp) = split_unit(field)?;
+    parse_number(field)?;
+    let (mantissa, exp) = match field.find(['e', 'E']) {
+        Some(pos) => (&field[..pos], field[pos + 1..].trim_start_matches('+').parse::<i32>().ok()?),
+        None => (field, 0),
+    };
+    let frac = mantissa.split_once(['.', ',']).map_or(0, |(_, f)| f.chars().filter(char::is_ascii_digit).count());
+    Some((frac as i32 - exp - prefix_exp).max(0) as usize)
+}
+
+/// 從實際的 `MEAS:ALL?` 回應推測儀器的顯示解析度 (V / I 各幾位小數)
+///
+/// 取看過的最大位數：有些儀器會把尾端的 0 省略 ("5" 而不是 "5.000")，只看一筆會低估。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Resolution {
+    pub voltage: Option<usize>,
+    pub current: Option<usize>,
+}
+
+impl Resolution {
+    /// 超過這個位數就是雜訊或換算誤差，不是真的解析度
+    const MAX_DECIMALS: usize = 9;
+
+    pub fn observe(&mut self, resp: &str) {
+        let clean = resp.replace('«', "");
+        let mut fields = clean.split([',', ';']).map(str::trim).filter(|f| !f.is_empty());
+        let update = |slot: &mut Option<usize>, field: Option<&str>| {
+            if let Some(d) = field.and_then(decimals_of).filter(|&d| d <= Self::MAX_DECIMALS) {
+                *slot = Some(slot.map_or(d, |s| s.max(d)));
+            }
+        };
+        update(&mut self.voltage, fields.next());
+        update(&mut self.current, fields.next());
+    }
+}
+
+/// 數值 → 送給儀器的字串：固定用 `.` 小數、最多 6 位、不用科學記號也不補多餘的 0
+///
+/// 例如 `10.0` → `"10"`、`3.300000001` → `"3.3"`。
+pub fn format_number(val: f64) -> String {
+    let s = format!("{:.6}", val);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+/// 解析帶單位的量測回應，例如 `"230.1 V"`、`"12.000V"`、`"+5.00E+01HZ"`、`"12.5mV"`
+///
+/// 去掉尾端的單位字母，有 SI 字首 (m / u / k) 時一併換算；沒有單位的純數字照常解析。
+/// 數字部分交給 `parse_number`，所以科學記號、前面的 `+` 和小數逗號 (`"12,5"`) 都接受。
+pub fn parse_measurement(text: &str) -> Option<f64> {
+    let (num, exp) = split_unit(text)?;
+    let val = parse_number(num)?;
+    let scale = match exp {
+        -3 => 1e-3,
+        -6 => 1e-6,
+        3 => 1e3,
+        _ => 1.0,
+    };
+    Some(val * scale).filter(|v| v.is_finite())
+}
+
+/// 把回應拆成數字部分與單位字首的 10 的次方 (m → -3、µ → -6、k → 3、沒有字首 → 0)
+fn split_unit(text: &str) -> Option<(&str, i32)> {
+    let text = text.trim();
+    // 數字部分到最後一個數字為止 (科學記號的 E 後面一定接數字)
+    let end = text.rfind(|c: char| c.is_ascii_digit() || c == '.')? + 1;
+    let (num, unit) = text.split_at(end);
+    let unit = unit.trim();
+    let exp = match unit.chars().next() {
+        // 只有單一字母的 "M" 不算字首 (沒有這種單位)
+        Some('m') if unit.len() > 1 => -3,
+        Some('u') | Some('µ') if unit.chars().count() > 1 => -6,
+        Some('k') | Some('K') if unit.len() > 1 => 3,
+        _ => 0,
+    };
+    Some((num.trim(), exp))
+}
+
+/// 顯示用：小數值自動換成 m / µ 字首，例如 `0.005` A → `"5.0000 mA"`
+///
+/// 固定 5 位有效數字 (讀值跳動時寬度不變)；存檔 / 送指令仍然用基本單位。
+pub fn format_with_unit(value: f64, base_unit: &str) -> String {
+    format_with_unit_digits(value, base_unit, 5)
+}
+
+/// 同 `format_with_unit`，可指定有效位數 (圖表標籤用較短的 3 位)
+pub fn format_with_unit_digits(value: f64, base_unit: &str, digits: usize) -> String {
+    if !value.is_finite() { return format!("--- {}", base_unit); }
+    let mag = value.abs();
+    let (scaled, prefix) = if mag == 0.0 || mag >= 1.0 {
+        (value, "")
+    } else if mag >= 1e-3 {
+        (value * 1e3, "m")
+    } else {
+        (value * 1e6, "µ")
+    };
+    // 整數部分佔幾位，剩下的有效位數給小數
+    let int_digits = if scaled.abs() < 1.0 { 1 } else { scaled.abs().log10().floor() as usize + 1 };
+    let decimals = digits.saturating_sub(int_digits);
+    format!("{:.*} {}{}", decimals, scaled, prefix, base_unit)
+}
+
+/// 照儀器的解析度顯示 (`decimals` = 基本單位的小數位數)，不多補假的位數也不截掉真的位數；
+/// 還不知道解析度時同 `format_with_unit`
+pub fn format_with_resolution(value: f64, base_unit: &str, decimals: Option<usize>) -> String {
+    let Some(decimals) = decimals else { return format_with_unit(value, base_unit); };
+    if !value.is_finite() { return format!("--- {}", base_unit); }
+    let mag = value.abs();
+    // 換成 m / µ 字首後，小數位數跟著少 3 / 6 位
+    let (scaled, prefix, shift) = if mag == 0.0 || mag >= 1.0 {
+        (value, "", 0)
+    } else if mag >= 1e-3 {
+        (value * 1e3, "m", 3)
+    } else {
+        (value * 1e6, "µ", 6)
+    };
+    format!("{:.*} {}{}", decimals.saturating_sub(shift), scaled, prefix, base_unit)
+}
+
+/// `parse_number` + `format_number`，所有送出去的數值參數都經過這裡
+pub fn normalize_number(input: &str) -> Option<String> {
+    parse_number(input).map(format_number)
+}
+
+/// 讀取序列埠回應 (已修剪)
+pub fn read_serial_response(link: &mut Link) -> Result<String, ScpiError> {
+    read_serial_response_raw(link).map(|r| r.text)
+}
+
+/// 讀取序列埠回應，同時保留未修剪的原始資料
+///
+/// 只有讀到結束字元 (預設 `\n`，見 `LinkOptions::read_terminator`) 才算完整；超時時已收到的半行回傳 `Partial`，
+/// 並清掉輸入緩衝區，呼叫端可以重送查詢而不是拿截斷的數字去解析。
+/// 超過長度上限還沒結束時同樣清掉緩衝區，回傳 `Truncated`。
+///
+/// 讀的過程中會暫時縮短 Port 的超時，結束時 (不管成功或失敗) 一律還原；
+/// 寫入也用同一個超時，不能留著越縮越短。
+pub fn read_serial_response_raw(link: &mut Link) -> Result<Response, ScpiError> {
+    let saved = link.port.timeout();
+    let res = read_until_terminator(link);
+    if link.port.timeout() != saved { let _ = link.port.set_timeout(saved); }
+    res
+}
+
+fn read_until_terminator(link: &mut Link) -> Result<Response, ScpiError> {
+    let port = &mut link.port;
     let mut received_bytes: Vec<u8> = Vec::new();
     let mut byte_buf = [0u8; 1];
     let start_time = std::time::Instant::now();
-    let timeout = Duration::from_millis(500);
+    let timeout = if link.opts.read_timeout.is_zero() { DEFAULT_READ_TIMEOUT } else { link.opts.read_timeout };
+    let max_len = if link.opts.max_response_len == 0 { DEFAULT_MAX_RESPONSE } else { link.opts.max_response_len };
+    let break_byte = link.opts.read_terminator.break_byte();
 
     loop {
-        if start_time.elapsed() > timeout {
-            if received_bytes.is_empty() { return None; }
-            break;
+        let remaining = timeout.saturating_sub(start_time.elapsed());
+        if remaining.is_zero() {
+            // 沒有結束字元的儀器：超時前收到的就是整個回應
+            if break_byte.is_none() && !received_bytes.is_empty() { break; }
+            if received_bytes.is_empty() {
+                link.trace.log("RX (timeout)", b"");
+                return Err(ScpiError::Timeout);
+            }
+            link.trace.log("RX (partial)", &received_bytes);
+            let _ = port.clear(serialport::ClearBuffer::Input);
+            return Err(ScpiError::Partial(String::from_utf8_lossy(&received_bytes).trim().to_string()));
         }
 
+        // 單次 read 最多只阻塞到整體期限為止 (Port 的超時比剩下的時間長時縮短它)
+        if port.timeout() > remaining {
+            let _ = port.set_timeout(remaining);
+        }
         match port.read(&mut byte_buf) {
             Ok(1) => {
                 let b = byte_buf[0];
                 received_bytes.push(b);
-                if b == b'\n' { break; } 
+                if Some(b) == break_byte { break; }
+                // 一直送資料卻不換行：到上限就停，不讓 Buffer 無限長大
+                if received_bytes.len() >= max_len {
+                    link.trace.log("RX (truncated)", &received_bytes);
+                    let _ = port.clear(serialport::ClearBuffer::Input);
+                    return Err(ScpiError::Truncated(String::from_utf8_lossy(&received_bytes).trim().to_string()));
+                }
             },
-            Ok(_) => continue,
+            // 有些驅動程式沒資料時立刻回 0，稍微睡一下免得空轉吃滿 CPU
+            Ok(_) => std::thread::sleep(Duration::from_millis(1)),
+            // 阻塞到 Port 超時才回來，不會空轉；回到迴圈開頭檢查整體期限
             Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
-            Err(e) => {
-                eprintln!("Read Error: {}", e);
-                break;
-            }
+            Err(e) => return Err(ScpiError::Read(e)),
         }
     }
     
-    if received_bytes.is_empty() { return None; }
-    Some(String::from_utf8_lossy(&received_bytes).trim().to_string())
+    let text = String::from_utf8_lossy(&received_bytes).trim().to_string();
+    let resp = Response { text, raw: received_bytes };
+    link.trace.log("RX", &resp.raw);
+    if resp.text.is_empty() { return Err(ScpiError::Empty); }
+    Ok(resp)
 }
 
 /// 傳送指令並(選擇性)讀取回傳
-pub fn send_command(port: &mut Box<dyn SerialPort>, cmd: &str) -> Option<String> {
-    let full_cmd = format!("{}\r\n", cmd);
-    if let Err(e) = port.write_all(full_cmd.as_bytes()) {
-        eprintln!("Write Error: {}", e);
-        return None;
+///
+/// 查詢指令 (含 `?`) 回傳 `Ok(Some(..))`；設定指令不等回應，回傳 `Ok(None)`。
+/// 有開交易紀錄 (`Link::txlog`) 時每次呼叫記一筆。
+pub fn send_command(link: &mut Link, cmd: &str) -> Result<Option<String>, ScpiError> {
+    let started = Instant::now();
+    let res = exchange(link, cmd);
+    if let Some(log) = &link.txlog { log.record(cmd, &res, started.elapsed()); }
+    res.map(|r| r.map(|r| r.text))
+}
+
+/// 寫出指令，查詢的話讀回原始回應
+fn exchange(link: &mut Link, cmd: &str) -> Result<Option<Response>, ScpiError> {
+    let full_cmd = format!("{}{}", cmd, link.opts.write_terminator.suffix());
+    link.trace.log("TX", full_cmd.as_bytes());
+    write_paced(link, full_cmd.as_bytes()).map_err(ScpiError::Write)?;
+    if is_setpoint_write(cmd) { link.last_setpoint_change = Some(Instant::now()); }
+    if !link.opts.post_write_delay.is_zero() {
+        std::thread::sleep(link.opts.post_write_delay);
     }
     
     if cmd.contains('?') {
-        read_serial_response(port)
+        if !link.opts.read_delay.is_zero() {
+            std::thread::sleep(link.opts.read_delay);
+        }
+        read_serial_response_raw(link).map(Some)
     } else {
-        None
+        Ok(None)
+    }
+}
+
+/// `send_command`，遇到暫時性的失敗 (超時、半行、空回應) 時隔 `backoff` 重送，最多再試 `retries` 次
+///
+/// 每次重送的間隔加倍。Port 錯誤 / 忙碌等不會自己好的錯誤直接回傳，不重試。
+pub fn send_command_retry(link: &mut Link, cmd: &str, retries: u32, backoff: Duration) -> Result<Option<String>, ScpiError> {
+    let mut wait = backoff;
+    let mut res = send_command(link, cmd);
+    for _ in 0..retries {
+        if !matches!(res, Err(ScpiError::Timeout | ScpiError::Partial(_) | ScpiError::Empty)) { break; }
+        std::thread::sleep(wait);
+        wait *= 2;
+        res = send_command(link, cmd);
+    }
+    res
+}
+
+/// 寫出資料；有設定 inter_byte_delay 時逐 byte 寫並在中間暫停
+fn write_paced(link: &mut Link, bytes: &[u8]) -> std::io::Result<()> {
+    if link.opts.inter_byte_delay.is_zero() {
+        return link.port.write_all(bytes);
+    }
+    for b in bytes {
+        link.port.write_all(std::slice::from_ref(b))?;
+        link.port.flush()?;
+        std::thread::sleep(link.opts.inter_byte_delay);
+    }
+    Ok(())
+}
+
+/// 送 `*OPC?` 等儀器把前面的指令 (例如 `*RST`) 做完；儀器做完才回 `1`
+///
+/// 這段期間讀取超時暫時放寬成 `timeout`。回應不是 `1` (殘留的舊回應) 就再問一次，
+/// 到期限還沒等到回傳 `Timeout`。
+pub fn wait_operation_complete(link: &mut Link, timeout: Duration) -> Result<(), ScpiError> {
+    let deadline = Instant::now() + timeout;
+    let saved = link.opts.read_timeout;
+    let result = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() { break Err(ScpiError::Timeout); }
+        link.opts.read_timeout = remaining;
+        match send_command(link, cmds::OPC) {
+            Ok(Some(r)) if parse_number(r.trim().trim_start_matches('+')) == Some(1.0) => break Ok(()),
+            Ok(_) => continue,
+            Err(e) => break Err(e),
+        }
+    };
+    link.opts.read_timeout = saved;
+    result
+}
+
+/// 一次送出多個查詢，依序回傳各自的結果
+///
+/// 連線支援複合查詢時合併成一道 `A?;:B?` 指令並以 `;` 拆開回應；
+/// 回應數量對不上 (儀器其實不支援) 就退回逐一查詢。
+pub fn query_multi(link: &mut Link, cmds: &[&str]) -> Vec<Option<String>> {
+    if link.opts.compound_queries && cmds.len() > 1 {
+        let joined = cmds.iter()
+            .map(|c| c.trim_start_matches(':'))
+            .collect::<Vec<_>>()
+            .join(";:");
+        if let Ok(Some(resp)) = send_command(link, &joined) {
+            let parts: Vec<&str> = resp.split(';').map(str::trim).collect();
+            if parts.len() == cmds.len() {
+                return parts.into_iter().map(|s| Some(s.to_string())).collect();
+            }
+        }
+        // 清掉殘留的回應，免得被下一道查詢讀到
+        let _ = link.port.clear(serialport::ClearBuffer::Input);
+    }
+    cmds.iter().map(|c| send_command(link, c).ok().flatten()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 只吐出預先放好的資料，沒資料時立刻回 TimedOut；記住被設定的超時
+    struct MockPort {
+        rx: VecDeque<u8>,
+        timeout: Duration,
+    }
+
+    impl Read for MockPort {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.rx.pop_front() {
+                Some(b) => { buf[0] = b; Ok(1) },
+                None => Err(std::io::ErrorKind::TimedOut.into()),
+            }
+        }
+    }
+
+    impl Write for MockPort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { Ok(buf.len()) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    impl SerialPort for MockPort {
+        fn name(&self) -> Option<String> { None }
+        fn baud_rate(&self) -> serialport::Result<u32> { Ok(9600) }
+        fn data_bits(&self) -> serialport::Result<DataBits> { Ok(DataBits::Eight) }
+        fn flow_control(&self) -> serialport::Result<serialport::FlowControl> { Ok(serialport::FlowControl::None) }
+        fn parity(&self) -> serialport::Result<Parity> { Ok(Parity::None) }
+        fn stop_bits(&self) -> serialport::Result<StopBits> { Ok(StopBits::One) }
+        fn timeout(&self) -> Duration { self.timeout }
+        fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> { Ok(()) }
+        fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> { Ok(()) }
+        fn set_flow_control(&mut self, _: serialport::FlowControl) -> serialport::Result<()> { Ok(()) }
+        fn set_parity(&mut self, _: Parity) -> serialport::Result<()> { Ok(()) }
+        fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> { Ok(()) }
+        fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> { self.timeout = timeout; Ok(()) }
+        fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> { Ok(()) }
+        fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> { Ok(()) }
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> { Ok(true) }
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> { Ok(true) }
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> { Ok(false) }
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> { Ok(true) }
+        fn bytes_to_read(&self) -> serialport::Result<u32> { Ok(self.rx.len() as u32) }
+        fn bytes_to_write(&self) -> serialport::Result<u32> { Ok(0) }
+        fn clear(&self, _: serialport::ClearBuffer) -> serialport::Result<()> { Ok(()) }
+        fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+            Ok(Box::new(MockPort { rx: self.rx.clone(), timeout: self.timeout }))
+        }
+        fn set_break(&self) -> serialport::Result<()> { Ok(()) }
+        fn clear_break(&self) -> serialport::Result<()> { Ok(()) }
+    }
+
+    fn mock_link(rx: &[u8], port_timeout: Duration, read_timeout: Duration) -> Link {
+        let port = MockPort { rx: rx.iter().copied().collect(), timeout: port_timeout };
+        Link::new(Box::new(port), LinkOptions { read_timeout, ..LinkOptions::default() })
+    }
+
+    #[test]
+    fn read_restores_port_timeout() {
+        let port_timeout = Duration::from_secs(1);
+        let mut link = mock_link(b"OK\n", port_timeout, Duration::from_millis(50));
+        assert_eq!(read_serial_response(&mut link).unwrap(), "OK");
+        assert_eq!(link.port.timeout(), port_timeout);
+        // 超時 / 半行結束也一樣
+        assert!(matches!(read_serial_response(&mut link), Err(ScpiError::Timeout)));
+        assert_eq!(link.port.timeout(), port_timeout);
+        let mut link = mock_link(b"12.0", port_timeout, Duration::from_millis(20));
+        assert!(matches!(read_serial_response(&mut link), Err(ScpiError::Partial(_))));
+        assert_eq!(link.port.timeout(), port_timeout);
+    }
+
+    fn assert_close(got: Option<f64>, want: f64) {
+        let got = got.expect("should parse");
+        assert!((got - want).abs() < 1e-12, "{} != {}", got, want);
+    }
+
+    #[test]
+    fn parse_measurement_strips_units() {
+        assert_close(parse_measurement("12.000V"), 12.0);
+        assert_close(parse_measurement("1.500A"), 1.5);
+        assert_close(parse_measurement("+1.5E-3A"), 1.5e-3);
+        assert_close(parse_measurement("12.5mV"), 0.0125);
+        assert_close(parse_measurement(" 230.1 V\r\n"), 230.1);
+        assert_close(parse_measurement("+5.00E+01HZ"), 50.0);
+        assert_eq!(parse_measurement("V"), None);
+        assert_eq!(parse_measurement(""), None);
+    }
+
+    #[test]
+    fn parse_measurement_keeps_decimal_comma() {
+        // 額定值查詢有些儀器回 "12,5"
+        assert_close(parse_measurement("12,5"), 12.5);
+        assert_close(parse_measurement("12,5V"), 12.5);
+        assert_close(parse_measurement("30"), 30.0);
+    }
+
+    #[test]
+    fn decimals_of_counts_unit_prefix() {
+        assert_eq!(decimals_of("1.500mA"), Some(6));
+        assert_eq!(decimals_of("12.000V"), Some(3));
+        assert_eq!(decimals_of("+1.2345E-03"), Some(7));
+        assert_eq!(decimals_of("5"), Some(0));
+    }
+
+    #[test]
+    fn clean_idn_is_not_garbled() {
+        assert!(!looks_garbled("RIGOL TECHNOLOGIES,DP832,DP8C123456789,00.01.14"));
+        assert!(!looks_garbled("Keysight Technologies,E36312A,MY12345678,2.1.3-1.0.4-1.12"));
+        assert!(!looks_garbled("OWON,SPE3103,2107033,FV:V3.0.0\tHV:V1.0"));
+    }
+
+    #[test]
+    fn mojibake_is_garbled() {
+        // 鮑率不對時的典型回應：幾乎都是高位元 byte (lossy 轉成 U+FFFD) 和控制字元
+        let raw = [0xF8, 0x80, 0x1C, 0xE0, 0xFE, 0x06, 0x98, 0xF0, 0x00, 0xC3];
+        assert!(looks_garbled(&String::from_utf8_lossy(&raw)));
+        assert!(looks_garbled("\u{fffd}\u{fffd}R\u{fffd}G\u{fffd}\u{fffd}"));
+        // 一兩個雜訊字元 (例如開頭的 '«') 還不算亂碼
+        assert!(!looks_garbled("«RIGOL TECHNOLOGIES,DP832"));
+    }
+
+    #[test]
+    fn empty_reply_is_not_garbled() {
+        // 沒回應是 Timeout / Empty 的事，不是鮑率的問題
+        assert!(!looks_garbled(""));
+    }
+
+    #[test]
+    fn normalize_number_pins_outgoing_strings() {
+        assert_eq!(normalize_number("1e1").as_deref(), Some("10"));
+        assert_eq!(normalize_number("12,5").as_deref(), Some("12.5"));
+        assert_eq!(normalize_number("3.300000001").as_deref(), Some("3.3"));
+        assert_eq!(normalize_number(" 5 ").as_deref(), Some("5"));
+        assert_eq!(normalize_number("1,000.5"), None);
+        assert_eq!(normalize_number("abc"), None);
+    }
+
+    #[test]
+    fn format_number_trims_without_exponent() {
+        assert_eq!(format_number(10.0), "10");
+        assert_eq!(format_number(100.0), "100");
+        assert_eq!(format_number(12.5), "12.5");
+        assert_eq!(format_number(3.300000001), "3.3");
+        assert_eq!(format_number(0.0000004), "0");
+        assert_eq!(format_number(-0.0000001), "0");
+        assert_eq!(format_number(0.001), "0.001");
+    }
+
+    #[test]
+    fn parse_meas_all_accepts_comma_and_semicolon() {
+        assert_eq!(parse_meas_all("12.000,1.500"), Some((12.0, 1.5, None)));
+        assert_eq!(parse_meas_all("12.000;1.500"), Some((12.0, 1.5, None)));
+        assert_eq!(parse_meas_all("12.000;1.500,18.000"), Some((12.0, 1.5, Some(18.0))));
+    }
+
+    #[test]
+    fn parse_meas_all_two_and_three_fields() {
+        assert_eq!(parse_meas_all("+5.000E+00,+2.000E-01"), Some((5.0, 0.2, None)));
+        assert_eq!(parse_meas_all("5.000,0.200,1.000"), Some((5.0, 0.2, Some(1.0))));
+        // 功率欄位壞掉只丟掉功率，電壓電流照用
+        assert_eq!(parse_meas_all("5.000,0.200,---"), Some((5.0, 0.2, None)));
+        assert_eq!(parse_meas_all("5.000"), None);
+        assert_eq!(parse_meas_all(""), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_meas_all_tolerates_whitespace() {
+        assert_eq!(parse_meas_all(" 12.000 , 1.500 \r\n"), Some((12.0, 1.5, None)));
+        assert_eq!(parse_meas_all("12.000,\t1.500;  18.000"), Some((12.0, 1.5, Some(18.0))));
+        assert_eq!(parse_meas_all("12.000,,1.500"), Some((12.0, 1.5, None)));
+        assert_eq!(parse_meas_all("«12.000V, 1.500A"), Some((12.0, 1.5, None)));
+    }
+}
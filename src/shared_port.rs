@@ -0,0 +1,361 @@
+//! # Shared Serial Port
+//!
+//! The one connection every callback and timer shares. It lives on the UI
+//! thread; [`SharedPort::run`] lends it to a [`Worker`] for blocking I/O and
+//! hands the result back through a continuation queue that the front end
+//! drains with [`dispatch`] (the Slint binary posts it with
+//! `invoke_from_event_loop`). While the link is lent, synchronous borrows get
+//! `Busy` and further `run` calls queue up instead of failing.
+
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::scpi::{self, ScpiError};
+use crate::worker::Worker;
+
+/// 晚一點才在擁有者執行緒上執行的工作
+type Deferred = Box<dyn FnOnce()>;
+
+/// 在背景執行緒上對連線做的收尾
+type LinkJob<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+/// 背景工作做完時從 worker 執行緒呼叫；要安排擁有者執行緒去呼叫 [`dispatch`]
+pub type Notify = Arc<dyn Fn() + Send + Sync>;
+
+thread_local! {
+    /// 等背景工作做完才執行的後續處理 (worker 依序執行，回報也依序到達)
+    static IO_PENDING: RefCell<VecDeque<Deferred>> = RefCell::new(VecDeque::new());
+}
+
+/// 執行最早一個做完的背景工作的後續處理；在建立 `SharedPort` 的執行緒上呼叫
+pub fn dispatch() {
+    if let Some(next) = IO_PENDING.with(|q| q.borrow_mut().pop_front()) { next(); }
+}
+
+/// 所有 callback / timer 共用的序列埠
+///
+/// 平常放在擁有者 (UI) 執行緒這邊；`run` 把連線整個交給背景 I/O 執行緒，做完再還回來。
+/// 借出去的這段時間 (`lent`) 同步借用一律回 `Busy`，和被別的 callback 佔用一樣處理；
+/// 這時再呼叫 `run` 的會排隊，連線還回來後依序執行。
+pub struct SharedPort<T = scpi::Link> {
+    link: Rc<RefCell<Option<T>>>,
+    lent: Rc<Cell<bool>>,
+    waiting: Rc<RefCell<VecDeque<Deferred>>>,
+    /// 借出去時按了斷線：連線還回來就做這個收尾再關掉
+    closing: Rc<RefCell<Option<LinkJob<T>>>>,
+    worker: Rc<Worker>,
+    notify: Notify,
+}
+
+impl<T> Clone for SharedPort<T> {
+    fn clone(&self) -> Self {
+        Self {
+            link: self.link.clone(),
+            lent: self.lent.clone(),
+            waiting: self.waiting.clone(),
+            closing: self.closing.clone(),
+            worker: self.worker.clone(),
+            notify: self.notify.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> SharedPort<T> {
+    pub fn new(worker: Worker, notify: Notify) -> Self {
+        Self {
+            link: Rc::new(RefCell::new(None)),
+            lent: Rc::new(Cell::new(false)),
+            waiting: Rc::new(RefCell::new(VecDeque::new())),
+            closing: Rc::new(RefCell::new(None)),
+            worker: Rc::new(worker),
+            notify,
+        }
+    }
+
+    /// 同步借用；連線借給背景執行緒或被別的 callback 佔用時回 `Busy`
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, Option<T>>, ScpiError> {
+        if self.lent.get() { return Err(ScpiError::Busy); }
+        self.link.try_borrow_mut().map_err(|_| ScpiError::Busy)
+    }
+
+    /// 連線是否正借給背景執行緒
+    pub fn is_lent(&self) -> bool { self.lent.get() }
+
+    /// 在背景執行緒上對連線執行 `job`，結果回到擁有者執行緒交給 `done`
+    ///
+    /// 未連線 (或被同步借用中) 時 `done` 馬上收到錯誤；斷線時還在排隊、還沒回來的工作不會再呼叫 `done`。
+    pub fn run<R: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut T) -> R + Send + 'static,
+        done: impl FnOnce(Result<R, ScpiError>) + 'static,
+    ) {
+        if self.lent.get() {
+            let sp = self.clone();
+            self.waiting.borrow_mut().push_back(Box::new(move || sp.run(job, done)));
+            return;
+        }
+        let taken = match self.try_borrow_mut() {
+            Ok(mut port_ref) => port_ref.take(),
+            Err(e) => return done(Err(e)),
+        };
+        let Some(mut link) = taken else { return done(Err(ScpiError::Disconnected)); };
+
+        let sp = self.clone();
+        self.lend(move || { let r = job(&mut link); (link, r) }, move |res| {
+            let close = sp.closing.borrow_mut().take();
+            match (res, close) {
+                (Some((mut link, _)), Some(close)) => { sp.worker.submit(move || close(&mut link)); }
+                (Some((link, r)), None) => {
+                    *sp.link.borrow_mut() = Some(link);
+                    done(Ok(r));
+                }
+                // 工作 panic 或執行緒不在了：連線跟著工作一起被丟掉
+                (None, _) => done(Err(ScpiError::Disconnected)),
+            }
+        });
+    }
+
+    /// 不用連線的背景工作 (開 Port、掃描鮑率)：跟 `run` 排同一個佇列
+    ///
+    /// 前面還有借出去的工作或斷線收尾時排在它們後面，Port 關掉之後才會輪到。
+    pub fn run_detached<R: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> R + Send + 'static,
+        done: impl FnOnce(Result<R, ScpiError>) + 'static,
+    ) {
+        if self.lent.get() {
+            let sp = self.clone();
+            self.waiting.borrow_mut().push_back(Box::new(move || sp.run_detached(job, done)));
+            return;
+        }
+        self.lend(job, move |r| done(r.ok_or(ScpiError::Disconnected)));
+    }
+
+    /// 把 `job` 交給 worker，做完後在擁有者執行緒上呼叫 `back` (`None` = 工作 panic 或執行緒不在了)
+    ///
+    /// 做完之前 `lent` 一直是 true，後來的工作都會排隊。
+    fn lend<R: Send + 'static>(&self, job: impl FnOnce() -> R + Send + 'static, back: impl FnOnce(Option<R>) + 'static) {
+        // 結果走 channel 回來 (擁有者那邊的 closure 不需要 Send)
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sp = self.clone();
+        IO_PENDING.with(|q| q.borrow_mut().push_back(Box::new(move || {
+            sp.lent.set(false);
+            back(rx.recv().ok());
+            sp.run_waiting();
+        })));
+        self.lent.set(true);
+        let notify = self.notify.clone();
+        let submitted = self.worker.submit(move || {
+            // 工作 panic 時沒有結果 (借出去的連線狀態不明，跟著丟掉)；不管怎樣都要通知，`lent` 才會被清掉
+            if let Ok(r) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                let _ = tx.send(r);
+            }
+            drop(tx);
+            notify();
+        });
+        if !submitted {
+            if let Some(next) = IO_PENDING.with(|q| q.borrow_mut().pop_back()) { next(); }
+        }
+    }
+
+    /// 不需要結果的工作 (改連線參數、關輸出之類)：連線借出去時排隊，不會因為忙碌被略過
+    pub fn post(&self, job: impl FnOnce(&mut T) + Send + 'static) {
+        self.run(job, |_| {});
+    }
+
+    /// 安全相關的工作 (關輸出之類)：連線借出去時插到佇列最前面，一還回來就先做
+    ///
+    /// 不跟其他排隊的工作搶先後的話，前面排著的讀取 / 掃描步驟每個都可能等滿讀取超時。
+    pub fn run_urgent<R: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut T) -> R + Send + 'static,
+        done: impl FnOnce(Result<R, ScpiError>) + 'static,
+    ) {
+        if self.lent.get() {
+            let sp = self.clone();
+            self.waiting.borrow_mut().push_front(Box::new(move || sp.run(job, done)));
+        } else {
+            self.run(job, done);
+        }
+    }
+
+    /// 不需要結果的 `run_urgent`
+    pub fn post_urgent(&self, job: impl FnOnce(&mut T) + Send + 'static) {
+        self.run_urgent(job, |_| {});
+    }
+
+    /// 連線還回來之後，把排隊中的工作送出去 (一次只借出一個)
+    fn run_waiting(&self) {
+        while !self.lent.get() {
+            let next = self.waiting.borrow_mut().pop_front();
+            let Some(next) = next else { break; };
+            next();
+        }
+    }
+
+    /// 連上之後放進來
+    pub fn attach(&self, link: T) -> Result<(), ScpiError> {
+        *self.try_borrow_mut()? = Some(link);
+        Ok(())
+    }
+
+    /// 斷線：先做 `job` 收尾 (回本地模式之類的) 再關掉 Port，排隊中的工作一起取消
+    ///
+    /// 收尾一律在背景執行緒上做；連線正借出去時不用等，在它還回來之後才做。
+    pub fn close(&self, job: impl FnOnce(&mut T) + Send + 'static) -> Result<(), ScpiError> {
+        if self.lent.get() {
+            *self.closing.borrow_mut() = Some(Box::new(job));
+        } else if let Some(mut p) = self.link.try_borrow_mut().map_err(|_| ScpiError::Busy)?.take() {
+            self.worker.submit(move || job(&mut p));
+        }
+        self.waiting.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+
+    /// 測試執行緒當成事件迴圈：worker 做完時送通知過來，`pump` 收到才呼叫 `dispatch`
+    fn port() -> (SharedPort<Vec<String>>, Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let notify: Notify = Arc::new(move || { let _ = tx.lock().unwrap().send(()); });
+        let sp = SharedPort::new(Worker::spawn("test-io").unwrap(), notify);
+        sp.attach(Vec::new()).unwrap();
+        (sp, rx)
+    }
+
+    fn pump(rx: &Receiver<()>) {
+        rx.recv_timeout(Duration::from_secs(5)).expect("worker never reported back");
+        dispatch();
+    }
+
+    #[test]
+    fn sync_borrow_while_lent_is_busy() {
+        let (sp, rx) = port();
+        sp.post(|l| l.push("a".into()));
+        assert!(sp.is_lent());
+        assert!(matches!(sp.try_borrow_mut(), Err(ScpiError::Busy)));
+        assert!(matches!(sp.attach(Vec::new()), Err(ScpiError::Busy)));
+        pump(&rx);
+        assert!(!sp.is_lent());
+        assert_eq!(sp.try_borrow_mut().unwrap().as_deref(), Some(&["a".to_string()][..]));
+    }
+
+    #[test]
+    fn overlapping_runs_queue_in_order() {
+        let (sp, rx) = port();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        for n in 0..3 {
+            let seen = seen.clone();
+            sp.run(move |l| { l.push(n.to_string()); l.len() }, move |r| seen.borrow_mut().push(r.unwrap()));
+        }
+        // 第一個借出去，後面兩個排隊，都不會馬上失敗
+        assert!(seen.borrow().is_empty());
+        for _ in 0..3 { pump(&rx); }
+        assert_eq!(*seen.borrow(), vec![1, 2, 3]);
+        assert!(!sp.is_lent());
+    }
+
+    #[test]
+    fn borrowed_synchronously_is_busy_not_panic() {
+        let (sp, _rx) = port();
+        let guard = sp.try_borrow_mut().unwrap();
+        assert!(matches!(sp.try_borrow_mut(), Err(ScpiError::Busy)));
+        let got = Rc::new(RefCell::new(None));
+        let g = got.clone();
+        sp.run(|_| (), move |r| *g.borrow_mut() = Some(r.is_err()));
+        assert_eq!(*got.borrow(), Some(true));
+        drop(guard);
+    }
+
+    #[test]
+    fn run_without_link_reports_disconnected() {
+        let (sp, _rx) = port();
+        sp.close(|_| {}).unwrap();
+        let got = Rc::new(RefCell::new(None));
+        let g = got.clone();
+        sp.run(|_| (), move |r| *g.borrow_mut() = Some(r));
+        assert!(matches!(got.borrow().as_ref(), Some(Err(ScpiError::Disconnected))));
+    }
+
+    #[test]
+    fn close_while_lent_drops_queued_jobs() {
+        let (sp, rx) = port();
+        let called = Rc::new(Cell::new(0));
+        for _ in 0..2 {
+            let c = called.clone();
+            sp.run(|_| (), move |_| c.set(c.get() + 1));
+        }
+        sp.close(|_| {}).unwrap();
+        pump(&rx);
+        assert_eq!(called.get(), 0);
+        assert!(!sp.is_lent());
+        assert!(sp.try_borrow_mut().unwrap().is_none());
+    }
+
+    #[test]
+    fn panicking_job_releases_the_port() {
+        let (sp, rx) = port();
+        let got = Rc::new(RefCell::new(None));
+        let g = got.clone();
+        sp.run(|_| -> () { panic!("job failed") }, move |r| *g.borrow_mut() = Some(r));
+        pump(&rx);
+        assert!(!sp.is_lent());
+        assert!(matches!(got.borrow().as_ref(), Some(Err(ScpiError::Disconnected))));
+        // 連線丟掉了，之後的工作馬上收到 Disconnected，不會卡在排隊
+        let g = got.clone();
+        sp.run(|_| (), move |r| *g.borrow_mut() = Some(r));
+        assert!(matches!(got.borrow().as_ref(), Some(Err(ScpiError::Disconnected))));
+    }
+
+    #[test]
+    fn detached_job_queues_behind_lent_link() {
+        let (sp, rx) = port();
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let o = order.clone();
+        sp.post(move |_| o.lock().unwrap().push("lent"));
+        let o = order.clone();
+        let got = Rc::new(Cell::new(0));
+        let g = got.clone();
+        sp.run_detached(move || { o.lock().unwrap().push("detached"); 7 }, move |r| g.set(r.unwrap()));
+        assert_eq!(got.get(), 0);
+        for _ in 0..2 { pump(&rx); }
+        assert_eq!(*order.lock().unwrap(), ["lent", "detached"]);
+        assert_eq!(got.get(), 7);
+        assert!(!sp.is_lent());
+    }
+
+    #[test]
+    fn detached_job_runs_after_close() {
+        // 斷線後馬上掃描鮑率：收尾 (ABOR / 回本地模式) 做完、Port 關掉之後才輪到掃描
+        for lent in [false, true] {
+            let (sp, rx) = port();
+            let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+            if lent { sp.post(|_| std::thread::sleep(Duration::from_millis(20))); }
+            let o = order.clone();
+            sp.close(move |_| o.lock().unwrap().push("close")).unwrap();
+            let o = order.clone();
+            sp.run_detached(move || o.lock().unwrap().push("scan"), |_| {});
+            for _ in 0..(1 + lent as usize) { pump(&rx); }
+            assert_eq!(*order.lock().unwrap(), ["close", "scan"]);
+        }
+    }
+
+    #[test]
+    fn urgent_job_jumps_the_queue() {
+        let (sp, rx) = port();
+        sp.post(|l| l.push("lent".into()));
+        sp.post(|l| l.push("queued".into()));
+        sp.post_urgent(|l| l.push("urgent".into()));
+        for _ in 0..3 { pump(&rx); }
+        assert_eq!(sp.try_borrow_mut().unwrap().clone().unwrap(), ["lent", "urgent", "queued"]);
+    }
+}
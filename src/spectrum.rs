@@ -0,0 +1,42 @@
+//! # Ripple Spectrum Analysis
+//!
+//! Runs an FFT over the voltage history buffer to find the dominant
+//! ripple frequency and its amplitude.
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+/// 主要漣波成分
+#[derive(Debug, Clone, Copy)]
+pub struct Ripple {
+    pub freq_hz: f32,
+    /// 峰值振幅 (與輸入同單位，例如 V)
+    pub amplitude: f32,
+}
+
+/// 計算單邊振幅頻譜
+///
+/// `sample_rate` 為每秒取樣數。回傳 `(各頻點振幅, 主要漣波)`；
+/// 頻點 k 對應的頻率為 `k * sample_rate / N`，第 0 點 (DC) 已扣掉平均值。
+pub fn analyze(samples: &[f32], sample_rate: f32) -> (Vec<f32>, Option<Ripple>) {
+    let n = samples.len();
+    if n < 4 || sample_rate <= 0.0 { return (Vec::new(), None); }
+
+    // 先扣掉平均值，避免 DC 蓋過漣波
+    let mean = samples.iter().sum::<f32>() / n as f32;
+    let mut buf: Vec<Complex<f32>> = samples.iter().map(|&v| Complex::new(v - mean, 0.0)).collect();
+
+    let fft = FftPlanner::new().plan_fft_forward(n);
+    fft.process(&mut buf);
+
+    // 單邊頻譜：振幅 = 2|X| / N
+    let mags: Vec<f32> = buf[..n / 2].iter().map(|c| c.norm() * 2.0 / n as f32).collect();
+
+    let ripple = mags.iter()
+        .enumerate()
+        .skip(1)
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .filter(|(_, &m)| m > 0.0)
+        .map(|(k, &m)| Ripple { freq_hz: k as f32 * sample_rate / n as f32, amplitude: m });
+
+    (mags, ripple)
+}
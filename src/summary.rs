@@ -0,0 +1,146 @@
+//! # Session Summary
+//!
+//! Running statistics of every published measurement (min/max/avg/peak-to-peak
+//! of V and I, energy, charge, peak power) plus the protection trips seen
+//! during the session, rendered as a short `key,value,detail` CSV report.
+//! Subscribe it to [`crate::measurement::Observers`] like any other sink.
+
+use chrono::{DateTime, Local};
+
+use crate::logger::TS_FORMAT;
+use crate::measurement::{Measurement, MeasurementSink};
+
+/// 摘要 CSV 的格式版本；欄位有變動時才加 1，解析的程式可以依此判斷
+pub const SUMMARY_VERSION: u32 = 1;
+
+/// 兩筆量測間隔超過這個秒數 (斷線、暫停輪詢) 就不積分，免得把空白時間算成耗電
+const MAX_GAP_S: f64 = 10.0;
+
+/// 單一數值的最小 / 最大 / 平均
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stat {
+    pub min: f64,
+    pub max: f64,
+    sum: f64,
+    count: u64,
+}
+
+impl Stat {
+    pub fn push(&mut self, x: f64) {
+        if self.count == 0 {
+            self.min = x;
+            self.max = x;
+        } else {
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+        }
+        self.sum += x;
+        self.count += 1;
+    }
+
+    pub fn avg(&self) -> Option<f64> { (self.count > 0).then(|| self.sum / self.count as f64) }
+
+    /// 峰對峰 (max - min)，當作整段的漣波 / 漂移量
+    pub fn peak_to_peak(&self) -> Option<f64> { (self.count > 0).then_some(self.max - self.min) }
+}
+
+/// 一次保護動作 (過功率、安全連鎖) 關掉輸出的紀錄
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trip {
+    pub ts: DateTime<Local>,
+    pub reason: String,
+}
+
+/// 整段連線的量測摘要
+#[derive(Debug, Clone, Default)]
+pub struct SessionSummary {
+    /// 連線時讀到的 `*IDN?` (空字串 = 沒讀到)
+    pub identity: String,
+    pub start: Option<DateTime<Local>>,
+    pub end: Option<DateTime<Local>>,
+    pub v: Stat,
+    pub i: Stat,
+    /// 累積能量 (Wh) 與電荷 (Ah)，梯形積分；負功率 (sink) 會抵減
+    pub energy_wh: f64,
+    pub charge_ah: f64,
+    pub peak_power: Option<(f32, DateTime<Local>)>,
+    pub trips: Vec<Trip>,
+    last: Option<Measurement>,
+}
+
+impl SessionSummary {
+    pub fn clear(&mut self) { *self = Self::default(); }
+
+    pub fn samples(&self) -> u64 { self.v.count }
+
+    pub fn duration_s(&self) -> f64 {
+        match (self.start, self.end) {
+            (Some(s), Some(e)) => (e - s).num_milliseconds() as f64 / 1000.0,
+            _ => 0.0,
+        }
+    }
+
+    pub fn add(&mut self, m: &Measurement) {
+        if let Some(prev) = &self.last {
+            let dt = (m.ts - prev.ts).num_milliseconds() as f64 / 1000.0;
+            if dt > 0.0 && dt <= MAX_GAP_S {
+                let hours = dt / 3600.0;
+                self.energy_wh += (prev.p + m.p) as f64 / 2.0 * hours;
+                self.charge_ah += (prev.i + m.i) as f64 / 2.0 * hours;
+            }
+        }
+        self.start.get_or_insert(m.ts);
+        self.end = Some(m.ts);
+        self.v.push(m.v as f64);
+        self.i.push(m.i as f64);
+        if !matches!(self.peak_power, Some((p, _)) if p >= m.p) {
+            self.peak_power = Some((m.p, m.ts));
+        }
+        self.last = Some(m.clone());
+    }
+
+    pub fn trip(&mut self, ts: DateTime<Local>, reason: &str) {
+        self.trips.push(Trip { ts, reason: reason.to_string() });
+    }
+
+    /// 固定格式的摘要：`key,value,detail` 三欄，detail 是單位 (trip 列則是原因)
+    ///
+    /// 列的順序與名稱只在 `SUMMARY_VERSION` 變動時才會改；沒有資料的數值留空。
+    pub fn to_csv(&self) -> String {
+        let ts = |t: Option<DateTime<Local>>| t.map(|t| t.format(TS_FORMAT).to_string()).unwrap_or_default();
+        let num = |x: Option<f64>, decimals: usize| x.map(|x| format!("{:.*}", decimals, x)).unwrap_or_default();
+        let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+
+        let mut rows = vec![
+            "key,value,detail".to_string(),
+            format!("version,{},", SUMMARY_VERSION),
+            format!("identity,{},", quote(&self.identity)),
+            format!("start,{},", ts(self.start)),
+            format!("end,{},", ts(self.end)),
+            format!("duration,{:.3},s", self.duration_s()),
+            format!("samples,{},", self.samples()),
+        ];
+        for (name, stat, unit) in [("voltage", &self.v, "V"), ("current", &self.i, "A")] {
+            rows.push(format!("{}_min,{},{}", name, num((stat.count > 0).then_some(stat.min), 6), unit));
+            rows.push(format!("{}_max,{},{}", name, num((stat.count > 0).then_some(stat.max), 6), unit));
+            rows.push(format!("{}_avg,{},{}", name, num(stat.avg(), 6), unit));
+            rows.push(format!("{}_ripple_pp,{},{}", name, num(stat.peak_to_peak(), 6), unit));
+        }
+        rows.push(format!("energy,{:.6},Wh", self.energy_wh));
+        rows.push(format!("charge,{:.6},Ah", self.charge_ah));
+        rows.push(format!("peak_power,{},W", num(self.peak_power.map(|(p, _)| p as f64), 4)));
+        rows.push(format!("peak_power_at,{},", ts(self.peak_power.map(|(_, t)| t))));
+        rows.push(format!("trips,{},", self.trips.len()));
+        for t in &self.trips {
+            rows.push(format!("trip,{},{}", t.ts.format(TS_FORMAT), quote(&t.reason)));
+        }
+        rows.join("\n") + "\n"
+    }
+}
+
+impl MeasurementSink for SessionSummary {
+    fn record(&mut self, m: &Measurement) -> Result<(), String> {
+        self.add(m);
+        Ok(())
+    }
+}
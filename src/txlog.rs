@@ -0,0 +1,135 @@
+//! # SCPI Transaction Log
+//!
+//! Opt-in debug log of every `send_command` exchange: one timestamped line
+//! with the command, the raw reply bytes (non-printable bytes hex-escaped)
+//! and the round-trip time. Lines are handed to a writer thread over a
+//! channel, so logging never waits on the disk; the thread buffers them,
+//! flushes periodically and rotates the file once it reaches ~5 MB.
+
+use chrono::Local;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::logger::TS_FORMAT;
+use crate::scpi::{Response, ScpiError};
+
+/// 預設檔名 (目前資料夾)
+pub const DEFAULT_PATH: &str = "scpi_transactions.log";
+
+/// 檔案超過這個大小就換檔 (舊的改名成 `.1`，再舊的丟掉)
+pub const MAX_BYTES: u64 = 5_000_000;
+
+/// 沒有新紀錄時多久 flush 一次
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 交易紀錄的寫入端；clone 之後每個 Link 各拿一份，全部丟掉時寫入執行緒 flush 完就結束
+#[derive(Clone, Debug)]
+pub struct TxLog {
+    tx: Sender<String>,
+    path: PathBuf,
+}
+
+impl TxLog {
+    /// 開啟 (續寫) `path` 並啟動寫入執行緒
+    pub fn start(path: &Path) -> std::io::Result<Self> {
+        let file = open_append(path)?;
+        let (tx, rx) = mpsc::channel::<String>();
+        let mut writer = Writer { out: Some(BufWriter::new(file)), bytes: std::fs::metadata(path)?.len(), path: path.to_path_buf() };
+        thread::Builder::new().name("scpi-txlog".into()).spawn(move || {
+            loop {
+                match rx.recv_timeout(FLUSH_INTERVAL) {
+                    Ok(line) => writer.write(&line),
+                    Err(RecvTimeoutError::Timeout) => writer.flush(),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            writer.flush();
+        })?;
+        Ok(Self { tx, path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// 記一筆交易：指令、原始回應 (或錯誤 / 沒有回應的設定指令)、來回時間
+    pub fn record(&self, cmd: &str, result: &Result<Option<Response>, ScpiError>, elapsed: Duration) {
+        let reply = match result {
+            Ok(Some(r)) => format!("RX \"{}\"", escape_bytes(&r.raw)),
+            Ok(None) => "(no reply)".to_string(),
+            Err(e) => format!("ERR {}", e),
+        };
+        let line = format!("{}\tTX \"{}\"\t{}\t{:.1} ms",
+            Local::now().format(TS_FORMAT), escape_bytes(cmd.as_bytes()), reply, elapsed.as_secs_f64() * 1000.0);
+        // 寫入執行緒不在了 (開檔後磁碟出錯) 就算了，不影響通訊
+        let _ = self.tx.send(line);
+    }
+}
+
+/// 可見的 ASCII 原樣保留，`\r` `\n` `\t` `\\` `"` 跳脫，其他 byte 寫成 `\xNN`
+pub fn escape_bytes(raw: &[u8]) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for &b in raw {
+        match b {
+            b'\r' => out.push_str("\\r"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// 寫入執行緒這邊：緩衝寫入 + 超過大小就換檔
+struct Writer {
+    /// 換檔失敗 (重新開檔出錯) 時是 None，下一筆再試著開
+    out: Option<BufWriter<File>>,
+    bytes: u64,
+    path: PathBuf,
+}
+
+impl Writer {
+    fn write(&mut self, line: &str) {
+        if self.out.is_none() {
+            match open_append(&self.path) {
+                Ok(f) => self.out = Some(BufWriter::new(f)),
+                Err(e) => { eprintln!("Transaction Log Error: {}", e); return; }
+            }
+        }
+        let Some(out) = self.out.as_mut() else { return; };
+        if let Err(e) = writeln!(out, "{}", line) {
+            eprintln!("Transaction Log Error: {}", e);
+            return;
+        }
+        self.bytes += line.len() as u64 + 1;
+        if self.bytes >= MAX_BYTES {
+            if let Err(e) = self.rotate() { eprintln!("Transaction Log Rotate Error: {}", e); }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(Err(e)) = self.out.as_mut().map(|out| out.flush()) { eprintln!("Transaction Log Error: {}", e); }
+    }
+
+    /// 目前的檔案改名成 `<name>.1` (蓋掉上一份)，重新開一個空的
+    ///
+    /// 改名前先關檔 (Windows 不能改開著的檔案)。
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if let Some(mut out) = self.out.take() { out.flush()?; }
+        let mut old = self.path.clone().into_os_string();
+        old.push(".1");
+        std::fs::rename(&self.path, &old)?;
+        self.out = Some(BufWriter::new(open_append(&self.path)?));
+        self.bytes = 0;
+        Ok(())
+    }
+}
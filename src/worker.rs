@@ -0,0 +1,48 @@
+//! # Background I/O Worker
+//!
+//! A dedicated thread for blocking serial I/O. Jobs arrive over an `mpsc`
+//! channel and run one at a time in submission order, so a read timeout or a
+//! vanished port never stalls the Slint event loop. The front end moves the
+//! [`crate::scpi::Link`] into a job and posts it back with the result.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// 單一背景執行緒；送進來的工作依序執行
+pub struct Worker {
+    tx: Sender<Job>,
+}
+
+impl Worker {
+    pub fn spawn(name: &str) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::channel::<Job>();
+        // 所有 Sender 都丟掉 (程式結束) 時迴圈自然結束；單一工作 panic 不會帶走整個執行緒
+        thread::Builder::new().name(name.to_string()).spawn(move || {
+            for job in rx { let _ = panic::catch_unwind(AssertUnwindSafe(job)); }
+        })?;
+        Ok(Self { tx })
+    }
+
+    /// 排進佇列；執行緒已經不在時回傳 `false`
+    pub fn submit(&self, job: impl FnOnce() + Send + 'static) -> bool {
+        self.tx.send(Box::new(job)).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn survives_a_panicking_job() {
+        let w = Worker::spawn("test-worker").unwrap();
+        assert!(w.submit(|| panic!("job failed")));
+        let (tx, rx) = mpsc::channel();
+        assert!(w.submit(move || tx.send(42).unwrap()));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(42));
+    }
+}